@@ -0,0 +1,114 @@
+//! Benchmarks of the event hot path on synthetic trees of 10, 100 and 1000 nodes.
+//!
+//! Run with `cargo bench`, optionally with a filter, e.g. `cargo bench -- decide`. Criterion
+//! isn't a dependency, so this is a small harness of its own: each benchmark runs for doubling
+//! numbers of iterations until a batch takes long enough to time reliably.
+
+use std::{
+    env,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use i3_alternating_layout::{
+    config::Config,
+    ipc::{
+        event::{Binding, BindingEventInfo, Event},
+        fake::{FakeConnection, NodeBuilder, TreeBuilder},
+        reply::{Node, NodeLayout},
+    },
+    LayoutEngine,
+};
+
+const SIZES: [usize; 3] = [10, 100, 1000];
+const BATCH_TIME: Duration = Duration::from_millis(500);
+
+/// A container with about `nodes` nodes below it, three children per container and the last
+/// window focused, so finding it takes the longest.
+fn container(nodes: usize, depth: usize, last: bool) -> NodeBuilder {
+    let layout = match depth % 2 {
+        0 => NodeLayout::SplitH,
+        _ => NodeLayout::SplitV,
+    };
+    if nodes <= 3 {
+        let count = nodes.max(1);
+        let windows = (0..count).map(|i| {
+            let window = NodeBuilder::window();
+            if last && i == count - 1 {
+                window.focused()
+            } else {
+                window
+            }
+        });
+        return NodeBuilder::container(layout, windows);
+    }
+    let share = (nodes - 1) / 3;
+    NodeBuilder::container(
+        layout,
+        (0..3).map(|i| container(share, depth + 1, last && i == 2)),
+    )
+}
+
+fn tree(nodes: usize) -> Node {
+    TreeBuilder::default()
+        .workspace("1", container(nodes, 0, true))
+        .build()
+}
+
+fn bench(filter: Option<&str>, name: &str, mut f: impl FnMut()) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+
+    let mut iterations = 1;
+    loop {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            f();
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= BATCH_TIME {
+            let per_iteration = elapsed / iterations;
+            println!("{name:<24} {per_iteration:>12.2?}/iter ({iterations} iterations)");
+            return;
+        }
+        iterations *= 2;
+    }
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, which isn't a filter.
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let filter = filter.as_deref();
+    let event = Event::Binding(BindingEventInfo {
+        binding: Binding {
+            command: "focus left".to_owned(),
+        },
+    });
+
+    for size in SIZES {
+        let tree = tree(size);
+        let nodes = tree.descendants().count();
+
+        bench(filter, &format!("descendants/{size}"), || {
+            black_box(black_box(&tree).descendants().count());
+        });
+        bench(filter, &format!("focused/{size}"), || {
+            black_box(black_box(&tree).descendants().find(|n| n.focused));
+        });
+        // What the fake connection adds to every decision, to subtract from `decide`.
+        bench(filter, &format!("clone/{size}"), || {
+            black_box(black_box(&tree).clone());
+        });
+
+        let mut engine = LayoutEngine::new(Config::default());
+        let mut wm = FakeConnection::new(tree.clone());
+        bench(filter, &format!("decide/{size}"), || {
+            black_box(engine.handle_event(&mut wm, black_box(&event)));
+        });
+
+        if filter.is_none() {
+            println!("{:<24} {nodes} nodes", format!("tree/{size}"));
+        }
+    }
+}
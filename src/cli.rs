@@ -0,0 +1,417 @@
+use std::{env, fmt, path::PathBuf, process};
+
+use crate::{
+    config::Config,
+    control::Command,
+    ipc::event::WindowChange,
+    logger::{Filter, LogTarget},
+    output::OutputMode,
+    scheme::Scheme,
+};
+
+/// The commands, which end the list of workspaces of `--workspaces`.
+const COMMANDS: [&str; 6] = ["ctl", "health", "doctor", "dump-tree", "replay", "menu"];
+
+const USAGE: &str = "\
+Usage: i3-alternating-layout-rs [OPTIONS]
+       i3-alternating-layout-rs [OPTIONS] ctl <COMMAND>
+       i3-alternating-layout-rs [OPTIONS] menu [<COMMAND>]
+       i3-alternating-layout-rs [OPTIONS] health
+       i3-alternating-layout-rs [OPTIONS] doctor
+       i3-alternating-layout-rs [OPTIONS] dump-tree
+       i3-alternating-layout-rs [OPTIONS] replay <FILE>
+
+Alternates the split orientation of new i3 windows and prints the next split for a status bar.
+
+Commands:
+  ctl <COMMAND>         Control the running daemon with one of these commands:
+    pause|resume|toggle [<WORKSPACE>]
+                        Stop or start splitting windows on all workspaces or the named one
+    status [<WORKSPACE>]
+                        Print the state of the daemon on the focused workspace or the named one
+    scheme <NAME>|default|next|previous [<WORKSPACE>]
+                        Switch all workspaces or the named one to another layout scheme
+    balance|transpose [<WORKSPACE>]
+                        Give all windows of the focused workspace or the named one equal
+                        shares, or swap every horizontal split there with a vertical one
+    rotate              Move the last window of the focused container to the front
+    promote             Swap the focused window with the largest one
+    undo                Undo the latest automatic change
+    save|restore <NAME> Store the layout of the focused workspace, or apply it again
+    stats               Print what the daemon did since it started
+  health                Check that the running daemon is connected to i3 and print when it got
+                        the last event, exiting with 1 if it isn't
+  doctor                Check the setup: whether i3 is reachable and new enough, the bar and
+                        the bindings are set up and no other autotiling daemon is running
+  dump-tree             Print the layout tree with what the daemon would decide for each window
+                        if it got focus, e.g. horizontal or ignore: a rule matches the window
+  replay <FILE>         Feed the events recorded with --record to the daemon without i3, logging
+                        the commands it would run
+  menu [<COMMAND>]      List the common commands for dmenu or rofi, or send the one picked,
+                        e.g. rofi -show alternating -modi alternating:'i3-alternating-layout-rs menu'
+                        or i3-alternating-layout-rs menu \"$(i3-alternating-layout-rs menu | dmenu)\"
+
+Options:
+  -c, --config <PATH>   Config file [default: $XDG_CONFIG_HOME/i3-alternating-layout/config.toml]
+      --replace         Take over from the daemon already managing the same i3 instance
+      --socket <PATH>   The IPC socket of the i3 or sway instance to manage
+                        [default: $I3SOCK, $SWAYSOCK or the one the running compositor reports]
+  -s, --scheme <NAME>   Layout scheme: alternating (default), spiral, dwindle or master_stack
+  -r, --ratio <RATIO>   Split vertically once a container is RATIO times taller than it is wide
+  -l, --limit <DEPTH>   Stop splitting windows nested more than DEPTH containers deep
+  -w, --workspaces <NAME>...
+                        Only manage these workspaces, up to the next option or command
+      --events <CHANGE>,...
+                        Window events to split windows on
+                        [default: new,focus,move,close,fullscreen_mode]
+  -o, --output <MODE>   Status output: plain (default), i3bar, waybar, polybar, json,
+                        rootname to set the X root window name, or fifo:<PATH> to write
+                        plain lines to a FIFO created at PATH
+      --force           Write the status after every event, even if it didn't change
+      --wrap            Add the status to the i3bar stream of i3status or i3status-rust on
+                        stdin, implying --output i3bar, e.g. as the status_command
+                        i3status | i3-alternating-layout-rs --wrap
+  -q, --quiet           Don't write any status, only split windows
+  -n, --dry-run         Log the commands that would be sent to i3 instead of sending them
+      --explain         Log why each window was split as it was, or left alone
+      --log-level <LEVEL>
+                        What to log, e.g. debug or warn,i3_alternating_layout::ipc=trace
+                        [default: $RUST_LOG or warn]
+      --log-target <TARGET>
+                        Where to log: stderr (default), journald or syslog
+      --log-file <PATH> Append the log to a file instead of writing it to stderr
+      --record <FILE>   Append the events and trees from i3 to FILE, for replay
+  -h, --help            Print help
+  -V, --version         Print version
+";
+
+#[derive(Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    /// The IPC socket to connect to instead of the detected one.
+    pub socket: Option<PathBuf>,
+    /// Make the daemon already running for the same i3 instance exit instead of exiting.
+    pub replace: bool,
+    pub scheme: Option<Scheme>,
+    pub ratio: Option<f64>,
+    pub limit: Option<u32>,
+    pub workspaces: Option<Vec<String>>,
+    pub events: Option<Vec<WindowChange>>,
+    pub output: Option<OutputMode>,
+    pub force: bool,
+    pub wrap: bool,
+    pub quiet: bool,
+    pub dry_run: bool,
+    pub explain: bool,
+    pub log_level: Option<String>,
+    pub log_target: Option<LogTarget>,
+    pub log_file: Option<PathBuf>,
+    /// Where to record the events and trees from i3.
+    pub record: Option<PathBuf>,
+    /// A command to send to the running daemon instead of running one.
+    pub ctl: Option<String>,
+    /// List the commands of the menu instead of running a daemon.
+    pub menu: bool,
+    /// Check the setup instead of running a daemon.
+    pub doctor: bool,
+    /// Print the tree with the decisions for its windows instead of running a daemon.
+    pub dump_tree: bool,
+    /// A recording to replay instead of running a daemon.
+    pub replay: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+enum ArgsError {
+    Help,
+    Version,
+    Unknown(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help | ArgsError::Version => Ok(()),
+            ArgsError::Unknown(arg) => write!(f, "unexpected argument '{arg}'"),
+            ArgsError::MissingValue(flag) => write!(f, "a value is required for '{flag}'"),
+            ArgsError::InvalidValue(flag, value) => {
+                write!(f, "invalid value '{value}' for '{flag}'")
+            }
+        }
+    }
+}
+
+impl Args {
+    /// Parses the process arguments, exiting for `--help`, `--version` and invalid input.
+    pub fn parse() -> Self {
+        match Self::try_parse(env::args().skip(1)) {
+            Ok(args) => args,
+            Err(ArgsError::Help) => {
+                print!("{USAGE}");
+                process::exit(0)
+            }
+            Err(ArgsError::Version) => {
+                println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                process::exit(0)
+            }
+            Err(e) => {
+                eprintln!("Error: {e}\n\nFor more information, try '--help'.");
+                process::exit(2)
+            }
+        }
+    }
+
+    fn try_parse(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ArgsError::MissingValue(flag.clone()))
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Err(ArgsError::Help),
+                "-V" | "--version" => return Err(ArgsError::Version),
+                "-c" | "--config" => parsed.config = Some(value()?.into()),
+                "--socket" => parsed.socket = Some(value()?.into()),
+                "--replace" => parsed.replace = true,
+                "-s" | "--scheme" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(scheme) => parsed.scheme = Some(scheme),
+                        Err(_) => return Err(ArgsError::InvalidValue(flag, value)),
+                    }
+                }
+                "-r" | "--ratio" => {
+                    let value = value()?;
+                    match value.parse::<f64>() {
+                        Ok(ratio) if ratio.is_finite() && ratio > 0.0 => parsed.ratio = Some(ratio),
+                        _ => return Err(ArgsError::InvalidValue(flag, value)),
+                    }
+                }
+                "-l" | "--limit" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(limit) => parsed.limit = Some(limit),
+                        Err(_) => return Err(ArgsError::InvalidValue(flag, value)),
+                    }
+                }
+                "-w" | "--workspaces" => {
+                    let mut workspaces: Vec<_> = inline_value.into_iter().collect();
+                    while let Some(workspace) = args
+                        .next_if(|arg| !arg.starts_with('-') && !COMMANDS.contains(&arg.as_str()))
+                    {
+                        workspaces.push(workspace);
+                    }
+                    if workspaces.is_empty() {
+                        return Err(ArgsError::MissingValue(flag));
+                    }
+                    parsed
+                        .workspaces
+                        .get_or_insert_with(Vec::new)
+                        .extend(workspaces);
+                }
+                "--events" => {
+                    let value = value()?;
+                    match value
+                        .split(',')
+                        .map(|change| change.trim().parse())
+                        .collect()
+                    {
+                        Ok(events) => parsed.events = Some(events),
+                        Err(_) => return Err(ArgsError::InvalidValue(flag, value)),
+                    }
+                }
+                "-o" | "--output" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(output) => parsed.output = Some(output),
+                        Err(_) => return Err(ArgsError::InvalidValue(flag, value)),
+                    }
+                }
+                "--force" => parsed.force = true,
+                "--wrap" => parsed.wrap = true,
+                "-q" | "--quiet" => parsed.quiet = true,
+                "-n" | "--dry-run" => parsed.dry_run = true,
+                "--explain" => parsed.explain = true,
+                "--log-level" => {
+                    let value = value()?;
+                    if Filter::parse(&value).is_err() {
+                        return Err(ArgsError::InvalidValue(flag, value));
+                    }
+                    parsed.log_level = Some(value);
+                }
+                "--log-target" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(target) => parsed.log_target = Some(target),
+                        Err(_) => return Err(ArgsError::InvalidValue(flag, value)),
+                    }
+                }
+                "--log-file" => parsed.log_file = Some(value()?.into()),
+                "--record" => parsed.record = Some(value()?.into()),
+                "ctl" => {
+                    let command = args.by_ref().collect::<Vec<_>>().join(" ");
+                    if command.is_empty() {
+                        return Err(ArgsError::MissingValue(flag));
+                    }
+                    if command.parse::<Command>().is_err() {
+                        return Err(ArgsError::InvalidValue(flag, command));
+                    }
+                    parsed.ctl = Some(command);
+                }
+                "health" => parsed.ctl = Some("health".to_owned()),
+                "doctor" => parsed.doctor = true,
+                "dump-tree" => parsed.dump_tree = true,
+                "replay" => parsed.replay = Some(value()?.into()),
+                // rofi runs scripts without arguments for the entries and with the one picked.
+                "menu" => {
+                    let command = args.by_ref().collect::<Vec<_>>().join(" ");
+                    if command.is_empty() {
+                        parsed.menu = true;
+                    } else if command.parse::<Command>().is_ok() {
+                        parsed.ctl = Some(command);
+                    } else {
+                        return Err(ArgsError::InvalidValue(flag, command));
+                    }
+                }
+                _ => return Err(ArgsError::Unknown(flag)),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Overrides config file settings with the ones given on the command line.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(scheme) = self.scheme {
+            config.scheme = scheme;
+        }
+        if let Some(ratio) = self.ratio {
+            config.ratio = ratio;
+        }
+        if self.limit.is_some() {
+            config.limit = self.limit;
+        }
+        if let Some(workspaces) = &self.workspaces {
+            config.workspaces.clone_from(workspaces);
+        }
+        if let Some(events) = &self.events {
+            config.events.clone_from(events);
+        }
+        if let Some(output) = &self.output {
+            config.output.clone_from(output);
+        }
+        if self.force {
+            config.force = true;
+        }
+        if self.wrap {
+            config.wrap = true;
+        }
+        if self.quiet {
+            config.quiet = true;
+        }
+        if self.dry_run {
+            config.dry_run = true;
+        }
+        if self.explain {
+            config.explain = true;
+        }
+        if let Some(target) = self.log_target {
+            config.log_target = target;
+        }
+        if self.log_file.is_some() {
+            config.log_file.clone_from(&self.log_file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, ArgsError> {
+        Args::try_parse(args.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn parses_values_after_flags_and_inline() {
+        let args = parse("-c /tmp/a.toml --ratio=1.5 --scheme spiral --events=new,close").unwrap();
+
+        assert_eq!(args.config, Some(PathBuf::from("/tmp/a.toml")));
+        assert_eq!(args.ratio, Some(1.5));
+        assert_eq!(args.scheme, Some(Scheme::Spiral));
+        assert_eq!(
+            args.events,
+            Some(vec![WindowChange::New, WindowChange::Close])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(matches!(
+            parse("--config"),
+            Err(ArgsError::MissingValue(flag)) if flag == "--config"
+        ));
+        assert!(matches!(
+            parse("-r 0"),
+            Err(ArgsError::InvalidValue(flag, value)) if flag == "-r" && value == "0"
+        ));
+        assert!(matches!(
+            parse("--bogus"),
+            Err(ArgsError::Unknown(flag)) if flag == "--bogus"
+        ));
+        assert!(matches!(parse("--help"), Err(ArgsError::Help)));
+        assert!(matches!(
+            parse("ctl bogus"),
+            Err(ArgsError::InvalidValue(..))
+        ));
+    }
+
+    #[test]
+    fn dispatches_commands() {
+        assert_eq!(
+            parse("ctl pause 2").unwrap().ctl.as_deref(),
+            Some("pause 2")
+        );
+        assert_eq!(parse("health").unwrap().ctl.as_deref(), Some("health"));
+        assert!(parse("doctor").unwrap().doctor);
+        assert!(parse("dump-tree").unwrap().dump_tree);
+        assert!(parse("menu").unwrap().menu);
+        assert_eq!(parse("menu toggle").unwrap().ctl.as_deref(), Some("toggle"));
+        assert_eq!(
+            parse("replay events.jsonl").unwrap().replay,
+            Some(PathBuf::from("events.jsonl"))
+        );
+    }
+
+    #[test]
+    fn ends_the_workspaces_at_the_next_option_or_command() {
+        let args = parse("-w 1 2 doctor").unwrap();
+        assert_eq!(args.workspaces, Some(vec!["1".to_owned(), "2".to_owned()]));
+        assert!(args.doctor);
+
+        let args = parse("--workspaces=doctor 3 -q").unwrap();
+        assert_eq!(
+            args.workspaces,
+            Some(vec!["doctor".to_owned(), "3".to_owned()])
+        );
+        assert!(args.quiet);
+
+        assert!(matches!(
+            parse("-w ctl pause"),
+            Err(ArgsError::MissingValue(_))
+        ));
+    }
+}
@@ -0,0 +1,692 @@
+use std::{
+    collections::HashMap,
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    ipc::{
+        event::WindowChange,
+        reply::{Node, NodeLayout},
+    },
+    logger::{self, LogTarget},
+    output::OutputMode,
+    scheme::Scheme,
+    toml, I3Split, Input,
+};
+
+/// How often a watched config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub scheme: Scheme,
+    /// How many times taller than it is wide a container has to be to be split vertically.
+    pub ratio: f64,
+    /// The share of the workspace width given to the master window by the master_stack scheme.
+    pub master_ratio: f64,
+    /// The share of its container the focused window is resized to if it has neighbours, e.g.
+    /// 0.618 like golden-ratio.el.
+    pub golden_ratio: Option<f64>,
+    /// How deep below its workspace a window may be nested and still be split.
+    pub limit: Option<u32>,
+    /// Splits that would leave windows narrower or shorter than this many pixels are made the
+    /// other way, or not at all if neither fits.
+    pub min_width: u32,
+    pub min_height: u32,
+    /// The inner gaps between windows, in pixels, if i3 doesn't take them out of the sizes it
+    /// reports. Windows are as large as their client areas less the gaps for all decisions.
+    pub gaps: u32,
+    /// Containers holding more windows than this are made tabbed, and split again once they
+    /// hold no more than this.
+    pub tabbed_after: Option<u32>,
+    /// Move windows out of split containers that hold nothing else once a window closes.
+    pub flatten: bool,
+    /// Mark the containers the daemon lays out with `_alt_managed_` followed by their id, so
+    /// scripts can tell them from the ones made by hand. Only those are flattened then.
+    pub mark: bool,
+    /// Put the containers marked with `mark` back to i3's default layout when exiting on
+    /// SIGTERM or SIGINT.
+    pub restore_on_exit: bool,
+    /// Windows nested deeper than this below their workspace are moved up into the container
+    /// above theirs, and windows this deep aren't split.
+    pub max_depth: Option<u32>,
+    /// For how many seconds a container split by hand isn't split automatically, unless a new
+    /// window appears in the meantime.
+    pub grace_period: f64,
+    /// Workspaces (by name) to manage. All workspaces are managed if this is empty.
+    pub workspaces: Vec<String>,
+    /// Workspaces (by name) on which splits are never changed automatically.
+    pub ignore_workspaces: Vec<String>,
+    /// Outputs (by name, e.g. `HDMI-1`) on which splits are never changed automatically.
+    pub ignore_outputs: Vec<String>,
+    /// How windows sitting directly on a workspace are split instead of by the scheme, e.g.
+    /// `auto` to stack the first windows of rotated monitors. The outputs can override it.
+    pub orientation: Option<Orientation>,
+    /// Windows matching any of these are never split automatically. Rules with a layout keep
+    /// their windows in a container with that layout instead.
+    pub rules: Vec<Rule>,
+    /// The kinds of window events that windows are split on.
+    pub events: Vec<WindowChange>,
+    /// For how many seconds to wait for more window events after one arrives, so that bursts
+    /// of them are handled at once.
+    pub debounce: f64,
+    /// Binding modes in which nothing is split automatically, e.g. `resize`.
+    pub pause_modes: Vec<String>,
+    /// Window types, as i3 reports them, that are never split automatically.
+    pub ignore_window_types: Vec<String>,
+    pub output: OutputMode,
+    /// Write the status after every event, even if it didn't change.
+    pub force: bool,
+    /// Add the status to the i3bar stream of i3status or i3status-rust read from stdin, which
+    /// implies the i3bar output.
+    pub wrap: bool,
+    /// Don't write any status, only split windows.
+    pub quiet: bool,
+    /// Log the commands that would be run instead of running them.
+    pub dry_run: bool,
+    /// Log why every decision was made, at the info level.
+    pub explain: bool,
+    /// The name of the polybar ipc module to send the status to.
+    pub polybar_module: String,
+    pub glyphs: Glyphs,
+    /// Templates for the status of each state, see `Format`.
+    pub format: Format,
+    /// Colors of each state, for output modes that support them.
+    pub colors: Colors,
+    /// Let i3bar render pango markup in the formats, e.g. `<b>{glyph}</b>`.
+    pub markup: bool,
+    pub hooks: Hooks,
+    pub clicks: Clicks,
+    /// Where to listen for control commands, see `control`. Defaults to a socket in
+    /// `$XDG_RUNTIME_DIR` for each i3 instance, see `control::default_socket_path`.
+    pub control_socket: Option<PathBuf>,
+    /// Offer the control commands as a D-Bus service on the session bus. Only the first daemon
+    /// on the bus gets the service name, e.g. of nested i3 sessions.
+    pub dbus: bool,
+    /// Serve Prometheus metrics on this port of localhost, if built with the `metrics` feature.
+    pub metrics_port: Option<u16>,
+    /// Show a desktop notification when splitting is paused or resumed or the scheme is changed
+    /// with a keybind or control command.
+    pub notify: bool,
+    /// Reload the config file whenever it changes, as on SIGHUP. The output mode, control
+    /// socket and D-Bus service are only set up on startup.
+    pub watch: bool,
+    /// What to log, in the same format as `RUST_LOG`, which takes precedence.
+    pub log_level: Option<String>,
+    pub log_target: LogTarget,
+    /// A file to append the log to instead of writing it to stderr.
+    pub log_file: Option<PathBuf>,
+    /// Per-workspace overrides, keyed by workspace name.
+    pub workspace: HashMap<String, WorkspaceConfig>,
+    /// Per-output overrides, keyed by output name.
+    pub outputs: HashMap<String, OutputConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            scheme: Scheme::default(),
+            ratio: 1.0,
+            master_ratio: 0.6,
+            golden_ratio: None,
+            limit: None,
+            min_width: 0,
+            min_height: 0,
+            gaps: 0,
+            tabbed_after: None,
+            flatten: true,
+            mark: false,
+            restore_on_exit: false,
+            max_depth: None,
+            grace_period: 10.0,
+            workspaces: Vec::new(),
+            ignore_workspaces: Vec::new(),
+            ignore_outputs: Vec::new(),
+            orientation: None,
+            rules: Vec::new(),
+            events: vec![
+                WindowChange::New,
+                WindowChange::Focus,
+                WindowChange::Move,
+                WindowChange::Close,
+                WindowChange::FullscreenMode,
+            ],
+            debounce: 0.03,
+            pause_modes: vec!["resize".to_owned()],
+            ignore_window_types: ["dialog", "splash", "utility", "notification"]
+                .map(str::to_owned)
+                .into(),
+            output: OutputMode::default(),
+            force: false,
+            wrap: false,
+            quiet: false,
+            dry_run: false,
+            explain: false,
+            polybar_module: "alternating".to_owned(),
+            glyphs: Glyphs::default(),
+            format: Format::default(),
+            colors: Colors::default(),
+            markup: false,
+            hooks: Hooks::default(),
+            clicks: Clicks::default(),
+            control_socket: None,
+            dbus: false,
+            metrics_port: None,
+            notify: false,
+            watch: false,
+            log_level: None,
+            log_target: LogTarget::default(),
+            log_file: None,
+            workspace: HashMap::new(),
+            outputs: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    pub scheme: Option<Scheme>,
+    pub ratio: Option<f64>,
+    /// The name of a saved layout to restore when the first window opens on the workspace.
+    pub layout: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub orientation: Option<Orientation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    /// Vertical on outputs taller than they are wide or rotated upright on sway, horizontal
+    /// on the others.
+    Auto,
+    Horizontal,
+    Vertical,
+}
+
+impl Orientation {
+    /// The split for a window on `output`.
+    pub fn split(self, output: &Node) -> I3Split {
+        let (_, _, width, height) = output.rect;
+        let rotated = output
+            .transform
+            .as_deref()
+            .is_some_and(|transform| transform.ends_with("90") || transform.ends_with("270"));
+        match self {
+            Orientation::Auto if rotated || height > width => I3Split::Vertical,
+            Orientation::Auto | Orientation::Horizontal => I3Split::Horizontal,
+            Orientation::Vertical => I3Split::Vertical,
+        }
+    }
+}
+
+/// Window criteria, which all have to match exactly.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub title: Option<String>,
+    pub app_id: Option<String>,
+    /// Matching windows open into a container with this layout, next to the other windows
+    /// matching the rule if there is one on the workspace.
+    pub layout: Option<RuleLayout>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleLayout {
+    Tabbed,
+    Stacked,
+}
+
+impl RuleLayout {
+    /// The i3 command giving a container the layout.
+    pub fn command(self) -> &'static str {
+        match self {
+            RuleLayout::Tabbed => "layout tabbed",
+            RuleLayout::Stacked => "layout stacking",
+        }
+    }
+
+    pub fn node_layout(self) -> NodeLayout {
+        match self {
+            RuleLayout::Tabbed => NodeLayout::Tabbed,
+            RuleLayout::Stacked => NodeLayout::Stacked,
+        }
+    }
+
+    pub fn split(self) -> I3Split {
+        match self {
+            RuleLayout::Tabbed => I3Split::Tabbed,
+            RuleLayout::Stacked => I3Split::Stacked,
+        }
+    }
+}
+
+impl Rule {
+    pub fn matches(&self, node: &Node) -> bool {
+        let properties = node.window_properties.as_ref();
+        let criteria = [
+            (&self.class, properties.and_then(|p| p.class.as_ref())),
+            (&self.instance, properties.and_then(|p| p.instance.as_ref())),
+            (
+                &self.title,
+                properties
+                    .and_then(|p| p.title.as_ref())
+                    .or(node.name.as_ref()),
+            ),
+            (&self.app_id, node.app_id.as_ref()),
+        ];
+
+        criteria
+            .iter()
+            .all(|(expected, actual)| expected.is_none() || expected.as_ref() == *actual)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.class.is_none()
+            && self.instance.is_none()
+            && self.title.is_none()
+            && self.app_id.is_none()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Glyphs {
+    pub horizontal: String,
+    pub vertical: String,
+    pub tabbed: String,
+    pub stacked: String,
+    pub paused: String,
+    /// Shown while a floating window is focused. The status is left as it is if this isn't set.
+    pub floating: Option<String>,
+}
+
+impl Default for Glyphs {
+    fn default() -> Self {
+        Glyphs {
+            horizontal: "→".to_owned(),
+            vertical: " ↓".to_owned(),
+            tabbed: "t".to_owned(),
+            stacked: "s".to_owned(),
+            paused: "⏸".to_owned(),
+            floating: None,
+        }
+    }
+}
+
+impl Glyphs {
+    pub fn get(&self, split: &I3Split) -> &str {
+        match split {
+            // Toggling is resolved before printing, so it is never shown.
+            I3Split::Horizontal | I3Split::Toggle => &self.horizontal,
+            I3Split::Vertical => &self.vertical,
+            I3Split::Tabbed => &self.tabbed,
+            I3Split::Stacked => &self.stacked,
+        }
+    }
+}
+
+/// What is printed for each state. `{glyph}` is replaced with the state's glyph, `{workspace}`
+/// with the name of the focused workspace, `{windows}` with the number of windows on it,
+/// `{container_windows}` with the number in the focused container and `{scheme}` with the
+/// workspace's scheme.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Format {
+    pub horizontal: String,
+    pub vertical: String,
+    pub tabbed: String,
+    pub stacked: String,
+    pub paused: String,
+    pub floating: String,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format {
+            horizontal: "{glyph}".to_owned(),
+            vertical: "{glyph}".to_owned(),
+            tabbed: "{glyph}".to_owned(),
+            stacked: "{glyph}".to_owned(),
+            paused: "{glyph}".to_owned(),
+            floating: "{glyph}".to_owned(),
+        }
+    }
+}
+
+impl Format {
+    fn templates(&self) -> [&String; 6] {
+        [
+            &self.horizontal,
+            &self.vertical,
+            &self.tabbed,
+            &self.stacked,
+            &self.paused,
+            &self.floating,
+        ]
+    }
+
+    /// Whether any template needs to know about the workspace.
+    fn has_placeholders(&self) -> bool {
+        self.templates().iter().any(|template| {
+            [
+                "{workspace}",
+                "{windows}",
+                "{container_windows}",
+                "{scheme}",
+            ]
+            .iter()
+            .any(|placeholder| template.contains(placeholder))
+        })
+    }
+
+    /// Whether any template shows the scheme.
+    pub fn has_scheme(&self) -> bool {
+        self.templates()
+            .iter()
+            .any(|template| template.contains("{scheme}"))
+    }
+
+    pub fn get(&self, split: &I3Split) -> &str {
+        match split {
+            // Toggling is resolved before printing, so it is never shown.
+            I3Split::Horizontal | I3Split::Toggle => &self.horizontal,
+            I3Split::Vertical => &self.vertical,
+            I3Split::Tabbed => &self.tabbed,
+            I3Split::Stacked => &self.stacked,
+        }
+    }
+}
+
+/// Shell commands run when things change, with `ALT_EVENT`, `ALT_SPLIT`, `ALT_WORKSPACE` and
+/// `ALT_CONTAINER_ID` in their environment, see `hooks`.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Run after the daemon split a window or changed the layout of a container.
+    pub layout: Option<String>,
+    /// Run when the status changes, with the state it shows as `ALT_SPLIT`.
+    pub status: Option<String>,
+}
+
+/// The i3 commands run when the status is clicked in i3bar or i3blocks, by button. Commands for
+/// the daemon itself go in a `nop`, as with bindings.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Clicks {
+    pub left: Option<String>,
+    pub middle: Option<String>,
+    pub right: Option<String>,
+    pub scroll_up: Option<String>,
+    pub scroll_down: Option<String>,
+}
+
+impl Default for Clicks {
+    fn default() -> Self {
+        Clicks {
+            left: Some("split toggle".to_owned()),
+            middle: None,
+            right: Some("nop alternating:toggle".to_owned()),
+            scroll_up: Some("nop alternating:scheme previous".to_owned()),
+            scroll_down: Some("nop alternating:scheme next".to_owned()),
+        }
+    }
+}
+
+impl Clicks {
+    /// The command for `button`, numbered as X11 does.
+    pub fn get(&self, button: u32) -> Option<&str> {
+        match button {
+            1 => self.left.as_deref(),
+            2 => self.middle.as_deref(),
+            3 => self.right.as_deref(),
+            4 => self.scroll_up.as_deref(),
+            5 => self.scroll_down.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub horizontal: Option<String>,
+    pub vertical: Option<String>,
+    pub tabbed: Option<String>,
+    pub stacked: Option<String>,
+    pub paused: Option<String>,
+    pub floating: Option<String>,
+    /// For the split of a window the daemon leaves alone, e.g. because of a rule, instead of
+    /// the color of the split.
+    pub ignored: Option<String>,
+}
+
+impl Colors {
+    pub fn get(&self, split: &I3Split) -> Option<&str> {
+        match split {
+            // Toggling is resolved before printing, so it is never shown.
+            I3Split::Horizontal | I3Split::Toggle => self.horizontal.as_deref(),
+            I3Split::Vertical => self.vertical.as_deref(),
+            I3Split::Tabbed => self.tabbed.as_deref(),
+            I3Split::Stacked => self.stacked.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::ParseError),
+    Invalid(PathBuf, serde_json::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "couldn't read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "couldn't parse {}: {e}", path.display()),
+            ConfigError::Invalid(path, e) => write!(f, "invalid config {}: {e}", path.display()),
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/i3-alternating-layout/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+        Some(
+            config_home
+                .join("i3-alternating-layout")
+                .join("config.toml"),
+        )
+    }
+
+    /// Loads the config from the default path, using the defaults if there is no config file.
+    pub fn load() -> Result<Self, ConfigError> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::from_path(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_owned(), e))?;
+        let value = toml::parse(&contents).map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+
+        let config: Config =
+            serde_json::from_value(value).map_err(|e| ConfigError::Invalid(path.to_owned(), e))?;
+        config
+            .validate()
+            .map_err(|e| ConfigError::Invalid(path.to_owned(), serde::de::Error::custom(e)))?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let is_valid = |ratio: f64| ratio.is_finite() && ratio > 0.0;
+        if !is_valid(self.ratio) {
+            return Err(format!(
+                "ratio must be a positive number, got {}",
+                self.ratio
+            ));
+        }
+        if !(self.master_ratio > 0.0 && self.master_ratio < 1.0) {
+            return Err(format!(
+                "master_ratio must be between 0 and 1, got {}",
+                self.master_ratio
+            ));
+        }
+        match self.golden_ratio {
+            Some(ratio) if !(ratio > 0.0 && ratio < 1.0) => {
+                return Err(format!("golden_ratio must be between 0 and 1, got {ratio}"))
+            }
+            _ => {}
+        }
+        if self.tabbed_after == Some(0) {
+            return Err("tabbed_after must be at least 1".to_owned());
+        }
+        if self.max_depth == Some(0) {
+            return Err("max_depth must be at least 1".to_owned());
+        }
+        for (name, seconds) in [
+            ("grace_period", self.grace_period),
+            ("debounce", self.debounce),
+        ] {
+            if !(seconds.is_finite() && seconds >= 0.0) {
+                return Err(format!("{name} must be a number of seconds, got {seconds}"));
+            }
+        }
+        for (name, workspace) in &self.workspace {
+            match workspace.ratio {
+                Some(ratio) if !is_valid(ratio) => {
+                    return Err(format!(
+                        "ratio for workspace {name} must be a positive number, got {ratio}"
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        if self.events.contains(&WindowChange::Unknown) {
+            return Err(
+                "events may only contain new, close, focus, title, fullscreen_mode, move, \
+                 floating, urgent and mark"
+                    .to_owned(),
+            );
+        }
+
+        if let Some(directives) = &self.log_level {
+            logger::Filter::parse(directives).map_err(|e| format!("log_level: {e}"))?;
+        }
+
+        if self.rules.iter().any(Rule::is_empty) {
+            return Err("rules need at least one of class, instance, title or app_id".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// The scheme for `workspace`, preferring its own override over the global setting.
+    pub fn scheme(&self, workspace: Option<&str>) -> Scheme {
+        workspace
+            .and_then(|name| self.workspace.get(name))
+            .and_then(|ws| ws.scheme)
+            .unwrap_or(self.scheme)
+    }
+
+    /// The ratio for `workspace`, preferring its own override over the global setting.
+    pub fn ratio(&self, workspace: Option<&str>) -> f64 {
+        workspace
+            .and_then(|name| self.workspace.get(name))
+            .and_then(|ws| ws.ratio)
+            .unwrap_or(self.ratio)
+    }
+
+    /// The layout the first window on `workspace` is placed in.
+    pub fn layout(&self, workspace: Option<&str>) -> Option<&str> {
+        workspace
+            .and_then(|name| self.workspace.get(name))
+            .and_then(|ws| ws.layout.as_deref())
+    }
+
+    /// How windows directly on a workspace of `output` are split, if not by the scheme.
+    pub fn orientation(&self, output: Option<&str>) -> Option<Orientation> {
+        output
+            .and_then(|name| self.outputs.get(name))
+            .and_then(|output| output.orientation)
+            .or(self.orientation)
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs_f64(self.grace_period)
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_secs_f64(self.debounce)
+    }
+
+    /// Whether windows are handled the same no matter where in the tree they are.
+    pub fn is_uniform(&self) -> bool {
+        self.workspaces.is_empty()
+            && self.ignore_workspaces.is_empty()
+            && self.ignore_outputs.is_empty()
+            && self.orientation.is_none()
+            && self.outputs.is_empty()
+            && self.workspace.is_empty()
+            && self.limit.is_none()
+            && self.max_depth.is_none()
+            && !self.format.has_placeholders()
+    }
+
+    pub fn is_managed(&self, workspace: &str) -> bool {
+        (self.workspaces.is_empty() || self.workspaces.iter().any(|ws| ws == workspace))
+            && !self.ignore_workspaces.iter().any(|ws| ws == workspace)
+    }
+
+    pub fn is_ignored_output(&self, output: &str) -> bool {
+        self.ignore_outputs.iter().any(|o| o == output)
+    }
+
+    pub fn is_ignored_window_type(&self, node: &Node) -> bool {
+        node.window_type
+            .as_ref()
+            .is_some_and(|window_type| self.ignore_window_types.contains(window_type))
+    }
+}
+
+/// Sends `Input::Reload` to `inputs` whenever the modification time of `path` changes.
+pub fn watch(path: PathBuf, inputs: Sender<Input>) {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    thread::spawn(move || {
+        let mut last = modified(&path);
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let current = modified(&path);
+            if current != last {
+                last = current;
+                if inputs.send(Input::Reload).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
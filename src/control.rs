@@ -0,0 +1,318 @@
+//! The command protocol used to control the daemon at runtime, e.g. through
+//! `i3-msg -t send_tick "alternating:scheme spiral"`, `nop alternating:pause` bindings or the
+//! control socket.
+//!
+//! The socket takes one command per line and answers each with a line of JSON, which always has
+//! a `success` field and an `error` field if it is `false`.
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::{scheme::Scheme, Input};
+
+/// Prefix marking messages meant for this daemon.
+pub const PREFIX: &str = "alternating:";
+
+/// A command, applying to the workspace it names or to all of them.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Stop splitting windows until resumed.
+    Pause(Option<String>),
+    Resume(Option<String>),
+    /// Pause if running, resume if paused.
+    Toggle(Option<String>),
+    /// Report the state of the daemon, as it applies to the workspace or the focused one.
+    Status(Option<String>),
+    /// Use this scheme, or go back to the configured schemes for `None`.
+    Scheme(Option<Scheme>, Option<String>),
+    /// Give all windows in a container equal shares of it, undoing manual resizes. Only
+    /// applies to the named workspace or the focused one.
+    Balance(Option<String>),
+    /// Turn every horizontal split of the named workspace or the focused one into a vertical
+    /// one and vice versa.
+    Transpose(Option<String>),
+    /// Swap the focused window with the largest one of its workspace, or with the next largest
+    /// if it is the largest.
+    Promote,
+    /// Reverse the most recent automatic split or layout change.
+    Undo,
+    /// Save the layout of the focused workspace under this name, see `layout`.
+    Save(String),
+    /// Restore the layout saved under this name on the focused workspace.
+    Restore(String),
+    /// Move the last window of the focused container to the front, shifting the others back.
+    Rotate,
+    /// Report whether the daemon is connected to i3 and when it last got an event.
+    Health,
+    /// Report the counters of what the daemon did since it started.
+    Stats,
+    /// Switch the named workspace, or all of them, to the next scheme, or to the previous one
+    /// for `true`.
+    CycleScheme(bool, Option<String>),
+}
+
+impl Command {
+    /// Parses `message` if it is addressed to this daemon.
+    pub fn from_message(message: &str) -> Option<Result<Self, String>> {
+        message.trim().strip_prefix(PREFIX).map(str::parse)
+    }
+}
+
+/// The commands offered by the `menu` subcommand, one per line as dmenu and rofi take them.
+pub fn menu() -> Vec<String> {
+    let mut entries: Vec<_> = ["toggle", "pause", "resume"].map(str::to_owned).into();
+    entries.extend(
+        Scheme::NAMES
+            .split(", ")
+            .map(|name| format!("scheme {name}")),
+    );
+    entries.push("scheme default".to_owned());
+    entries.extend(["balance", "transpose", "rotate", "promote", "undo"].map(str::to_owned));
+    entries
+}
+
+/// Splits off the first word of `s`.
+fn first_word(s: &str) -> (&str, &str) {
+    let (word, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    (word, rest.trim())
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // `set scheme spiral`, as the ctl subcommand has it.
+        let (command, rest) = first_word(s.strip_prefix("set ").unwrap_or(s));
+        // Workspace names may contain spaces, so the rest of the line is the name.
+        let workspace = |rest: &str| (!rest.is_empty()).then(|| rest.to_owned());
+        match command {
+            "pause" => Ok(Command::Pause(workspace(rest))),
+            "resume" => Ok(Command::Resume(workspace(rest))),
+            "toggle" => Ok(Command::Toggle(workspace(rest))),
+            "status" => Ok(Command::Status(workspace(rest))),
+            "balance" => Ok(Command::Balance(workspace(rest))),
+            "transpose" => Ok(Command::Transpose(workspace(rest))),
+            "promote" | "swap-master" | "swap_master" if rest.is_empty() => Ok(Command::Promote),
+            "promote" | "swap-master" | "swap_master" => Err(format!("usage: {command}")),
+            "undo" if rest.is_empty() => Ok(Command::Undo),
+            "undo" => Err("usage: undo".to_owned()),
+            "save" | "restore" if rest.is_empty() => Err(format!("usage: {command} <name>")),
+            "save" => Ok(Command::Save(rest.to_owned())),
+            "restore" => Ok(Command::Restore(rest.to_owned())),
+            "rotate" if rest.is_empty() => Ok(Command::Rotate),
+            "rotate" => Err("usage: rotate".to_owned()),
+            "health" if rest.is_empty() => Ok(Command::Health),
+            "health" => Err("usage: health".to_owned()),
+            "stats" if rest.is_empty() => Ok(Command::Stats),
+            "stats" => Err("usage: stats".to_owned()),
+            "scheme" | "set-scheme" => {
+                let (scheme, rest) = first_word(rest);
+                if let "next" | "previous" = scheme {
+                    return Ok(Command::CycleScheme(scheme == "previous", workspace(rest)));
+                }
+                let scheme = match scheme {
+                    "" => {
+                        return Err(
+                            "usage: scheme <name>|default|next|previous [<workspace>]".to_owned()
+                        )
+                    }
+                    "default" => None,
+                    scheme => Some(scheme.parse()?),
+                };
+                Ok(Command::Scheme(scheme, workspace(rest)))
+            }
+            _ => Err(format!("unknown command '{s}'")),
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/i3-alternating-layout-<hash>.sock`, named after the IPC socket of the
+/// compositor like the lock of `instance`, so the daemons of nested or parallel sessions each
+/// have one.
+pub fn default_socket_path(wm_socket: &Path) -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(&dir).join(socket_name(wm_socket)))
+}
+
+fn socket_name(wm_socket: &Path) -> String {
+    // FNV-1a, as the hasher of std may change between builds of the daemon and of `ctl`, and the
+    // path itself may not fit into a socket address.
+    let hash = wm_socket
+        .as_os_str()
+        .as_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("i3-alternating-layout-{hash:016x}.sock")
+}
+
+/// Binds the control socket at `path` and forwards the commands sent to it to `inputs`.
+pub fn serve(path: &Path, inputs: Sender<Input>) -> io::Result<()> {
+    let listener = match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is used by another instance", path.display()),
+                ));
+            }
+            // Left behind by an instance that didn't exit cleanly.
+            fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        }
+        listener => listener?,
+    };
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let inputs = inputs.clone();
+            thread::spawn(move || handle_client(stream, &inputs));
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_client(stream: UnixStream, inputs: &Sender<Input>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match line.parse() {
+            Ok(command) => {
+                let (reply, response) = mpsc::channel();
+                inputs
+                    .send(Input::Control(command, reply))
+                    .ok()
+                    .and_then(|()| response.recv().ok())
+                    .unwrap_or_else(|| error("the daemon is shutting down"))
+            }
+            Err(e) => error(&e),
+        };
+        writeln!(writer, "{response}")?;
+    }
+
+    Ok(())
+}
+
+/// Sends `command` to the daemon listening on `path` and returns its response.
+pub fn send(path: &Path, command: &str) -> io::Result<Value> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{command}")?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    serde_json::from_str(&response).map_err(io::Error::from)
+}
+
+fn error(message: &str) -> Value {
+    json!({ "success": false, "error": message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_the_socket_after_the_compositor() {
+        // The same for every build, so `ctl` finds a daemon built differently.
+        assert_eq!(
+            socket_name(Path::new("/run/user/1000/i3/ipc-socket.1234")),
+            "i3-alternating-layout-3966864da746ba93.sock"
+        );
+        assert_ne!(
+            socket_name(Path::new("/run/user/1000/i3/ipc-socket.1234")),
+            socket_name(Path::new("/run/user/1000/i3/ipc-socket.5678"))
+        );
+    }
+
+    fn parse(s: &str) -> Result<Command, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("pause"), Ok(Command::Pause(None)));
+        assert_eq!(parse("  toggle \n"), Ok(Command::Toggle(None)));
+        assert_eq!(parse("swap-master"), Ok(Command::Promote));
+        assert_eq!(parse("save coding"), Ok(Command::Save("coding".to_owned())));
+        assert_eq!(
+            parse("scheme master-stack"),
+            Ok(Command::Scheme(Some(Scheme::MasterStack), None))
+        );
+        assert_eq!(parse("set scheme default"), Ok(Command::Scheme(None, None)));
+        assert_eq!(
+            parse("scheme previous"),
+            Ok(Command::CycleScheme(true, None))
+        );
+    }
+
+    #[test]
+    fn parses_the_workspace_of_commands() {
+        // The rest of the line, as names may contain spaces.
+        assert_eq!(
+            parse("pause 2: web"),
+            Ok(Command::Pause(Some("2: web".to_owned())))
+        );
+        assert_eq!(
+            parse("transpose 3"),
+            Ok(Command::Transpose(Some("3".to_owned())))
+        );
+        assert_eq!(
+            parse("scheme spiral 2: web"),
+            Ok(Command::Scheme(
+                Some(Scheme::Spiral),
+                Some("2: web".to_owned())
+            ))
+        );
+        assert_eq!(
+            parse("scheme next 4"),
+            Ok(Command::CycleScheme(false, Some("4".to_owned())))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert_eq!(parse("dance"), Err("unknown command 'dance'".to_owned()));
+        assert_eq!(parse(""), Err("unknown command ''".to_owned()));
+        assert_eq!(parse("undo 2"), Err("usage: undo".to_owned()));
+        assert_eq!(parse("promote now"), Err("usage: promote".to_owned()));
+        assert_eq!(parse("restore"), Err("usage: restore <name>".to_owned()));
+        assert!(parse("scheme").unwrap_err().starts_with("usage: scheme"));
+        assert!(parse("scheme zigzag")
+            .unwrap_err()
+            .starts_with("unknown scheme 'zigzag'"));
+    }
+
+    #[test]
+    fn parses_only_messages_with_the_prefix() {
+        assert_eq!(
+            Command::from_message(" alternating:resume 1 "),
+            Some(Ok(Command::Resume(Some("1".to_owned()))))
+        );
+        assert_eq!(
+            Command::from_message("alternating:dance"),
+            Some(Err("unknown command 'dance'".to_owned()))
+        );
+        assert_eq!(Command::from_message("resume"), None);
+        assert_eq!(Command::from_message("other:resume"), None);
+    }
+}
@@ -0,0 +1,828 @@
+//! A service on the D-Bus session bus, offering the control commands as methods, e.g.
+//!
+//! ```sh
+//! busctl --user call org.i3wm.AlternatingLayout /org/i3wm/AlternatingLayout \
+//!     org.i3wm.AlternatingLayout SetScheme s spiral
+//! ```
+//!
+//! and emitting `LayoutChanged(s)` with the name of the new state whenever the status changes.
+//! Desktop notifications are sent over a connection of their own.
+//!
+//! Only the part of the protocol needed for that is implemented: connecting to `unix:`
+//! addresses, EXTERNAL authentication, and messages whose arguments are strings, integers or
+//! empty arrays.
+
+use std::{
+    env,
+    io::{self, Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixStream},
+    },
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, Sender},
+        Mutex, OnceLock,
+    },
+    thread,
+};
+
+use log::warn;
+
+use crate::{control::Command, Input};
+
+const NAME: &str = "org.i3wm.AlternatingLayout";
+const PATH: &str = "/org/i3wm/AlternatingLayout";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.i3wm.AlternatingLayout">
+    <method name="Pause"/>
+    <method name="Resume"/>
+    <method name="Balance"/>
+    <method name="Rotate"/>
+    <method name="Promote"/>
+    <method name="Undo"/>
+    <method name="Transpose"/>
+    <method name="Save">
+      <arg name="name" type="s" direction="in"/>
+    </method>
+    <method name="Restore">
+      <arg name="name" type="s" direction="in"/>
+    </method>
+    <method name="SetScheme">
+      <arg name="scheme" type="s" direction="in"/>
+    </method>
+    <signal name="LayoutChanged">
+      <arg name="state" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+/// Header field codes.
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// Messages larger than this are rejected instead of being read into memory.
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// How long notifications are shown for, in milliseconds.
+const NOTIFICATION_TIMEOUT: i32 = 3000;
+
+static BUS: OnceLock<Bus> = OnceLock::new();
+/// Passes the summary and body of notifications to the thread sending them.
+static NOTIFICATIONS: OnceLock<Sender<(String, String)>> = OnceLock::new();
+
+extern "C" {
+    fn getuid() -> u32;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    Str(String),
+    U32(u32),
+    I32(i32),
+    /// An empty array of elements with this signature.
+    EmptyArray(&'static str),
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    args: Vec<Arg>,
+}
+
+impl Message {
+    fn method_call(destination: &str, path: &str, interface: &str, member: &str) -> Self {
+        Message {
+            kind: METHOD_CALL,
+            path: Some(path.to_owned()),
+            interface: Some(interface.to_owned()),
+            member: Some(member.to_owned()),
+            destination: Some(destination.to_owned()),
+            ..Message::default()
+        }
+    }
+
+    fn method_return(call: &Message) -> Self {
+        Message {
+            kind: METHOD_RETURN,
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            ..Message::default()
+        }
+    }
+
+    fn error(call: &Message, name: &str, message: &str) -> Self {
+        Message {
+            kind: ERROR,
+            error_name: Some(name.to_owned()),
+            args: vec![Arg::Str(message.to_owned())],
+            ..Message::method_return(call)
+        }
+    }
+
+    fn encode(&self, serial: u32) -> Vec<u8> {
+        let mut body = Encoder::default();
+        for arg in &self.args {
+            match arg {
+                Arg::Str(s) => body.string(s),
+                Arg::U32(n) => body.u32(*n),
+                Arg::I32(n) => body.u32(*n as u32),
+                Arg::EmptyArray(element) => {
+                    body.u32(0);
+                    // Padded to the alignment of the first element even without one.
+                    body.pad(if element.starts_with('{') { 8 } else { 4 });
+                }
+            }
+        }
+        let signature: String = self
+            .args
+            .iter()
+            .map(|arg| match arg {
+                Arg::Str(_) => "s".to_owned(),
+                Arg::U32(_) => "u".to_owned(),
+                Arg::I32(_) => "i".to_owned(),
+                Arg::EmptyArray(element) => format!("a{element}"),
+            })
+            .collect();
+
+        let mut message = Encoder::default();
+        message.bytes(&[b'l', self.kind, self.flags, 1]);
+        message.u32(body.buf.len() as u32);
+        message.u32(serial);
+        // The length of the header field array, filled in below.
+        message.u32(0);
+
+        let strings = [
+            (FIELD_PATH, "o", &self.path),
+            (FIELD_INTERFACE, "s", &self.interface),
+            (FIELD_MEMBER, "s", &self.member),
+            (FIELD_ERROR_NAME, "s", &self.error_name),
+            (FIELD_DESTINATION, "s", &self.destination),
+            (FIELD_SENDER, "s", &self.sender),
+        ];
+        for (code, kind, value) in strings {
+            if let Some(value) = value {
+                message.pad(8);
+                message.bytes(&[code]);
+                message.signature(kind);
+                message.string(value);
+            }
+        }
+        if let Some(reply_serial) = self.reply_serial {
+            message.pad(8);
+            message.bytes(&[FIELD_REPLY_SERIAL]);
+            message.signature("u");
+            message.u32(reply_serial);
+        }
+        if !signature.is_empty() {
+            message.pad(8);
+            message.bytes(&[FIELD_SIGNATURE]);
+            message.signature("g");
+            message.signature(&signature);
+        }
+
+        let fields_len = (message.buf.len() - 16) as u32;
+        message.buf[12..16].copy_from_slice(&fields_len.to_le_bytes());
+        message.pad(8);
+        message.buf.extend(body.buf);
+        message.buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder {
+            buf,
+            // Just after the endianness flag.
+            pos: 1,
+            big_endian: buf.first() == Some(&b'B'),
+        };
+        let mut message = Message {
+            kind: decoder.byte()?,
+            flags: decoder.byte()?,
+            ..Message::default()
+        };
+        decoder.byte()?;
+        decoder.u32()?;
+        message.serial = decoder.u32()?;
+
+        let fields_end = decoder.u32()? as usize + 16;
+        let mut signature = String::new();
+        while decoder.pos < fields_end {
+            decoder.pad(8);
+            let code = decoder.byte()?;
+            let kind = decoder.signature()?;
+            match kind.as_str() {
+                "s" | "o" => {
+                    let value = Some(decoder.string()?);
+                    match code {
+                        FIELD_PATH => message.path = value,
+                        FIELD_INTERFACE => message.interface = value,
+                        FIELD_MEMBER => message.member = value,
+                        FIELD_ERROR_NAME => message.error_name = value,
+                        FIELD_DESTINATION => message.destination = value,
+                        FIELD_SENDER => message.sender = value,
+                        _ => {}
+                    }
+                }
+                "u" => {
+                    let value = decoder.u32()?;
+                    if code == FIELD_REPLY_SERIAL {
+                        message.reply_serial = Some(value);
+                    }
+                }
+                "g" => signature = decoder.signature()?,
+                _ => return None,
+            }
+        }
+
+        decoder.pad(8);
+        for kind in signature.chars() {
+            message.args.push(match kind {
+                's' => Arg::Str(decoder.string()?),
+                'u' => Arg::U32(decoder.u32()?),
+                // Nothing this service handles takes other arguments.
+                _ => break,
+            });
+        }
+
+        Some(message)
+    }
+}
+
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn pad(&mut self, align: usize) {
+        while !self.buf.len().is_multiple_of(align) {
+            self.buf.push(0);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.pad(4);
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes(s.as_bytes());
+        self.bytes(&[0]);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.bytes(&[s.len() as u8]);
+        self.bytes(s.as_bytes());
+        self.bytes(&[0]);
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl Decoder<'_> {
+    fn pad(&mut self, align: usize) {
+        self.pos = self.pos.next_multiple_of(align);
+    }
+
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.buf.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.pad(4);
+        let bytes = self.take(4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        let s = String::from_utf8(self.take(len)?.to_vec()).ok()?;
+        self.take(1)?;
+        Some(s)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let len = self.byte()? as usize;
+        let s = String::from_utf8(self.take(len)?.to_vec()).ok()?;
+        self.take(1)?;
+        Some(s)
+    }
+}
+
+/// The writing half of the bus connection, shared by the thread answering method calls and the
+/// main thread emitting signals.
+struct Bus {
+    stream: Mutex<UnixStream>,
+    serial: AtomicU32,
+}
+
+impl Bus {
+    fn send(&self, message: &Message) -> io::Result<u32> {
+        let serial = self.serial.fetch_add(1, Ordering::Relaxed);
+        let bytes = message.encode(serial);
+        let mut stream = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        stream.write_all(&bytes)?;
+        Ok(serial)
+    }
+
+    /// Sends a method call and waits for its reply, skipping any other messages.
+    fn call(&self, stream: &mut UnixStream, message: &Message) -> io::Result<Message> {
+        let serial = self.send(message)?;
+        loop {
+            let reply = read_message(stream)?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            if reply.kind == ERROR {
+                let error = match reply.args.first() {
+                    Some(Arg::Str(message)) => message.clone(),
+                    _ => reply.error_name.unwrap_or_default(),
+                };
+                return Err(io::Error::other(error));
+            }
+            return Ok(reply);
+        }
+    }
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed D-Bus message")
+}
+
+fn read_message(stream: &mut UnixStream) -> io::Result<Message> {
+    let mut buf = vec![0; 16];
+    stream.read_exact(&mut buf)?;
+    let u32_at = |buf: &[u8], at: usize| {
+        let bytes = [buf[at], buf[at + 1], buf[at + 2], buf[at + 3]];
+        if buf[0] == b'B' {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    let body_len = u32_at(&buf, 4) as usize;
+    let header_len = (16 + u32_at(&buf, 12) as usize).next_multiple_of(8);
+    if header_len + body_len > MAX_MESSAGE_LEN {
+        return Err(malformed());
+    }
+
+    buf.resize(header_len + body_len, 0);
+    stream.read_exact(&mut buf[16..])?;
+    Message::decode(&buf).ok_or_else(malformed)
+}
+
+/// Undoes the `%xx` escaping of address values.
+fn unescape(value: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Connects to the first reachable `unix:` address of the session bus.
+fn connect() -> io::Result<UnixStream> {
+    let addresses = env::var("DBUS_SESSION_BUS_ADDRESS")
+        .ok()
+        .filter(|address| !address.is_empty())
+        .or_else(|| {
+            let dir = env::var("XDG_RUNTIME_DIR").ok()?;
+            Some(format!("unix:path={dir}/bus"))
+        })
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "DBUS_SESSION_BUS_ADDRESS isn't set",
+            )
+        })?;
+
+    let mut error = io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("no supported address in '{addresses}'"),
+    );
+    for address in addresses.split(';') {
+        let Some(params) = address.strip_prefix("unix:") else {
+            continue;
+        };
+        let stream = params
+            .split(',')
+            .find_map(|param| match param.split_once('=') {
+                Some(("path", path)) => Some(UnixStream::connect(unescape(path))),
+                Some(("abstract", name)) => Some(
+                    SocketAddr::from_abstract_name(unescape(name))
+                        .and_then(|addr| UnixStream::connect_addr(&addr)),
+                ),
+                _ => None,
+            });
+        match stream {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(e)) => error = e,
+            None => {}
+        }
+    }
+
+    Err(error)
+}
+
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    // SAFETY: getuid never fails.
+    let uid = unsafe { getuid() };
+    let hex_uid: String = uid
+        .to_string()
+        .bytes()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {hex_uid}\r\n").as_bytes())?;
+
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    if !line.starts_with(b"OK ") {
+        let line = String::from_utf8_lossy(&line);
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("authentication rejected: {}", line.trim_end()),
+        ));
+    }
+
+    stream.write_all(b"BEGIN\r\n")
+}
+
+/// Connects to the session bus, claims the service name and answers method calls on a thread
+/// of its own by forwarding them to `inputs`.
+pub fn serve(inputs: Sender<Input>) -> io::Result<()> {
+    let mut stream = connect()?;
+    authenticate(&mut stream)?;
+    let bus = Bus {
+        stream: Mutex::new(stream.try_clone()?),
+        serial: AtomicU32::new(1),
+    };
+
+    let dbus = "org.freedesktop.DBus";
+    bus.call(
+        &mut stream,
+        &Message::method_call(dbus, "/org/freedesktop/DBus", dbus, "Hello"),
+    )?;
+    let request_name = Message {
+        // DBUS_NAME_FLAG_DO_NOT_QUEUE
+        args: vec![Arg::Str(NAME.to_owned()), Arg::U32(4)],
+        ..Message::method_call(dbus, "/org/freedesktop/DBus", dbus, "RequestName")
+    };
+    let reply = bus.call(&mut stream, &request_name)?;
+    if reply.args.first() != Some(&Arg::U32(1)) {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{NAME} is owned by another instance"),
+        ));
+    }
+
+    let bus = BUS.get_or_init(|| bus);
+    thread::spawn(move || {
+        while let Ok(message) = read_message(&mut stream) {
+            if message.kind != METHOD_CALL {
+                continue;
+            }
+            let reply = handle_call(&message, &inputs);
+            if message.flags & NO_REPLY_EXPECTED == 0 && bus.send(&reply).is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_call(call: &Message, inputs: &Sender<Input>) -> Message {
+    let command = match (call.interface.as_deref(), call.member.as_deref()) {
+        (Some("org.freedesktop.DBus.Introspectable"), Some("Introspect")) => {
+            return Message {
+                args: vec![Arg::Str(INTROSPECTION.to_owned())],
+                ..Message::method_return(call)
+            };
+        }
+        (Some("org.freedesktop.DBus.Peer"), Some("Ping")) => return Message::method_return(call),
+        (Some(NAME) | None, Some("Pause")) => Ok(Command::Pause(None)),
+        (Some(NAME) | None, Some("Resume")) => Ok(Command::Resume(None)),
+        (Some(NAME) | None, Some("Balance")) => Ok(Command::Balance(None)),
+        (Some(NAME) | None, Some("Rotate")) => Ok(Command::Rotate),
+        (Some(NAME) | None, Some("Promote")) => Ok(Command::Promote),
+        (Some(NAME) | None, Some("Undo")) => Ok(Command::Undo),
+        (Some(NAME) | None, Some("Transpose")) => Ok(Command::Transpose(None)),
+        (Some(NAME) | None, Some(member @ ("Save" | "Restore"))) => match call.args.first() {
+            Some(Arg::Str(name)) => format!("{} {name}", member.to_lowercase()).parse(),
+            _ => Err("expected the name of a layout".to_owned()),
+        },
+        (Some(NAME) | None, Some("SetScheme")) => match call.args.first() {
+            Some(Arg::Str(scheme)) => format!("scheme {scheme}").parse(),
+            _ => Err("expected the name of a scheme".to_owned()),
+        },
+        _ => {
+            let member = call.member.as_deref().unwrap_or_default();
+            return Message::error(
+                call,
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                &format!("unknown method '{member}'"),
+            );
+        }
+    };
+
+    let command = match command {
+        Ok(command) => command,
+        Err(e) => return Message::error(call, "org.freedesktop.DBus.Error.InvalidArgs", &e),
+    };
+    let (reply, response) = mpsc::channel();
+    let response = inputs
+        .send(Input::Control(command, reply))
+        .ok()
+        .and_then(|()| response.recv().ok());
+    match response {
+        Some(response) if response["success"] == true => Message::method_return(call),
+        Some(response) => Message::error(
+            call,
+            "org.freedesktop.DBus.Error.Failed",
+            response["error"].as_str().unwrap_or("command failed"),
+        ),
+        None => Message::error(
+            call,
+            "org.freedesktop.DBus.Error.Failed",
+            "the daemon is shutting down",
+        ),
+    }
+}
+
+/// Shows a desktop notification through the notification server, replacing the previous one.
+/// The notifications are sent from a thread of their own, which connects to the bus on the
+/// first one.
+pub fn notify(summary: &str, body: &str) {
+    let notifications = NOTIFICATIONS.get_or_init(|| {
+        let (sender, notifications) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(e) = send_notifications(notifications) {
+                warn!("couldn't show notifications: {e}");
+            }
+        });
+        sender
+    });
+    let _ = notifications.send((summary.to_owned(), body.to_owned()));
+}
+
+fn send_notifications(notifications: mpsc::Receiver<(String, String)>) -> io::Result<()> {
+    let mut stream = connect()?;
+    authenticate(&mut stream)?;
+    let bus = Bus {
+        stream: Mutex::new(stream.try_clone()?),
+        serial: AtomicU32::new(1),
+    };
+    let dbus = "org.freedesktop.DBus";
+    bus.call(
+        &mut stream,
+        &Message::method_call(dbus, "/org/freedesktop/DBus", dbus, "Hello"),
+    )?;
+
+    let server = "org.freedesktop.Notifications";
+    let mut id = 0;
+    for (summary, body) in notifications {
+        let notify = Message {
+            args: vec![
+                Arg::Str("i3-alternating-layout".to_owned()),
+                Arg::U32(id),
+                Arg::Str(String::new()),
+                Arg::Str(summary),
+                Arg::Str(body),
+                Arg::EmptyArray("s"),
+                Arg::EmptyArray("{sv}"),
+                Arg::I32(NOTIFICATION_TIMEOUT),
+            ],
+            ..Message::method_call(server, "/org/freedesktop/Notifications", server, "Notify")
+        };
+        if let Some(Arg::U32(replaced)) = bus.call(&mut stream, &notify)?.args.first() {
+            id = *replaced;
+        }
+    }
+
+    Ok(())
+}
+
+/// Emits `LayoutChanged` with `state`, if the service is running.
+pub fn layout_changed(state: &str) {
+    if let Some(bus) = BUS.get() {
+        let signal = Message {
+            kind: SIGNAL,
+            path: Some(PATH.to_owned()),
+            interface: Some(NAME.to_owned()),
+            member: Some("LayoutChanged".to_owned()),
+            args: vec![Arg::Str(state.to_owned())],
+            ..Message::default()
+        };
+        let _ = bus.send(&signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::scheme::Scheme;
+
+    /// Handles a call of `member` with `args`, answering the command it is turned into.
+    fn call(member: &str, args: Vec<Arg>) -> (Option<Command>, Message) {
+        let (inputs, received) = mpsc::channel();
+        let answering = thread::spawn(move || match received.recv() {
+            Ok(Input::Control(command, reply)) => {
+                let _ = reply.send(json!({ "success": true }));
+                Some(command)
+            }
+            _ => None,
+        });
+        let call = Message {
+            serial: 7,
+            sender: Some(":1.42".to_owned()),
+            args,
+            ..Message::method_call(NAME, PATH, NAME, member)
+        };
+        let reply = handle_call(&call, &inputs);
+        drop(inputs);
+        (answering.join().unwrap(), reply)
+    }
+
+    #[test]
+    fn encodes_the_header_of_messages() {
+        let signal = Message {
+            kind: SIGNAL,
+            path: Some(PATH.to_owned()),
+            member: Some("LayoutChanged".to_owned()),
+            args: vec![Arg::Str("vertical".to_owned())],
+            ..Message::default()
+        };
+        let bytes = signal.encode(3);
+
+        assert_eq!(bytes[..4], [b'l', SIGNAL, 0, 1]);
+        // The body is the string's length, its bytes and a nul.
+        assert_eq!(bytes[4..8], 13u32.to_le_bytes());
+        assert_eq!(bytes[8..12], 3u32.to_le_bytes());
+        let fields_len = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+        let body_start = (16 + fields_len as usize).next_multiple_of(8);
+        assert_eq!(bytes.len(), body_start + 13);
+        assert_eq!(bytes[body_start + 4..body_start + 12], *b"vertical");
+    }
+
+    #[test]
+    fn decodes_encoded_messages() {
+        let call = Message {
+            sender: Some(":1.42".to_owned()),
+            args: vec![Arg::Str(NAME.to_owned()), Arg::U32(4)],
+            ..Message::method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "Hi")
+        };
+        let decoded = Message::decode(&call.encode(9)).unwrap();
+
+        assert_eq!(decoded.kind, METHOD_CALL);
+        assert_eq!(decoded.serial, 9);
+        assert_eq!(decoded.path.as_deref(), Some("/"));
+        assert_eq!(decoded.interface.as_deref(), Some("org.freedesktop.DBus"));
+        assert_eq!(decoded.member.as_deref(), Some("Hi"));
+        assert_eq!(decoded.destination.as_deref(), Some("org.freedesktop.DBus"));
+        assert_eq!(decoded.sender.as_deref(), Some(":1.42"));
+        assert_eq!(decoded.args, call.args);
+
+        let error = Message::error(&decoded, "org.example.Error", "no");
+        let decoded = Message::decode(&error.encode(10)).unwrap();
+        assert_eq!(decoded.kind, ERROR);
+        assert_eq!(decoded.reply_serial, Some(9));
+        assert_eq!(decoded.destination.as_deref(), Some(":1.42"));
+        assert_eq!(decoded.error_name.as_deref(), Some("org.example.Error"));
+        assert_eq!(decoded.args, [Arg::Str("no".to_owned())]);
+    }
+
+    #[test]
+    fn reads_messages_from_the_stream() {
+        let (mut writer, mut reader) = UnixStream::pair().unwrap();
+        let signal = Message {
+            kind: SIGNAL,
+            member: Some("LayoutChanged".to_owned()),
+            args: vec![Arg::Str("paused".to_owned())],
+            ..Message::default()
+        };
+        writer.write_all(&signal.encode(1)).unwrap();
+        writer.write_all(&signal.encode(2)).unwrap();
+
+        assert_eq!(read_message(&mut reader).unwrap().serial, 1);
+        let second = read_message(&mut reader).unwrap();
+        assert_eq!(second.serial, 2);
+        assert_eq!(second.args, [Arg::Str("paused".to_owned())]);
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let call = Message {
+            args: vec![Arg::Str("spiral".to_owned())],
+            ..Message::method_call(NAME, PATH, NAME, "SetScheme")
+        };
+        let bytes = call.encode(1);
+
+        assert!(Message::decode(&bytes[..bytes.len() - 3]).is_none());
+    }
+
+    #[test]
+    fn turns_method_calls_into_commands() {
+        let (command, reply) = call("Pause", vec![]);
+        assert_eq!(command, Some(Command::Pause(None)));
+        assert_eq!(reply.kind, METHOD_RETURN);
+        assert_eq!(reply.reply_serial, Some(7));
+        assert_eq!(reply.destination.as_deref(), Some(":1.42"));
+
+        let (command, _) = call("Resume", vec![]);
+        assert_eq!(command, Some(Command::Resume(None)));
+
+        let (command, reply) = call("SetScheme", vec![Arg::Str("spiral".to_owned())]);
+        assert_eq!(command, Some(Command::Scheme(Some(Scheme::Spiral), None)));
+        assert_eq!(reply.kind, METHOD_RETURN);
+    }
+
+    #[test]
+    fn rejects_invalid_method_calls() {
+        let (command, reply) = call("SetScheme", vec![]);
+        assert_eq!(command, None);
+        assert_eq!(
+            reply.error_name.as_deref(),
+            Some("org.freedesktop.DBus.Error.InvalidArgs")
+        );
+
+        let (command, reply) = call("Explode", vec![]);
+        assert_eq!(command, None);
+        assert_eq!(
+            reply.error_name.as_deref(),
+            Some("org.freedesktop.DBus.Error.UnknownMethod")
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Coalescing bursts of window events, e.g. from restoring a session, so the layout is decided
+//! once for the last of them instead of once for each.
+
+use std::time::Instant;
+
+use crate::ipc::event::{WindowChange, WindowEventInfo};
+
+/// The window event held back to see if more follow, and when to stop waiting for them.
+#[derive(Debug, Default)]
+pub struct Debounce {
+    pending: Option<(WindowEventInfo, Instant)>,
+}
+
+impl Debounce {
+    /// Holds back `e` in place of the event held back before. It counts as a new window if any
+    /// event of the burst did, or else as a closed one. The first event of a burst sets the
+    /// `deadline`, so a steady stream of events can't hold them back forever.
+    pub fn hold(&mut self, e: WindowEventInfo, deadline: Instant) {
+        let held = match self.pending.take() {
+            Some((held, deadline))
+                if held.change == WindowChange::New
+                    || held.change == WindowChange::Close && e.change != WindowChange::New =>
+            {
+                (
+                    WindowEventInfo {
+                        change: held.change,
+                        ..e
+                    },
+                    deadline,
+                )
+            }
+            Some((_, deadline)) => (e, deadline),
+            None => (e, deadline),
+        };
+        self.pending = Some(held);
+    }
+
+    /// When the held back event is to be handled.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|&(_, deadline)| deadline)
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.deadline().is_some_and(|deadline| deadline <= now)
+    }
+
+    /// Takes the held back event, to handle it now.
+    pub fn take(&mut self) -> Option<WindowEventInfo> {
+        self.pending.take().map(|(e, _)| e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ipc::reply::Node;
+
+    fn event(change: WindowChange, id: i64) -> WindowEventInfo {
+        WindowEventInfo {
+            change,
+            container: Box::new(Node {
+                id,
+                ..Node::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn hands_on_a_burst_as_one_event() {
+        let start = Instant::now();
+        let mut debounce = Debounce::default();
+        debounce.hold(
+            event(WindowChange::New, 100),
+            start + Duration::from_millis(50),
+        );
+        debounce.hold(
+            event(WindowChange::Focus, 101),
+            start + Duration::from_millis(60),
+        );
+        debounce.hold(
+            event(WindowChange::Title, 102),
+            start + Duration::from_millis(70),
+        );
+
+        assert!(!debounce.is_due(start));
+        assert!(debounce.is_due(start + Duration::from_millis(50)));
+        let e = debounce.take().unwrap();
+        assert_eq!((e.change, e.container.id), (WindowChange::New, 102));
+        assert!(debounce.take().is_none());
+        assert!(!debounce.is_due(start + Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn counts_a_burst_as_closing_unless_a_window_opened() {
+        let deadline = Instant::now();
+        let mut debounce = Debounce::default();
+        debounce.hold(event(WindowChange::Close, 100), deadline);
+        debounce.hold(event(WindowChange::Focus, 101), deadline);
+        assert_eq!(debounce.take().unwrap().change, WindowChange::Close);
+
+        debounce.hold(event(WindowChange::Close, 100), deadline);
+        debounce.hold(event(WindowChange::New, 101), deadline);
+        assert_eq!(debounce.take().unwrap().change, WindowChange::New);
+
+        debounce.hold(event(WindowChange::Focus, 100), deadline);
+        debounce.hold(event(WindowChange::Move, 101), deadline);
+        assert_eq!(debounce.take().unwrap().change, WindowChange::Move);
+    }
+}
@@ -0,0 +1,94 @@
+//! Settings of the compositor's own config that the daemon adapts to, read over IPC when it
+//! starts and when its config is reloaded.
+//!
+//! i3's `default_orientation` decides how windows directly on a workspace are split unless
+//! `orientation` is configured, so the first split of a workspace matches what i3 would do. The
+//! tabbed or stacked containers `workspace_layout` makes are left alone either way, as are the
+//! sizes of gaps, which i3 takes out of the sizes it reports.
+
+use log::{info, warn};
+
+use crate::{
+    config::{Config, Orientation},
+    ipc::WmConnection,
+};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Defaults {
+    /// `default`, `stacking` or `tabbed`.
+    pub workspace_layout: Option<String>,
+    pub default_orientation: Option<Orientation>,
+    /// The inner gaps, in pixels.
+    pub gaps: Option<u32>,
+}
+
+impl Defaults {
+    /// Reads the config of the compositor behind `wm`, logging what was found.
+    pub fn detect(wm: &mut impl WmConnection) -> Self {
+        match wm.get_version() {
+            Ok(version) => info!(
+                "connected to {} with the config {}",
+                version.human_readable,
+                version
+                    .loaded_config_file_name
+                    .as_deref()
+                    .unwrap_or("unknown")
+            ),
+            Err(e) => warn!("couldn't get the version: {e}"),
+        }
+        let defaults = match wm.get_config() {
+            Ok(config) => Defaults::parse(&config),
+            Err(e) => {
+                warn!("couldn't get the config: {e}");
+                return Defaults::default();
+            }
+        };
+
+        if let Some(layout) = &defaults.workspace_layout {
+            info!("workspace_layout is {layout}");
+        }
+        if let Some(orientation) = defaults.default_orientation {
+            info!("default_orientation is {orientation:?}");
+        }
+        if let Some(gaps) = defaults.gaps {
+            info!("inner gaps are {gaps} pixels");
+        }
+        defaults
+    }
+
+    /// Takes the `workspace_layout`, `default_orientation` and `gaps inner` settings from
+    /// `config`. The last of each wins, as with i3.
+    pub fn parse(config: &str) -> Self {
+        let mut defaults = Defaults::default();
+        for line in config.lines() {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("workspace_layout"), Some(layout), _) => {
+                    defaults.workspace_layout = Some(layout.to_owned())
+                }
+                (Some("default_orientation"), Some(orientation), _) => {
+                    defaults.default_orientation = match orientation {
+                        "horizontal" => Some(Orientation::Horizontal),
+                        "vertical" => Some(Orientation::Vertical),
+                        "auto" => Some(Orientation::Auto),
+                        _ => defaults.default_orientation,
+                    }
+                }
+                (Some("gaps"), Some("inner"), Some(size)) => {
+                    if let Ok(size) = size.trim_end_matches("px").parse() {
+                        defaults.gaps = Some(size);
+                    }
+                }
+                _ => {}
+            }
+        }
+        defaults
+    }
+
+    /// Fills in what `config` leaves to the compositor.
+    pub fn apply(&self, config: &mut Config) {
+        if config.orientation.is_none() {
+            config.orientation = self.default_orientation;
+        }
+    }
+}
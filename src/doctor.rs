@@ -0,0 +1,195 @@
+//! The `doctor` subcommand, which checks the setup around the daemon and prints what to fix.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    config::Config,
+    control::{self, Command},
+    ipc::{reply::Version, I3Connection, WmConnection},
+    output::OutputMode,
+};
+
+/// i3 4.15 added the tick events and `GET_CONFIG`, the newest of what the daemon uses.
+const MIN_I3_VERSION: (u32, u32) = (4, 15);
+
+/// Other daemons that split windows automatically and would fight with this one.
+const AUTOTILERS: &[&str] = &[
+    "autotiling",
+    "autotiling-rs",
+    "i3-autolayout",
+    "i3-layouts",
+    "i3-auto-layout",
+    "persway",
+    "swaymonad",
+];
+
+#[derive(Debug)]
+enum Finding {
+    Ok(String),
+    Warning(String),
+    Error(String),
+}
+
+/// Runs all checks against the compositor at `socket`, or the detected one, printing the
+/// findings. Returns whether nothing is broken.
+pub fn run(config: &Config, socket: Option<&Path>) -> bool {
+    let mut findings = Vec::new();
+    let connection = match socket {
+        Some(path) => I3Connection::connect_to(path),
+        None => I3Connection::connect(),
+    };
+    match connection {
+        Ok(mut i3) => {
+            findings.push(Finding::Ok("i3 is reachable".to_owned()));
+            match i3.get_version() {
+                Ok(version) => findings.push(check_version(&version)),
+                Err(e) => findings.push(Finding::Error(format!("couldn't get the version: {e}"))),
+            }
+            match i3.get_config() {
+                Ok(i3_config) => findings.extend(check_i3_config(config, &i3_config)),
+                Err(e) => findings.push(Finding::Warning(format!("couldn't get the config: {e}"))),
+            }
+        }
+        Err(e) => findings.push(Finding::Error(format!(
+            "can't connect to i3: {e}; is it running, and is I3SOCK or SWAYSOCK right?"
+        ))),
+    }
+    findings.extend(check_autotilers());
+
+    for finding in &findings {
+        match finding {
+            Finding::Ok(message) => println!("ok: {message}"),
+            Finding::Warning(message) => println!("warning: {message}"),
+            Finding::Error(message) => println!("error: {message}"),
+        }
+    }
+    !findings.iter().any(|f| matches!(f, Finding::Error(_)))
+}
+
+fn check_version(version: &Version) -> Finding {
+    let name = &version.human_readable;
+    if version.variant.as_deref() == Some("sway") {
+        return Finding::Ok(format!("running on sway {name}"));
+    }
+    if (version.major, version.minor) < MIN_I3_VERSION {
+        let (major, minor) = MIN_I3_VERSION;
+        return Finding::Error(format!(
+            "i3 {name} is too old, the daemon needs {major}.{minor} or newer"
+        ));
+    }
+    Finding::Ok(format!("running on i3 {name}"))
+}
+
+/// Checks the bar setup and the bindings sending commands to the daemon.
+fn check_i3_config(config: &Config, i3_config: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let lines: Vec<_> = i3_config
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+
+    let bar = match &config.output {
+        OutputMode::Plain | OutputMode::I3bar => lines
+            .iter()
+            .any(|line| line.starts_with("status_command") && line.contains(env!("CARGO_PKG_NAME")))
+            .then(|| "a status_command runs the daemon".to_owned())
+            .ok_or("no status_command runs the daemon, so it only shows up in bars that run it"),
+        OutputMode::Waybar => ["waybar/config", "waybar/config.jsonc"]
+            .iter()
+            .find(|file| read_config(file).is_some_and(|c| c.contains(env!("CARGO_PKG_NAME"))))
+            .map(|file| format!("{file} runs the daemon"))
+            .ok_or("no waybar module runs the daemon"),
+        OutputMode::Polybar => {
+            let module = format!("[module/{}]", config.polybar_module);
+            read_config("polybar/config.ini")
+                .filter(|c| c.contains(&module))
+                .map(|_| format!("polybar has {module}"))
+                .ok_or("polybar has no module for the daemon, see polybar_module")
+        }
+        OutputMode::Json => Ok("the status is written as JSON, for scripts".to_owned()),
+        OutputMode::Fifo(path) => Ok(format!("the status is written to {}", path.display())),
+        OutputMode::Rootname => Ok("the status is set as the name of the root window".to_owned()),
+    };
+    findings.push(match bar {
+        Ok(message) => Finding::Ok(message),
+        Err(message) => Finding::Warning(message.to_owned()),
+    });
+
+    let mut bindings = 0;
+    for line in &lines {
+        if !line.starts_with("bindsym") && !line.starts_with("bindcode") {
+            continue;
+        }
+        for (start, _) in line.match_indices(control::PREFIX) {
+            let message = line[start..]
+                .split(['"', ';', ','])
+                .next()
+                .unwrap_or_default();
+            bindings += 1;
+            if let Some(Err(e)) = Command::from_message(message) {
+                findings.push(Finding::Error(format!(
+                    "'{line}' sends an invalid command: {e}"
+                )));
+            }
+        }
+    }
+    if bindings > 0 {
+        findings.push(Finding::Ok(format!("{bindings} bindings send commands")));
+    }
+    findings
+}
+
+/// The contents of `file` in `$XDG_CONFIG_HOME`.
+fn read_config(file: &str) -> Option<String> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    fs::read_to_string(config_home.join(file)).ok()
+}
+
+fn check_autotilers() -> Vec<Finding> {
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return vec![Finding::Warning(
+            "couldn't look for other autotiling daemons".to_owned(),
+        )];
+    };
+    let own = process::id().to_string();
+    let mut findings = Vec::new();
+    for process in processes.flatten() {
+        let pid = process.file_name();
+        if pid
+            .to_str()
+            .is_none_or(|pid| pid == own || !pid.bytes().all(|b| b.is_ascii_digit()))
+        {
+            continue;
+        }
+        let Ok(cmdline) = fs::read(process.path().join("cmdline")) else {
+            continue;
+        };
+        // The program, or the script run by an interpreter.
+        let found = cmdline.split(|&b| b == 0).take(2).find_map(|arg| {
+            let name = Path::new(std::str::from_utf8(arg).ok()?)
+                .file_name()?
+                .to_str()?;
+            AUTOTILERS.contains(&name).then(|| name.to_owned())
+        });
+        if let Some(name) = found {
+            findings.push(Finding::Error(format!(
+                "{name} is running as process {}, it splits windows as well",
+                pid.to_string_lossy()
+            )));
+        }
+    }
+    if findings.is_empty() {
+        findings.push(Finding::Ok(
+            "no other autotiling daemon is running".to_owned(),
+        ));
+    }
+    findings
+}
@@ -0,0 +1,87 @@
+//! The `dump-tree` subcommand, which prints the layout tree with what the daemon would decide
+//! for each window if it got focus, e.g. to find out why a window was split the way it was.
+
+use std::{fmt::Write, path::Path};
+
+use crate::{
+    config::Config,
+    defaults::Defaults,
+    engine::LayoutEngine,
+    ipc::{
+        reply::{Node, NodeType},
+        Backend,
+    },
+    state::Overrides,
+};
+
+/// Prints the tree of the compositor at `socket`, or the detected one. Returns whether it could
+/// be fetched.
+pub fn run<B: Backend>(mut config: Config, socket: Option<&Path>) -> bool {
+    let tree = B::connect(socket).and_then(|mut i3| {
+        Defaults::detect(&mut i3).apply(&mut config);
+        i3.get_tree()
+    });
+    let tree = match tree {
+        Ok(tree) => tree,
+        Err(e) => {
+            eprintln!("Error: couldn't get the tree: {e}");
+            return false;
+        }
+    };
+
+    // The overrides of the running daemon decide as well, e.g. a paused workspace.
+    let mut engine = LayoutEngine::new(config);
+    if let Some(Ok(overrides)) = Overrides::default_path().as_deref().map(Overrides::load) {
+        engine.state_mut().set_overrides(overrides);
+    }
+    print!("{}", dump(&engine, &tree));
+    true
+}
+
+/// One line per node, indented by its depth, with the decision for each window.
+fn dump(engine: &LayoutEngine, tree: &Node) -> String {
+    let mut dump = String::new();
+    let mut stack = vec![(tree, 0)];
+    while let Some((node, depth)) = stack.pop() {
+        let _ = write!(
+            dump,
+            "{:indent$}{} {:?} {:?}",
+            "",
+            node.id,
+            node.nodetype,
+            node.layout,
+            indent = depth * 2
+        );
+        let class = node
+            .window_properties
+            .as_ref()
+            .and_then(|properties| properties.class.as_deref())
+            .or(node.app_id.as_deref());
+        if let Some(class) = class {
+            let _ = write!(dump, " {class}");
+        }
+        if let Some(name) = &node.name {
+            let _ = write!(dump, " {name:?}");
+        }
+        if !node.marks.is_empty() {
+            let _ = write!(dump, " [{}]", node.marks.join(", "));
+        }
+        if node.nodes.is_empty() && matches!(node.nodetype, NodeType::Con | NodeType::FloatingCon) {
+            match engine.explain(tree, node.id) {
+                Some(decision) => {
+                    let _ = write!(dump, " -> {decision}");
+                }
+                None => dump.push_str(" -> nothing"),
+            }
+        }
+        dump.push('\n');
+        stack.extend(
+            node.floating_nodes
+                .iter()
+                .rev()
+                .chain(node.nodes.iter().rev())
+                .map(|child| (child, depth + 1)),
+        );
+    }
+    dump
+}
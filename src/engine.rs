@@ -0,0 +1,2964 @@
+//! Decides how to lay out windows, without talking to i3 itself: the engine is fed events and
+//! the layout tree and answers with the commands to carry out.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, error, info, trace, warn};
+use serde_json::{json, Value};
+
+use crate::{
+    config::Config,
+    control, dbus, hooks,
+    ipc::{
+        event::{BindingEventInfo, Event, ModeEventInfo, WindowChange, WindowEventInfo},
+        reply::{Node, NodeLayout, NodeType, ScratchpadState},
+        WmConnection,
+    },
+    layout::Layout,
+    logger, output, record,
+    scheme::{self, Scheme},
+    state::{State, Undo},
+    I3Split,
+};
+
+/// Logs why a decision was made, at the info level if the config asks for explanations.
+macro_rules! explain {
+    ($config:expr, $($arg:tt)+) => {
+        log::log!(
+            if $config.explain { log::Level::Info } else { log::Level::Debug },
+            $($arg)+
+        )
+    };
+}
+
+/// The hidden workspace holding the scratchpad windows.
+const SCRATCHPAD: &str = "__i3_scratch";
+/// Marks the window a new one is moved next to, as i3 can only move windows to marks.
+const MARK: &str = "_alternating_layout";
+/// Marks containers the daemon keeps out of, leaving everything in them to the user.
+pub const OPT_OUT_MARK: &str = "_noalt";
+/// Marks containers laid out by the daemon, followed by their id as marks are unique.
+pub const MANAGED_MARK: &str = "_alt_managed_";
+
+/// Something the engine wants done, in the order it is returned.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Run an i3 command. The commands after it are skipped if it fails.
+    Run(String),
+    /// The focused workspace, for the placeholders of the statuses that follow. `windows` counts
+    /// the tiled windows on it and `container_windows` those in the focused container.
+    Workspace {
+        name: String,
+        windows: usize,
+        container_windows: usize,
+        scheme: Scheme,
+    },
+    /// The scheme of the focused workspace changed, for the placeholders.
+    Scheme(Scheme),
+    Status(Status),
+    /// Container `container` was split or its layout changed to `split`, for the layout hook.
+    /// Only used if there is one.
+    LayoutChanged {
+        container: i64,
+        split: I3Split,
+    },
+    /// Fetch the tree again and pass it to `LayoutEngine::relayout`, as the commands before
+    /// changed it.
+    Relayout,
+    /// Show a desktop notification. Only used if they are enabled.
+    Notify(String),
+}
+
+/// What the status shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Split(I3Split),
+    /// The split of a window that is left alone, e.g. because of a rule.
+    Ignored(I3Split),
+    Paused,
+    /// A floating window is focused.
+    Floating,
+    /// Nothing is focused.
+    Empty,
+}
+
+impl Status {
+    /// The name of the state shown, as the hooks and waybar get it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Split(split) | Status::Ignored(split) => split.name(),
+            Status::Paused => "paused",
+            Status::Floating => "floating",
+            Status::Empty => "none",
+        }
+    }
+}
+
+/// The decision logic of the daemon and the state it keeps between events.
+pub struct LayoutEngine {
+    config: Config,
+    state: State,
+    /// The status the status hook was last run for.
+    hooked_status: Option<Status>,
+}
+
+impl LayoutEngine {
+    pub fn new(config: Config) -> Self {
+        LayoutEngine {
+            config,
+            state: State::default(),
+            hooked_status: None,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    /// Replaces the config, e.g. after reloading it, keeping the state.
+    pub fn set_config(&mut self, config: Config) -> Vec<Command> {
+        self.config = config;
+        let mut handler = self.handler();
+        handler.show_status(handler.state.status(None));
+        handler.commands
+    }
+
+    /// Decides what to do about `event`. The tree is only fetched from `wm` if the event can't
+    /// be decided without it.
+    pub fn handle_event(&mut self, wm: &mut impl WmConnection, event: &Event) -> Vec<Command> {
+        self.state.last_event = Some(SystemTime::now());
+        self.state.stats.count_event(event);
+        record::event(event);
+        let mut handler = self.handler();
+        match event {
+            // Workspaces may have moved to other outputs, with other settings.
+            Event::Output(_) => handler.set_layout(None, wm),
+            Event::Mode(e) => handler.handle_mode(e, wm),
+            Event::Window(e) if handler.config.events.contains(&e.change) => {
+                handler.set_layout(Some(e), wm)
+            }
+            Event::Window(e) => {
+                trace!("ignoring {:?} event", e.change);
+                Some(())
+            }
+            Event::Binding(e) => handler.handle_keybind(e, wm),
+            Event::Tick(e) => handler.handle_message(&e.payload, wm),
+            Event::Shutdown(_) => Some(()),
+        };
+        handler.commands
+    }
+
+    /// Runs the commands configured for clicking the status with `button`, and handles them like
+    /// those of a binding. i3 doesn't know to run them, so each one is run right away for the
+    /// decisions after it to see the tree it leaves behind.
+    pub fn handle_click(&mut self, wm: &mut impl WmConnection, button: u32) -> Vec<Command> {
+        let Some(commands) = self.config.clicks.get(button).map(str::to_owned) else {
+            debug!("nothing to do for button {button}");
+            return Vec::new();
+        };
+        let mut decided = Vec::new();
+        for command in split_commands(&commands) {
+            if !command.starts_with("nop") {
+                // What was decided for the commands before goes first, and a failed command
+                // skips the rest as with `execute`.
+                decided.push(Command::Run(command.to_owned()));
+                if self.execute(wm, std::mem::take(&mut decided)).is_none() {
+                    return Vec::new();
+                }
+            }
+            let mut handler = self.handler();
+            handler.handle_binding_command(command, wm);
+            decided.append(&mut handler.commands);
+        }
+        decided
+    }
+
+    /// Puts the containers on managed workspaces that are marked as laid out by the daemon back
+    /// to i3's default layout, and unmarks them.
+    pub fn restore_layouts(&mut self, wm: &mut impl WmConnection) -> Vec<Command> {
+        let mut handler = self.handler();
+        let Some(tree) = get_tree(wm) else {
+            return handler.commands;
+        };
+        let workspaces = tree.descendants().filter(|n| {
+            n.nodetype == NodeType::Workspace
+                && n.name
+                    .as_deref()
+                    .is_some_and(|name| handler.config.is_managed(name))
+        });
+        for container in workspaces.flat_map(Node::descendants) {
+            let mark = container
+                .marks
+                .iter()
+                .find(|mark| mark.starts_with(MANAGED_MARK));
+            if let Some(mark) = mark {
+                handler.run(&format!(
+                    "[con_id={}] layout default, unmark {mark}",
+                    container.id
+                ));
+            }
+        }
+        handler.commands
+    }
+
+    /// Decides the split for the focused window afresh, see `Command::Relayout`.
+    pub fn relayout(&mut self, wm: &mut impl WmConnection) -> Vec<Command> {
+        let mut handler = self.handler();
+        handler.set_layout(None, wm);
+        handler.commands
+    }
+
+    /// What would be decided if `window` in `tree` got focus, e.g. `horizontal` or `ignore: a
+    /// rule matches the window`, without changing the state.
+    pub fn explain(&self, tree: &Node, window: i64) -> Option<Decision> {
+        let mut tree = tree.clone();
+        refocus(&mut tree, window);
+        let mut state = State::default();
+        state.set_overrides(self.state.overrides());
+        state.mode.clone_from(&self.state.mode);
+        for (workspace, master) in self.state.masters() {
+            state.set_master(workspace, Some(master));
+        }
+        let mut handler = Handler {
+            config: &self.config,
+            state: &mut state,
+            commands: Vec::new(),
+            decision: None,
+        };
+
+        handler.set_layout_of(None, &tree)?;
+        handler.decision
+    }
+
+    /// Runs a control command, returning the response for the control socket.
+    pub fn handle_control(
+        &mut self,
+        wm: &mut impl WmConnection,
+        command: control::Command,
+    ) -> (Value, Vec<Command>) {
+        let mut handler = self.handler();
+        let response = handler.handle_command(command, wm);
+        (response, handler.commands)
+    }
+
+    /// Carries out `commands`, stopping at the first i3 command that fails.
+    pub fn execute(&mut self, wm: &mut impl WmConnection, commands: Vec<Command>) -> Option<()> {
+        let config = &self.config;
+        let mut relayout = false;
+        for command in commands {
+            match command {
+                Command::Run(command) => {
+                    self.state.stats.commands += 1;
+                    if run_command(wm, config, &command).is_none() {
+                        self.state.stats.failed_commands += 1;
+                        return None;
+                    }
+                }
+                Command::Workspace {
+                    name,
+                    windows,
+                    container_windows,
+                    scheme,
+                } => output::set_workspace(&name, windows, container_windows, scheme),
+                Command::Scheme(scheme) => output::set_scheme(scheme),
+                Command::Status(status) => {
+                    config.output.print_status(config, &status);
+                    if let Some(hook) = &config.hooks.status {
+                        if self.hooked_status.replace(status) != Some(status) {
+                            run_hook(config, hook, "status", status.name(), &self.state, None);
+                        }
+                    }
+                }
+                Command::LayoutChanged { container, split } => {
+                    if let Some(hook) = &config.hooks.layout {
+                        run_hook(
+                            config,
+                            hook,
+                            "layout",
+                            split.name(),
+                            &self.state,
+                            Some(container),
+                        );
+                    }
+                }
+                Command::Relayout => relayout = true,
+                Command::Notify(body) if config.dry_run => info!("would notify: {body}"),
+                Command::Notify(body) => dbus::notify("Alternating layout", &body),
+            }
+        }
+
+        if relayout {
+            let commands = self.relayout(wm);
+            self.execute(wm, commands)?;
+        }
+
+        Some(())
+    }
+
+    fn handler(&mut self) -> Handler<'_> {
+        Handler {
+            config: &self.config,
+            state: &mut self.state,
+            commands: Vec::new(),
+            decision: None,
+        }
+    }
+}
+
+/// Handles a single input, collecting the commands.
+struct Handler<'a> {
+    config: &'a Config,
+    state: &'a mut State,
+    commands: Vec<Command>,
+    /// What was decided for the focused window, if anything.
+    decision: Option<Decision>,
+}
+
+/// What was decided for the focused window, for the log and `dump-tree`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// The split or what was done instead, e.g. `horizontal`, `tabbed` or `ignore`.
+    pub split: String,
+    /// Why the window was left alone, if it was.
+    pub reason: Option<&'static str>,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason {
+            Some(reason) => write!(f, "{}: {reason}", self.split),
+            None => write!(f, "{}", self.split),
+        }
+    }
+}
+
+impl Handler<'_> {
+    /// Records what was decided, and adds it to the span of the input.
+    fn decide(&mut self, split: impl Into<String>, reason: Option<&'static str>) {
+        let decision = Decision {
+            split: split.into(),
+            reason,
+        };
+        logger::record("decision", &decision.split);
+        if let Some(reason) = reason {
+            logger::record("reason", reason);
+        }
+        self.decision = Some(decision);
+    }
+
+    fn set_layout<C: WmConnection>(
+        &mut self,
+        event: Option<&WindowEventInfo>,
+        wm: &mut C,
+    ) -> Option<()> {
+        let config = self.config;
+        if let Some(e) = event {
+            if let Some(split) = self.split_from_event(e) {
+                self.state.focus_container(e.container.id);
+                self.decide(split.name(), None);
+                let (_, _, width, height) = e.container.rect;
+                explain!(
+                    config,
+                    "container {} fills its {width}x{height} parent: {split:?}",
+                    e.container.id
+                );
+                self.run(split_command(split));
+                self.mark_parent(e.container.id);
+                // Splitting a lone window changes the layout of its parent.
+                let previous = self.state.previous_split();
+                if previous != split {
+                    self.state.record(Undo::Run(format!(
+                        "[con_id={}] {}",
+                        e.container.id,
+                        split_command(previous)
+                    )));
+                    self.layout_changed(e.container.id, split);
+                }
+                self.print_status(split);
+                return Some(());
+            }
+        }
+
+        self.set_layout_of(event, &get_tree(wm)?)
+    }
+
+    /// Decides the split of the focused window in `tree`, which `event` led to.
+    fn set_layout_of(&mut self, event: Option<&WindowEventInfo>, tree: &Node) -> Option<()> {
+        let config = self.config;
+        let path = focused_path(tree);
+        self.state.opted_out = tree.descendants().any(is_opted_out);
+        self.mark_new_containers(tree);
+
+        let name_of = |nodetype: NodeType| {
+            path.iter()
+                .rfind(|n| n.nodetype == nodetype)
+                .and_then(|n| n.name.as_deref())
+        };
+        // How many containers deep below its workspace the focused node sits.
+        let depth = path
+            .iter()
+            .rposition(|n| n.nodetype == NodeType::Workspace)
+            .map_or(0, |ws| path.len() - 1 - ws);
+        let workspace_node = path
+            .iter()
+            .rfind(|n| n.nodetype == NodeType::Workspace)
+            .copied();
+        match path.iter().rev().nth(1) {
+            Some(parent) => {
+                let workspace = name_of(NodeType::Workspace);
+                let output = name_of(NodeType::Output);
+                let focused = path.last()?;
+                let ids: HashSet<_> = tree.descendants().map(|n| n.id).collect();
+                self.state.focus(workspace, Some(focused.id), &ids);
+                if event.is_some_and(|e| e.change == WindowChange::New) {
+                    self.state.forget_manual_splits();
+                }
+                if let Some(workspace) = workspace {
+                    logger::record("workspace", workspace);
+                }
+                self.commands.push(Command::Workspace {
+                    name: workspace.unwrap_or_default().to_owned(),
+                    windows: workspace_node.map_or(0, count_windows),
+                    container_windows: count_windows(parent),
+                    scheme: self
+                        .state
+                        .scheme(workspace)
+                        .unwrap_or_else(|| config.scheme(workspace)),
+                });
+                let restructure = event.is_some()
+                    && workspace.is_some_and(|name| config.is_managed(name))
+                    && !self.state.is_paused(workspace);
+                if let Some(ws) = workspace_node.filter(|ws| restructure && !is_opted_out(ws)) {
+                    if let Some(name) = config.layout(workspace).filter(|_| {
+                        event.is_some_and(|e| e.change == WindowChange::New)
+                            && count_windows(ws) == 1
+                    }) {
+                        match Layout::load(name).and_then(|layout| layout.apply(ws)) {
+                            Ok(commands) => {
+                                self.decide("restore", None);
+                                explain!(config, "restoring layout '{name}' for the first window");
+                                self.run(&commands.join("; "));
+                                // The window is in its placeholder now, so decide afresh.
+                                self.commands.push(Command::Relayout);
+                                return Some(());
+                            }
+                            Err(e) => warn!("couldn't restore layout '{name}': {e}"),
+                        }
+                    }
+                    let mut moves = Vec::new();
+                    if config.flatten && event.is_some_and(|e| e.change == WindowChange::Close) {
+                        moves = flatten(ws, focused.id, config.mark);
+                    }
+                    if let Some(max_depth) = config.max_depth.filter(|_| moves.is_empty()) {
+                        moves = move_up(ws, max_depth as usize);
+                    }
+                    if !moves.is_empty() {
+                        self.decide("restructure", None);
+                        explain!(
+                            config,
+                            "restructuring the workspace with {} moves",
+                            moves.len()
+                        );
+                        self.run(&moves.join("; "));
+                        // The windows moved, so decide the split afresh.
+                        self.commands.push(Command::Relayout);
+                        return Some(());
+                    }
+                }
+                let ignored = [
+                    (
+                        workspace.is_some_and(|name| !config.is_managed(name)),
+                        "the workspace isn't managed",
+                    ),
+                    (
+                        output.is_some_and(|name| config.is_ignored_output(name)),
+                        "the output is ignored",
+                    ),
+                    (
+                        config
+                            .rules
+                            .iter()
+                            .any(|rule| rule.layout.is_none() && rule.matches(focused)),
+                        "a rule matches the window",
+                    ),
+                    (
+                        // Moving a window to the scratchpad focuses the window below it, which
+                        // shouldn't change because of that.
+                        workspace == Some(SCRATCHPAD)
+                            || event.is_some_and(|e| {
+                                e.container.scratchpad_state != ScratchpadState::None
+                            }),
+                        "the event is about a scratchpad window",
+                    ),
+                    (
+                        // The split would only show once fullscreen ends, when it is unexpected.
+                        workspace_node
+                            .is_some_and(|ws| ws.descendants().any(|n| n.fullscreen_mode != 0)),
+                        "a window is fullscreen",
+                    ),
+                    (
+                        path.iter().any(|n| is_opted_out(n)),
+                        "it is in a container marked _noalt",
+                    ),
+                    (
+                        config.is_ignored_window_type(focused),
+                        "its window type is ignored",
+                    ),
+                    (
+                        config.limit.is_some_and(|limit| depth > limit as usize)
+                            || config.max_depth.is_some_and(|max| depth >= max as usize),
+                        "the window is nested too deeply",
+                    ),
+                    (
+                        self.state.is_split_manually(config.grace_period()),
+                        "it was split by hand",
+                    ),
+                    (
+                        config.pause_modes.contains(&self.state.mode),
+                        "the binding mode pauses splitting",
+                    ),
+                    (self.state.is_paused(workspace), "splitting is paused"),
+                ]
+                .into_iter()
+                .find_map(|(ignored, reason)| ignored.then_some(reason));
+
+                // Floating windows sit in the floating nodes of their workspace, directly on sway
+                // and wrapped in a floating container on i3.
+                let floating = path
+                    .windows(2)
+                    .any(|w| w[0].floating_nodes.iter().any(|n| n.id == w[1].id));
+
+                // Making a container with a lone window tabbed wraps the workspace's children in
+                // a new container, so the automatically tabbed container may be the workspace.
+                let grandparent = path
+                    .iter()
+                    .rev()
+                    .nth(2)
+                    .filter(|g| g.nodetype == NodeType::Workspace && g.nodes.len() == 1);
+                let auto_tabbed = [Some(*parent), grandparent.copied()]
+                    .into_iter()
+                    .flatten()
+                    .find(|n| self.state.is_auto_tabbed(n.id));
+                let windows = parent.nodes.len();
+                let layout_rule = config
+                    .rules
+                    .iter()
+                    .find(|rule| rule.layout.is_some() && rule.matches(focused))
+                    .filter(|_| ignored.is_none());
+
+                if floating {
+                    self.decide("ignore", Some("it is floating"));
+                    explain!(
+                        config,
+                        "not splitting container {}: it is floating",
+                        focused.id
+                    );
+                    self.commands
+                        .push(Command::Status(if self.state.is_paused(None) {
+                            Status::Paused
+                        } else {
+                            Status::Floating
+                        }));
+                } else if let Some((rule, layout)) =
+                    layout_rule.and_then(|rule| Some((rule, rule.layout?)))
+                {
+                    let is_new = event.is_some_and(|e| e.change == WindowChange::New);
+                    // Another window of the rule, already in a container with its layout.
+                    let neighbour = workspace_node.and_then(|ws| {
+                        ws.descendants()
+                            .filter(|n| n.layout == layout.node_layout())
+                            .flat_map(|n| &n.nodes)
+                            .find(|n| n.id != focused.id && n.nodes.is_empty() && rule.matches(n))
+                    });
+                    if parent.layout == layout.node_layout() {
+                        self.decide("ignore", Some("a rule placed it"));
+                        explain!(
+                            config,
+                            "not splitting container {}: a rule placed it",
+                            focused.id
+                        );
+                        self.print_status(layout.split())
+                    } else if !is_new {
+                        self.decide("ignore", Some("a rule places it"));
+                        explain!(
+                            config,
+                            "not splitting container {}: a rule places it",
+                            focused.id
+                        );
+                        self.print_status(layout_split(&parent.layout))
+                    } else if let Some(neighbour) = neighbour {
+                        self.decide("move to mark", None);
+                        explain!(
+                            config,
+                            "moving container {} next to container {}",
+                            focused.id,
+                            neighbour.id
+                        );
+                        self.run(&format!("[con_id={}] mark --add {MARK}", neighbour.id));
+                        self.run(&format!(
+                            "[con_id={}] move window to mark {MARK}",
+                            focused.id
+                        ));
+                        self.run(&format!("[con_id={}] unmark {MARK}", neighbour.id));
+                        self.print_status(layout.split())
+                    } else {
+                        self.decide(layout.command(), None);
+                        explain!(config, "wrapping container {} for a rule", focused.id);
+                        self.run("split vertical");
+                        self.run(layout.command());
+                        self.mark_parent(focused.id);
+                        self.layout_changed(focused.id, layout.split());
+                        self.print_status(layout.split())
+                    }
+                } else if ignored.is_none()
+                    && matches!(parent.layout, NodeLayout::SplitH | NodeLayout::SplitV)
+                    && config
+                        .tabbed_after
+                        .is_some_and(|limit| windows > limit as usize)
+                {
+                    self.decide("tabbed", None);
+                    explain!(
+                        config,
+                        "making container {} tabbed: it holds {windows} windows",
+                        parent.id
+                    );
+                    self.run("layout tabbed");
+                    self.record_layout(focused, parent);
+                    self.mark_managed(parent);
+                    self.layout_changed(parent.id, I3Split::Tabbed);
+                    self.state.set_auto_tabbed(parent.id, true);
+                    self.print_status(I3Split::Tabbed)
+                } else if let Some(tabbed) = auto_tabbed.filter(|_| {
+                    ignored.is_none()
+                        && parent.layout == NodeLayout::Tabbed
+                        && config
+                            .tabbed_after
+                            .is_none_or(|limit| windows <= limit as usize)
+                }) {
+                    let (width, height) = scheme::usable_size(parent, config.gaps);
+                    let (layout, split) =
+                        if f64::from(width) * config.ratio(workspace) > f64::from(height) {
+                            ("layout splith", I3Split::Horizontal)
+                        } else {
+                            ("layout splitv", I3Split::Vertical)
+                        };
+                    self.decide(layout, None);
+                    explain!(
+                        config,
+                        "splitting container {} again: it holds {windows} windows",
+                        parent.id
+                    );
+                    self.run(layout);
+                    self.record_layout(focused, parent);
+                    self.mark_managed(parent);
+                    self.layout_changed(parent.id, split);
+                    self.state.set_auto_tabbed(tabbed.id, false);
+                    // The windows are side by side now, so decide the split afresh.
+                    self.commands.push(Command::Relayout);
+                } else if matches!(parent.layout, NodeLayout::Tabbed | NodeLayout::Stacked) {
+                    self.decide("keep", Some("the container is tabbed or stacked"));
+                    explain!(
+                        config,
+                        "not splitting container {}: container {} is {:?}",
+                        focused.id,
+                        parent.id,
+                        parent.layout
+                    );
+                    self.print_status(if parent.layout == NodeLayout::Tabbed {
+                        I3Split::Tabbed
+                    } else {
+                        I3Split::Stacked
+                    })
+                } else if let Some(reason) = ignored {
+                    self.decide("ignore", Some(reason));
+                    explain!(config, "not splitting container {}: {reason}", focused.id);
+                    self.print_ignored(layout_split(&parent.layout))
+                } else {
+                    let scheme = self
+                        .state
+                        .scheme(workspace)
+                        .unwrap_or_else(|| config.scheme(workspace));
+                    let context = scheme::Context {
+                        focused,
+                        parent,
+                        workspace: workspace_node,
+                        depth,
+                        ratio: config.ratio(workspace),
+                        master: workspace.and_then(|name| self.state.master(name)),
+                        master_ratio: config.master_ratio,
+                        gaps: config.gaps,
+                    };
+                    // Kept, so the master stays in place as the windows before it close.
+                    if let Some(name) = workspace.filter(|_| scheme == Scheme::MasterStack) {
+                        self.state.set_master(name, context.master());
+                    }
+
+                    if event.is_some_and(|e| e.change == WindowChange::New) {
+                        if let Some(command) = scheme.placement(&context) {
+                            self.decide(&command, None);
+                            self.run(&command);
+                            // Placing the window changes the tree, so decide the split afresh.
+                            self.commands.push(Command::Relayout);
+                            return Some(());
+                        }
+                    }
+
+                    // The output decides for windows on the workspace itself, if configured to.
+                    let seeded = config
+                        .orientation(output)
+                        .filter(|_| parent.nodetype == NodeType::Workspace)
+                        .zip(path.iter().rfind(|n| n.nodetype == NodeType::Output))
+                        .map(|(orientation, output)| orientation.split(output));
+                    let split = seeded
+                        .or_else(|| scheme.split(&context))
+                        .and_then(|split| fit(config, split, focused));
+                    self.decide(split.as_ref().map_or("keep", |split| split.name()), None);
+                    let (width, height) = scheme::usable_size(parent, config.gaps);
+                    match seeded {
+                        Some(seeded) => explain!(
+                            config,
+                            "container {} on the workspace of output {}: {seeded:?}, \
+                             fitted to {split:?}",
+                            focused.id,
+                            output.unwrap_or("unknown")
+                        ),
+                        None => explain!(
+                            config,
+                            "{scheme:?} scheme, container {} at depth {depth} in {:?} container \
+                             {} of {width}x{height} with ratio {}: {split:?}",
+                            focused.id,
+                            parent.layout,
+                            parent.id,
+                            context.ratio
+                        ),
+                    }
+                    match split {
+                        Some(split) => {
+                            self.run(split_command(split));
+                            match parent.nodes.len() {
+                                // A lone window takes the split to its parent.
+                                1 if layout_split(&parent.layout) == split => {}
+                                1 => {
+                                    self.record_layout(focused, parent);
+                                    self.mark_managed(parent);
+                                    self.layout_changed(parent.id, split)
+                                }
+                                _ => {
+                                    self.state.record(Undo::Unwrap(focused.id));
+                                    self.mark_parent(focused.id);
+                                    self.layout_changed(focused.id, split)
+                                }
+                            }
+                            self.print_status(split)
+                        }
+                        None => self.print_status(layout_split(&parent.layout)),
+                    }
+                    if let Some(command) = golden_ratio(config, focused, parent) {
+                        self.run(&command);
+                        if let Some(undo) = resize_command(focused, parent) {
+                            self.state.record(Undo::Run(undo));
+                        }
+                    }
+                }
+            }
+            None => self.commands.push(Command::Status(Status::Empty)),
+        }
+
+        Some(())
+    }
+
+    /// Decides the split from the window an event is about, if the rest of the tree can't make
+    /// a difference. This saves fetching the tree for most focus events.
+    fn split_from_event(&self, e: &WindowEventInfo) -> Option<I3Split> {
+        let (config, state) = (self.config, &self.state);
+        let window = &*e.container;
+        let scheme = state.scheme(None).unwrap_or(config.scheme);
+        let (_, _, width, height) = window.rect;
+        let simple = matches!(e.change, WindowChange::New | WindowChange::Focus)
+            && config.is_uniform()
+            && state.is_uniform()
+            && !state.opted_out
+            && !state.is_paused(None)
+            && !config.pause_modes.contains(&state.mode)
+            // The other schemes depend on the depth and the neighbours of the window.
+            && scheme == Scheme::Alternating
+            && window.nodetype == NodeType::Con
+            && window.nodes.is_empty()
+            && !window.is_floating()
+            && window.fullscreen_mode == 0
+            && window.scratchpad_state == ScratchpadState::None
+            // The only child, so it is as large as its parent. New windows may not have been
+            // laid out yet.
+            && window.percent == Some(1.0)
+            && width > 0
+            && height > 0
+            // Tabbed and stacked containers always show titles, so this isn't in one.
+            && window.deco_rect.3 == 0
+            && !config.is_ignored_window_type(window)
+            && !config.rules.iter().any(|rule| rule.matches(window));
+        if !simple {
+            return None;
+        }
+
+        scheme
+            .split(&scheme::Context {
+                focused: window,
+                parent: window,
+                workspace: None,
+                depth: 1,
+                ratio: config.ratio,
+                master: None,
+                master_ratio: config.master_ratio,
+                gaps: config.gaps,
+            })
+            .and_then(|split| fit(config, split, window))
+    }
+
+    fn run(&mut self, command: &str) {
+        self.commands.push(Command::Run(command.to_owned()));
+    }
+
+    /// Tells the layout hook about changing `container` to `split`.
+    fn layout_changed(&mut self, container: i64, split: I3Split) {
+        if self.config.hooks.layout.is_some() {
+            self.commands
+                .push(Command::LayoutChanged { container, split });
+        }
+    }
+
+    /// Marks `container` as laid out by the daemon, if marking is enabled.
+    fn mark_managed(&mut self, container: &Node) {
+        let mark = format!("{MANAGED_MARK}{}", container.id);
+        if self.config.mark
+            && container.nodetype == NodeType::Con
+            && !container.marks.contains(&mark)
+        {
+            self.run(&format!("[con_id={}] mark --add {mark}", container.id));
+        }
+    }
+
+    /// Marks the parent of `window` once the tree shows it, as splitting the window may have
+    /// made a new one.
+    fn mark_parent(&mut self, window: i64) {
+        if self.config.mark {
+            self.state.unmarked.insert(window);
+        }
+    }
+
+    /// Marks the containers windows were split into since the tree was last read.
+    fn mark_new_containers(&mut self, tree: &Node) {
+        if self.state.unmarked.is_empty() {
+            return;
+        }
+        let unmarked = std::mem::take(&mut self.state.unmarked);
+        let parents: Vec<_> = tree
+            .descendants()
+            .filter(|n| n.nodes.iter().any(|child| unmarked.contains(&child.id)))
+            .collect();
+        for parent in parents {
+            self.mark_managed(parent);
+        }
+    }
+
+    /// Records how to give `parent` of `window` its current layout back.
+    fn record_layout(&mut self, window: &Node, parent: &Node) {
+        if let Some(layout) = layout_command(&parent.layout) {
+            // Layout commands for windows apply to their parents, which may be new ones.
+            self.state
+                .record(Undo::Run(format!("[con_id={}] {layout}", window.id)));
+        }
+    }
+
+    fn handle_keybind<C: WmConnection>(&mut self, e: &BindingEventInfo, wm: &mut C) -> Option<()> {
+        // split v; exec alacritty
+        for command in split_commands(&e.binding.command) {
+            self.handle_binding_command(command, wm);
+        }
+
+        Some(())
+    }
+
+    fn handle_binding_command<C: WmConnection>(&mut self, command: &str, wm: &mut C) -> Option<()> {
+        // [class="Firefox"] focus
+        let command = command
+            .strip_prefix('[')
+            .and_then(|command| command.split_once(']'))
+            .map_or(command, |(_, command)| command.trim_start());
+        let mut binding = command.split_whitespace();
+        match binding.next()? {
+            "split" => {
+                self.state.split_manually();
+                self.print_status(binding.next()?.parse().ok()?);
+            }
+            // The window left behind is handled by the event for the move.
+            "move" if command.ends_with("scratchpad") => {}
+            "move" | "focus" | "workspace" => self.set_layout(None, wm)?,
+            // bindsym $mod+a nop alternating:pause
+            "nop" => {
+                let message = command.strip_prefix("nop")?;
+                self.handle_message(message.trim().trim_matches('"'), wm)?
+            }
+            "layout" => {
+                let command = binding.next()?;
+                let split = if command.starts_with("split") {
+                    // layout splith, splitv
+                    command.chars().last()?.to_string()
+                } else {
+                    command.to_owned()
+                };
+
+                let split = split.parse().ok()?;
+                if matches!(
+                    split,
+                    I3Split::Vertical | I3Split::Horizontal | I3Split::Toggle
+                ) {
+                    self.state.split_manually();
+                }
+                self.print_status(split)
+            }
+            _ => {}
+        }
+
+        Some(())
+    }
+
+    /// Splits the focused window once a binding mode pausing splitting is left.
+    fn handle_mode<C: WmConnection>(&mut self, e: &ModeEventInfo, wm: &mut C) -> Option<()> {
+        let was_paused = self.config.pause_modes.contains(&self.state.mode);
+        self.state.mode.clone_from(&e.change);
+        if was_paused && !self.config.pause_modes.contains(&self.state.mode) {
+            self.set_layout(None, wm)?;
+        }
+
+        Some(())
+    }
+
+    /// Runs `message` from a tick or `nop` binding if it is a command for this daemon.
+    fn handle_message<C: WmConnection>(&mut self, message: &str, wm: &mut C) -> Option<()> {
+        match control::Command::from_message(message)? {
+            Ok(command) => {
+                self.handle_command(command, wm);
+            }
+            Err(e) => warn!("{e}"),
+        }
+
+        Some(())
+    }
+
+    fn handle_command<C: WmConnection>(&mut self, command: control::Command, wm: &mut C) -> Value {
+        use control::Command;
+
+        let (config, state) = (self.config, &mut *self.state);
+        match &command {
+            Command::Pause(workspace) | Command::Resume(workspace) | Command::Toggle(workspace) => {
+                let was_paused = state.is_paused(None);
+                let paused = match &command {
+                    Command::Pause(_) => true,
+                    Command::Resume(_) => false,
+                    _ => !state.is_paused(workspace.as_deref()),
+                };
+                state.set_paused(workspace.as_deref(), paused);
+                if state.is_paused(None) != was_paused {
+                    self.show_status(self.state.status(None));
+                }
+                let change = if paused { "paused" } else { "resumed" };
+                self.notify(match workspace {
+                    Some(workspace) => format!("{change} on workspace {workspace}"),
+                    None => change.to_owned(),
+                });
+            }
+            Command::Status(workspace) => {
+                let workspace = workspace.as_deref();
+                return json!({
+                    "success": true,
+                    "workspace": workspace.or(state.focused_workspace()),
+                    "paused": state.is_paused(workspace),
+                    "scheme": state
+                        .scheme(workspace)
+                        .unwrap_or_else(|| config.scheme(workspace.or(state.focused_workspace())))
+                        .name(),
+                    "split": state.status(workspace).name(),
+                });
+            }
+            Command::Scheme(scheme, workspace) => self.set_scheme(workspace.as_deref(), *scheme),
+            Command::CycleScheme(backwards, workspace) => {
+                let current = state.scheme(workspace.as_deref()).unwrap_or_else(|| {
+                    config.scheme(workspace.as_deref().or(state.focused_workspace()))
+                });
+                self.set_scheme(workspace.as_deref(), Some(current.cycle(*backwards)));
+            }
+            Command::Balance(workspace) => return self.balance(workspace.as_deref(), wm),
+            Command::Transpose(workspace) => return self.transpose(workspace.as_deref(), wm),
+            Command::Rotate => return self.rotate(wm),
+            Command::Health => return self.health(wm),
+            Command::Stats => {
+                let mut stats = state.stats.to_json();
+                stats["success"] = true.into();
+                return stats;
+            }
+            Command::Promote => return self.promote(wm),
+            Command::Undo => return self.undo(wm),
+            Command::Save(name) => return self.save(name, wm),
+            Command::Restore(name) => return self.restore(name, wm),
+        }
+
+        json!({ "success": true })
+    }
+
+    /// Checks the connection for commands to i3. The daemon adds whether the events still
+    /// arrive, which only it knows.
+    fn health(&mut self, wm: &mut impl WmConnection) -> Value {
+        let last_event = self
+            .state
+            .last_event
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs());
+        match wm.get_version() {
+            Ok(_) => json!({ "success": true, "connected": true, "last_event": last_event }),
+            Err(e) => json!({
+                "success": false,
+                "error": format!("the connection to i3 is broken: {e}"),
+                "connected": false,
+                "last_event": last_event,
+            }),
+        }
+    }
+
+    /// Resizes the windows of `workspace`, or of the focused one, to equal shares of their
+    /// containers, in one batch of commands.
+    fn balance(&mut self, workspace: Option<&str>, wm: &mut impl WmConnection) -> Value {
+        let Some(tree) = get_tree(wm) else {
+            return json!({ "success": false, "error": "couldn't get the tree" });
+        };
+        let Some(workspace_node) = find_workspace(&tree, workspace) else {
+            return json!({ "success": false, "error": "no such workspace" });
+        };
+
+        let (mut resizes, mut undo) = (Vec::new(), Vec::new());
+        for container in managed_nodes(workspace_node) {
+            let side = match container.layout {
+                NodeLayout::SplitH => "width",
+                NodeLayout::SplitV => "height",
+                _ => continue,
+            };
+            let count = container.nodes.len();
+            let share = 100.0 / count as f64;
+            let unbalanced = container
+                .nodes
+                .iter()
+                .any(|n| n.percent.is_some_and(|p| (p * 100.0 - share).abs() >= 0.5));
+            if count < 2 || !unbalanced {
+                continue;
+            }
+            // The last window gets what is left.
+            for node in &container.nodes[..count - 1] {
+                resizes.push(format!(
+                    "[con_id={}] resize set {side} {} ppt",
+                    node.id,
+                    share.round()
+                ));
+                undo.extend(resize_command(node, container));
+            }
+        }
+        debug!("resizing {} windows", resizes.len());
+        if !resizes.is_empty() {
+            self.run(&resizes.join("; "));
+            self.state.record(Undo::Run(undo.join("; ")));
+        }
+
+        json!({ "success": true })
+    }
+
+    /// Flips the layout of `workspace`, or of the focused one, by 90 degrees.
+    fn transpose(&mut self, workspace: Option<&str>, wm: &mut impl WmConnection) -> Value {
+        let Some(tree) = get_tree(wm) else {
+            return json!({ "success": false, "error": "couldn't get the tree" });
+        };
+        let Some(workspace_node) = find_workspace(&tree, workspace) else {
+            return json!({ "success": false, "error": "no such workspace" });
+        };
+
+        // Windows take layout commands to their parents, so only containers are changed.
+        let changes: Vec<_> = managed_nodes(workspace_node)
+            .filter(|n| n.nodetype != NodeType::FloatingCon && !n.nodes.is_empty())
+            .filter_map(|n| match n.layout {
+                NodeLayout::SplitH => Some((n.id, "splith", "splitv")),
+                NodeLayout::SplitV => Some((n.id, "splitv", "splith")),
+                _ => None,
+            })
+            .collect();
+        debug!("transposing {} containers", changes.len());
+        if !changes.is_empty() {
+            let layouts = |undo: bool| {
+                changes
+                    .iter()
+                    .map(|&(id, from, to)| {
+                        format!("[con_id={id}] layout {}", if undo { from } else { to })
+                    })
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            };
+            self.run(&layouts(false));
+            self.state.record(Undo::Run(layouts(true)));
+            // The next split changes with the layout.
+            self.commands.push(Command::Relayout);
+        }
+
+        json!({ "success": true })
+    }
+
+    /// Moves the last child of the focused window's container to the front by swapping it with
+    /// each of the others, from the back.
+    fn rotate(&mut self, wm: &mut impl WmConnection) -> Value {
+        let Some(tree) = get_tree(wm) else {
+            return json!({ "success": false, "error": "couldn't get the tree" });
+        };
+        let path = focused_path(&tree);
+        let Some(container) = path.len().checked_sub(2).map(|i| path[i]) else {
+            return json!({ "success": false, "error": "nothing is focused" });
+        };
+
+        if let [others @ .., last] = &container.nodes[..] {
+            let swaps: Vec<_> = others
+                .iter()
+                .rev()
+                .map(|node| {
+                    format!(
+                        "[con_id={}] swap container with con_id {}",
+                        last.id, node.id
+                    )
+                })
+                .collect();
+            debug!("rotating {} windows", container.nodes.len());
+            if !swaps.is_empty() {
+                self.run(&swaps.join("; "));
+                // Swapping back in the opposite order undoes it.
+                let undo: Vec<_> = swaps.iter().rev().map(String::as_str).collect();
+                self.state.record(Undo::Run(undo.join("; ")));
+            }
+        }
+
+        json!({ "success": true })
+    }
+
+    /// Reverses the most recent automatic change, and keeps the container from being split
+    /// again right away as if it was split by hand.
+    fn undo(&mut self, wm: &mut impl WmConnection) -> Value {
+        let command = match self.state.take_undo() {
+            None => return json!({ "success": false, "error": "nothing to undo" }),
+            Some(Undo::Run(command)) => command,
+            Some(Undo::Unwrap(window)) => {
+                let Some(tree) = get_tree(wm) else {
+                    return json!({ "success": false, "error": "couldn't get the tree" });
+                };
+                // The parent of the container the window was split into.
+                let direction = tree
+                    .descendants()
+                    .find_map(|n| {
+                        let index = n
+                            .nodes
+                            .iter()
+                            .position(|c| c.nodes.len() == 1 && c.nodes[0].id == window)?;
+                        Some((n, index))
+                    })
+                    .and_then(|(parent, index)| move_out(parent, index, 0));
+                match direction {
+                    Some(direction) => format!("[con_id={window}] move {direction}"),
+                    None => {
+                        return json!({ "success": false, "error": "the split is gone" });
+                    }
+                }
+            }
+        };
+
+        debug!("undoing with '{command}'");
+        self.run(&command);
+        self.state.split_manually();
+        json!({ "success": true })
+    }
+
+    /// Saves the layout of the focused workspace as `name`.
+    fn save(&mut self, name: &str, wm: &mut impl WmConnection) -> Value {
+        let Some(tree) = get_tree(wm) else {
+            return json!({ "success": false, "error": "couldn't get the tree" });
+        };
+        let Some(workspace) = find_workspace(&tree, None) else {
+            return json!({ "success": false, "error": "no such workspace" });
+        };
+
+        match Layout::of_workspace(workspace).save(name) {
+            Ok(()) => {
+                info!(
+                    "saved the layout of workspace {:?} as '{name}'",
+                    workspace.name
+                );
+                json!({ "success": true })
+            }
+            Err(e) => json!({ "success": false, "error": e }),
+        }
+    }
+
+    /// Restores the layout saved as `name` on the focused workspace.
+    fn restore(&mut self, name: &str, wm: &mut impl WmConnection) -> Value {
+        let Some(tree) = get_tree(wm) else {
+            return json!({ "success": false, "error": "couldn't get the tree" });
+        };
+        let Some(workspace) = find_workspace(&tree, None) else {
+            return json!({ "success": false, "error": "no such workspace" });
+        };
+
+        match Layout::load(name).and_then(|layout| layout.apply(workspace)) {
+            Ok(commands) => {
+                debug!("restoring layout '{name}'");
+                self.run(&commands.join("; "));
+                json!({ "success": true })
+            }
+            Err(e) => json!({ "success": false, "error": e }),
+        }
+    }
+
+    /// Swaps the focused window with the largest tiling window of its workspace, like dwm's
+    /// zoom.
+    fn promote(&mut self, wm: &mut impl WmConnection) -> Value {
+        let Some(tree) = get_tree(wm) else {
+            return json!({ "success": false, "error": "couldn't get the tree" });
+        };
+        let path = focused_path(&tree);
+        let (Some(workspace), Some(focused)) = (
+            path.iter().rfind(|n| n.nodetype == NodeType::Workspace),
+            path.last(),
+        ) else {
+            return json!({ "success": false, "error": "nothing is focused" });
+        };
+
+        if focused.is_floating() {
+            return json!({ "success": false, "error": "the focused window is floating" });
+        }
+
+        let mut windows: Vec<_> = workspace
+            .nodes
+            .iter()
+            .flat_map(Node::descendants)
+            .filter(|n| n.nodetype == NodeType::Con && n.nodes.is_empty())
+            .collect();
+        // Largest first, keeping the tree order among equals, so the largest other window is
+        // the next largest if the focused one is the largest.
+        windows.sort_by_key(|n| std::cmp::Reverse(i64::from(n.rect.2) * i64::from(n.rect.3)));
+        let Some(target) = windows.iter().find(|n| n.id != focused.id) else {
+            return json!({ "success": true });
+        };
+
+        debug!("promoting {} over {}", focused.id, target.id);
+        let swap = format!(
+            "[con_id={}] swap container with con_id {}",
+            focused.id, target.id
+        );
+        self.run(&swap);
+        self.state.record(Undo::Run(swap));
+        if let Some(name) = &workspace.name {
+            self.state.swap_master(name, focused.id, target.id);
+        }
+
+        json!({ "success": true })
+    }
+
+    fn print_status(&mut self, split: I3Split) {
+        match split {
+            I3Split::Tabbed | I3Split::Stacked => self.show_status(split),
+            I3Split::Vertical | I3Split::Horizontal => {
+                self.state.set_split(split);
+                self.show_status(split)
+            }
+            I3Split::Toggle => match self.state.previous_split() {
+                I3Split::Vertical => self.print_status(I3Split::Horizontal),
+                _ => self.print_status(I3Split::Vertical),
+            },
+        }
+    }
+
+    /// Prints `split` as the status of a window that is left alone.
+    fn print_ignored(&mut self, split: I3Split) {
+        self.print_status(split);
+        if let Some(Command::Status(status)) = self.commands.last_mut() {
+            if let Status::Split(split) = *status {
+                *status = Status::Ignored(split);
+            }
+        }
+    }
+
+    /// Notifies about a change of mode, if notifications are enabled.
+    fn notify(&mut self, body: String) {
+        if self.config.notify {
+            self.commands.push(Command::Notify(body));
+        }
+    }
+
+    /// Overrides the scheme of `workspace`, or of all of them for `None`.
+    fn set_scheme(&mut self, workspace: Option<&str>, scheme: Option<Scheme>) {
+        self.state.set_scheme(workspace, scheme);
+        if self.config.format.has_scheme() {
+            self.show_scheme();
+        }
+        let scheme = scheme.map_or("configured", |scheme| scheme.name());
+        self.notify(match workspace {
+            Some(workspace) => format!("{scheme} scheme on workspace {workspace}"),
+            None => format!("{scheme} scheme"),
+        });
+    }
+
+    /// Shows the status again with the scheme of the focused workspace.
+    fn show_scheme(&mut self) {
+        let focused = self.state.focused_workspace();
+        let scheme = self
+            .state
+            .scheme(None)
+            .unwrap_or_else(|| self.config.scheme(focused));
+        self.commands.push(Command::Scheme(scheme));
+        self.show_status(self.state.status(None));
+    }
+
+    /// Shows `split`, or that splitting is paused on the focused workspace.
+    fn show_status(&mut self, split: I3Split) {
+        self.state.set_status(split);
+        self.commands
+            .push(Command::Status(if self.state.is_paused(None) {
+                Status::Paused
+            } else {
+                Status::Split(split)
+            }));
+    }
+}
+
+/// The workspace called `name`, or the focused one.
+fn find_workspace<'a>(tree: &'a Node, name: Option<&str>) -> Option<&'a Node> {
+    match name {
+        Some(name) => tree
+            .descendants()
+            .find(|n| n.nodetype == NodeType::Workspace && n.name.as_deref() == Some(name)),
+        None => focused_path(tree)
+            .into_iter()
+            .rfind(|n| n.nodetype == NodeType::Workspace),
+    }
+}
+
+/// The nodes from `root` down to the focused node, found by following the most recently
+/// focused child of each node. Empty if nothing is focused.
+fn focused_path(root: &Node) -> Vec<&Node> {
+    let mut path = vec![root];
+    let mut node = root;
+    while !node.focused {
+        let child = node.focus.first().and_then(|&id| {
+            node.nodes
+                .iter()
+                .chain(&node.floating_nodes)
+                .find(|n| n.id == id)
+        });
+        match child {
+            Some(child) => {
+                path.push(child);
+                node = child;
+            }
+            None => return Vec::new(),
+        }
+    }
+    path
+}
+
+/// Moves the focus to node `id`, returning whether it is below `node`.
+fn refocus(node: &mut Node, id: i64) -> bool {
+    node.focused = node.id == id;
+    let mut child = None;
+    for node in node.nodes.iter_mut().chain(&mut node.floating_nodes) {
+        if refocus(node, id) {
+            child = Some(node.id);
+        }
+    }
+    if let Some(child) = child {
+        node.focus.retain(|&focus| focus != child);
+        node.focus.insert(0, child);
+    }
+    node.focused || child.is_some()
+}
+
+fn get_tree(wm: &mut impl WmConnection) -> Option<Node> {
+    let tree = wm
+        .get_tree()
+        .map_err(|e| error!("couldn't get the tree: {e}"))
+        .ok()?;
+    record::tree(&tree);
+    Some(tree)
+}
+
+/// Splits a chain of i3 commands on the `;` and `,` between them, leaving quoted ones alone.
+fn split_commands(commands: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in commands.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            ';' | ',' if !quoted => {
+                split.push(&commands[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&commands[start..]);
+
+    split
+        .into_iter()
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+fn run_command(wm: &mut impl WmConnection, config: &Config, command: &str) -> Option<()> {
+    if config.dry_run {
+        info!("would run '{command}'");
+        return Some(());
+    }
+
+    debug!("running '{command}'");
+    let result = match wm.run_command(command) {
+        Err(e) if e.is_transient() => {
+            warn!("'{command}' failed: {e}, sending it again");
+            wm.reconnect().and_then(|()| wm.run_command(command))
+        }
+        result => result,
+    };
+    result.map_err(|e| error!("'{command}' failed: {e}")).ok()
+}
+
+/// `split`, or the other orientation if splitting `window` that way would leave it smaller than
+/// the minimum size. `None` if neither fits.
+fn fit(config: &Config, split: I3Split, window: &Node) -> Option<I3Split> {
+    let (width, height) = scheme::usable_size(window, config.gaps);
+    let fits = |split| match split {
+        I3Split::Horizontal => width / 2 >= config.min_width as i32,
+        I3Split::Vertical => height / 2 >= config.min_height as i32,
+        _ => true,
+    };
+    let other = match split {
+        I3Split::Horizontal => I3Split::Vertical,
+        _ => I3Split::Horizontal,
+    };
+    if fits(split) {
+        Some(split)
+    } else if fits(other) {
+        explain!(config, "a {split:?} split would be too small");
+        Some(other)
+    } else {
+        explain!(config, "the window is too small to split");
+        None
+    }
+}
+
+/// The command resizing `focused` to the golden ratio of `parent`, unless it is that large or
+/// has no neighbours to take the space from.
+fn golden_ratio(config: &Config, focused: &Node, parent: &Node) -> Option<String> {
+    let ratio = config.golden_ratio?;
+    let side = match parent.layout {
+        NodeLayout::SplitH => "width",
+        NodeLayout::SplitV => "height",
+        _ => return None,
+    };
+    if parent.nodes.len() < 2 || focused.percent.is_some_and(|p| (p - ratio).abs() < 0.01) {
+        return None;
+    }
+
+    Some(format!(
+        "[con_id={}] resize set {side} {} ppt",
+        focused.id,
+        (ratio * 100.0).round()
+    ))
+}
+
+/// The moves taking the windows out of split containers holding nothing but them, innermost
+/// first so chains of such containers are undone one level per move. The container the
+/// focused window was just split into is kept, and with `only_managed` the ones that aren't
+/// marked as laid out by the daemon.
+fn flatten(workspace: &Node, focused: i64, only_managed: bool) -> Vec<String> {
+    let redundant = |n: &Node| {
+        n.nodetype == NodeType::Con
+            && matches!(n.layout, NodeLayout::SplitH | NodeLayout::SplitV)
+            && n.nodes.len() == 1
+            && !is_opted_out(n)
+            && (!only_managed || n.marks.iter().any(|mark| mark.starts_with(MANAGED_MARK)))
+    };
+    let mut moves = Vec::new();
+    let mut stack = vec![workspace];
+    while let Some(parent) = stack.pop() {
+        for (index, top) in parent.nodes.iter().enumerate() {
+            let mut chain = vec![(move_out(parent, index, 0), top)];
+            let mut window = top;
+            while let Some(child) = window.nodes.first().filter(|_| redundant(window)) {
+                if redundant(child) {
+                    chain.push((move_out(window, 0, 0), child));
+                }
+                window = child;
+            }
+            if !is_opted_out(window) {
+                stack.push(window);
+            }
+            if window.id == focused {
+                chain.pop();
+            }
+            if !redundant(top) {
+                continue;
+            }
+            debug!(
+                "container {} holds nothing but container {}",
+                top.id, window.id
+            );
+            for (direction, _) in chain.iter().rev() {
+                if let Some(direction) = direction {
+                    moves.push(format!("[con_id={}] move {direction}", window.id));
+                }
+            }
+        }
+    }
+    moves
+}
+
+/// The moves taking windows nested deeper than `max_depth` below `workspace` up into the
+/// container above theirs, one per container as the others shift with it.
+fn move_up(workspace: &Node, max_depth: usize) -> Vec<String> {
+    let mut moves = Vec::new();
+    // Containers, by their parents and their index there, and how deep their children are.
+    let mut stack: Vec<_> = (0..workspace.nodes.len())
+        .filter(|&i| !is_opted_out(&workspace.nodes[i]))
+        .map(|i| (workspace, i, 2))
+        .collect();
+    while let Some((grandparent, index, depth)) = stack.pop() {
+        let parent = &grandparent.nodes[index];
+        let mut moved = false;
+        for (child, window) in parent.nodes.iter().enumerate() {
+            if !window.nodes.is_empty() {
+                if !is_opted_out(window) {
+                    stack.push((parent, child, depth + 1));
+                }
+            } else if depth > max_depth && window.nodetype == NodeType::Con && !moved {
+                if let Some(direction) = move_out(grandparent, index, child) {
+                    debug!("container {} is nested {depth} deep", window.id);
+                    moves.push(format!("[con_id={}] move {direction}", window.id));
+                    moved = true;
+                }
+            }
+        }
+    }
+    moves
+}
+
+fn is_opted_out(node: &Node) -> bool {
+    node.marks.iter().any(|mark| mark == OPT_OUT_MARK)
+}
+
+/// Like `Node::descendants`, but leaving out the containers marked with `OPT_OUT_MARK` and
+/// everything in them.
+fn managed_nodes(root: &Node) -> impl Iterator<Item = &Node> {
+    let mut stack = vec![root];
+    std::iter::from_fn(move || loop {
+        let node = stack.pop()?;
+        if is_opted_out(node) {
+            continue;
+        }
+        stack.extend(node.floating_nodes.iter().rev());
+        stack.extend(node.nodes.iter().rev());
+        return Some(node);
+    })
+}
+
+/// The direction in which the child at `child` of the container at `index` in `parent` moves
+/// out of it to land next to it in `parent`. Moves go along the orientation of `parent`, and
+/// only towards a window or an edge as moving towards a container would put it inside that.
+fn move_out(parent: &Node, index: usize, child: usize) -> Option<&'static str> {
+    let vertical = |layout: &NodeLayout| matches!(layout, NodeLayout::SplitV | NodeLayout::Stacked);
+    let (back, forth) = if vertical(&parent.layout) {
+        ("up", "down")
+    } else {
+        ("left", "right")
+    };
+    let container = parent.nodes.get(index)?;
+    // Along the container's own orientation, the child only leaves it from its edges.
+    let along = vertical(&container.layout) == vertical(&parent.layout);
+    let free = |sibling: Option<&Node>| sibling.is_none_or(|n| n.nodes.is_empty());
+    if (!along || child == 0) && free(index.checked_sub(1).and_then(|i| parent.nodes.get(i))) {
+        Some(back)
+    } else if (!along || child + 1 == container.nodes.len()) && free(parent.nodes.get(index + 1)) {
+        Some(forth)
+    } else {
+        None
+    }
+}
+
+/// The command resizing `node` in `parent` back to its current size.
+fn resize_command(node: &Node, parent: &Node) -> Option<String> {
+    let side = match parent.layout {
+        NodeLayout::SplitH => "width",
+        NodeLayout::SplitV => "height",
+        _ => return None,
+    };
+    let percent = node.percent?;
+    Some(format!(
+        "[con_id={}] resize set {side} {} ppt",
+        node.id,
+        (percent * 100.0).round()
+    ))
+}
+
+/// The command giving a container `layout`.
+fn layout_command(layout: &NodeLayout) -> Option<&'static str> {
+    match layout {
+        NodeLayout::SplitH => Some("layout splith"),
+        NodeLayout::SplitV => Some("layout splitv"),
+        NodeLayout::Tabbed => Some("layout tabbed"),
+        NodeLayout::Stacked => Some("layout stacking"),
+        _ => None,
+    }
+}
+
+/// Runs `hook` for `event`, with the focused workspace and `container` in its environment.
+fn run_hook(
+    config: &Config,
+    hook: &str,
+    event: &str,
+    split: &str,
+    state: &State,
+    container: Option<i64>,
+) {
+    let vars = [
+        ("ALT_EVENT", event.to_owned()),
+        ("ALT_SPLIT", split.to_owned()),
+        (
+            "ALT_WORKSPACE",
+            state.focused_workspace().unwrap_or_default().to_owned(),
+        ),
+        (
+            "ALT_CONTAINER_ID",
+            container.map_or_else(String::new, |id| id.to_string()),
+        ),
+    ];
+    if config.dry_run {
+        info!("would run hook '{hook}' with {vars:?}");
+    } else {
+        hooks::run(hook, &vars);
+    }
+}
+
+fn split_command(split: I3Split) -> &'static str {
+    match split {
+        I3Split::Horizontal => "split horizontal",
+        _ => "split vertical",
+    }
+}
+
+/// The number of tiled windows below `node`.
+fn count_windows(node: &Node) -> usize {
+    let mut stack = vec![node];
+    let mut windows = 0;
+    while let Some(node) = stack.pop() {
+        if node.nodes.is_empty() {
+            windows += usize::from(node.nodetype == NodeType::Con);
+        }
+        stack.extend(&node.nodes);
+    }
+    windows
+}
+
+/// The split new windows in a container with `layout` get.
+fn layout_split(layout: &NodeLayout) -> I3Split {
+    match layout {
+        NodeLayout::SplitV => I3Split::Vertical,
+        _ => I3Split::Horizontal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        config::{Clicks, Format, Orientation, Rule, RuleLayout},
+        defaults::Defaults,
+        ipc::{
+            event::{Binding, BindingEventInfo, OutputEventInfo},
+            fake::{FakeConnection, NodeBuilder, TreeBuilder},
+        },
+    };
+
+    fn focus_binding() -> Event {
+        Event::Binding(BindingEventInfo {
+            binding: Binding {
+                command: "focus left".to_owned(),
+            },
+        })
+    }
+
+    /// The commands for focusing a window in `tree`, decided from the tree.
+    fn decide(tree: TreeBuilder) -> Vec<Command> {
+        let mut wm = FakeConnection::new(tree.build());
+        LayoutEngine::new(Config::default()).handle_event(&mut wm, &focus_binding())
+    }
+
+    fn runs(commands: &[Command]) -> Vec<&str> {
+        commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::Run(command) => Some(command.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn workspace(windows: usize) -> Command {
+        Command::Workspace {
+            name: "1".to_owned(),
+            windows,
+            container_windows: windows,
+            scheme: Scheme::Alternating,
+        }
+    }
+
+    #[test]
+    fn splits_wide_containers_horizontally() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+
+        assert_eq!(
+            decide(tree),
+            [
+                workspace(1),
+                Command::Run("split horizontal".to_owned()),
+                Command::Status(Status::Split(I3Split::Horizontal)),
+            ]
+        );
+    }
+
+    #[test]
+    fn tells_the_layout_hook_about_splits() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(
+                NodeLayout::SplitH,
+                [
+                    NodeBuilder::window(),
+                    NodeBuilder::window().id(100).focused(),
+                ],
+            ),
+        );
+        let config = Config {
+            hooks: crate::config::Hooks {
+                layout: Some("true".to_owned()),
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let mut wm = FakeConnection::new(tree.build());
+
+        let commands = LayoutEngine::new(config).handle_event(&mut wm, &focus_binding());
+        assert!(commands.contains(&Command::LayoutChanged {
+            container: 100,
+            split: I3Split::Horizontal
+        }));
+    }
+
+    #[test]
+    fn splits_tall_containers_vertically() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(
+                NodeLayout::SplitH,
+                [
+                    NodeBuilder::window(),
+                    NodeBuilder::container(
+                        NodeLayout::SplitV,
+                        [NodeBuilder::window(), NodeBuilder::window().focused()],
+                    ),
+                ],
+            ),
+        );
+
+        assert_eq!(
+            decide(tree),
+            [
+                Command::Workspace {
+                    name: "1".to_owned(),
+                    windows: 3,
+                    container_windows: 2,
+                    scheme: Scheme::Alternating,
+                },
+                Command::Run("split vertical".to_owned()),
+                Command::Status(Status::Split(I3Split::Vertical)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ratio_favours_vertical_splits() {
+        let tree = TreeBuilder::new((0, 0, 1200, 1000)).workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        let config = Config {
+            ratio: 0.8,
+            ..Config::default()
+        };
+
+        let commands = LayoutEngine::new(config).handle_event(&mut wm, &focus_binding());
+        assert!(commands.contains(&Command::Run("split vertical".to_owned())));
+    }
+
+    #[test]
+    fn avoids_splits_leaving_windows_too_small() {
+        let tree = TreeBuilder::new((0, 0, 1000, 600)).workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+        let decide = |min_width, min_height| {
+            let mut wm = FakeConnection::new(tree.clone().build());
+            let config = Config {
+                min_width,
+                min_height,
+                ..Config::default()
+            };
+            let commands = LayoutEngine::new(config).handle_event(&mut wm, &focus_binding());
+            runs(&commands)
+                .into_iter()
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(decide(500, 300), ["split horizontal"]);
+        assert_eq!(decide(600, 300), ["split vertical"]);
+        assert!(decide(600, 400).is_empty());
+    }
+
+    #[test]
+    fn resizes_focused_windows_to_the_golden_ratio() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(
+                NodeLayout::SplitH,
+                [
+                    NodeBuilder::window(),
+                    NodeBuilder::window().id(100).focused(),
+                ],
+            ),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        let config = Config {
+            golden_ratio: Some(0.618),
+            ..Config::default()
+        };
+
+        let commands = LayoutEngine::new(config).handle_event(&mut wm, &focus_binding());
+        assert_eq!(
+            runs(&commands),
+            ["split horizontal", "[con_id=100] resize set width 62 ppt"]
+        );
+    }
+
+    #[test]
+    fn keeps_tabbed_and_stacked_containers() {
+        for (layout, split) in [
+            (NodeLayout::Tabbed, I3Split::Tabbed),
+            (NodeLayout::Stacked, I3Split::Stacked),
+        ] {
+            let tree = TreeBuilder::default().workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [NodeBuilder::container(
+                        layout,
+                        [NodeBuilder::window(), NodeBuilder::window().focused()],
+                    )],
+                ),
+            );
+
+            assert_eq!(
+                decide(tree),
+                [workspace(2), Command::Status(Status::Split(split))]
+            );
+        }
+    }
+
+    #[test]
+    fn explains_the_decision_for_each_window() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window().id(100).focused(),
+                        NodeBuilder::container(
+                            NodeLayout::Tabbed,
+                            [NodeBuilder::window().id(101), NodeBuilder::window().id(102)],
+                        ),
+                        NodeBuilder::window().id(103).class("Gimp"),
+                    ],
+                ),
+            )
+            .build();
+        let engine = LayoutEngine::new(Config {
+            rules: vec![Rule {
+                class: Some("Gimp".to_owned()),
+                ..Rule::default()
+            }],
+            ..Config::default()
+        });
+
+        assert_eq!(
+            engine.explain(&tree, 100).map(|d| d.to_string()).as_deref(),
+            Some("horizontal")
+        );
+        assert_eq!(
+            engine.explain(&tree, 102).map(|d| d.to_string()).as_deref(),
+            Some("keep: the container is tabbed or stacked")
+        );
+        assert_eq!(
+            engine.explain(&tree, 103).map(|d| d.to_string()).as_deref(),
+            Some("ignore: a rule matches the window")
+        );
+    }
+
+    #[test]
+    fn decides_the_same_for_recorded_trees() {
+        let tree = TreeBuilder::new((0, 0, 800, 1200))
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitV,
+                    [NodeBuilder::window(), NodeBuilder::window().focused()],
+                ),
+            )
+            .build();
+        let recorded: Node = serde_json::from_value(json!(tree)).unwrap();
+
+        let mut engine = LayoutEngine::new(Config::default());
+        let commands = engine.handle_event(&mut FakeConnection::new(tree), &focus_binding());
+        assert_eq!(
+            engine.handle_event(&mut FakeConnection::new(recorded), &focus_binding()),
+            commands
+        );
+        assert_eq!(runs(&commands), ["split vertical"]);
+    }
+
+    #[test]
+    fn tabs_crowded_containers_and_splits_them_again() {
+        let tree = |layout, windows| {
+            let mut windows: Vec<_> = (0..windows).map(|_| NodeBuilder::window()).collect();
+            windows.push(NodeBuilder::window().focused());
+            TreeBuilder::default()
+                .workspace(
+                    "1",
+                    NodeBuilder::container(
+                        NodeLayout::SplitH,
+                        [NodeBuilder::container(layout, windows).id(100)],
+                    ),
+                )
+                .build()
+        };
+        let mut engine = LayoutEngine::new(Config {
+            tabbed_after: Some(2),
+            ..Config::default()
+        });
+
+        let mut wm = FakeConnection::new(tree(NodeLayout::SplitV, 2));
+        assert_eq!(
+            engine.handle_event(&mut wm, &focus_binding()),
+            [
+                workspace(3),
+                Command::Run("layout tabbed".to_owned()),
+                Command::Status(Status::Split(I3Split::Tabbed)),
+            ]
+        );
+
+        let mut wm = FakeConnection::new(tree(NodeLayout::Tabbed, 1));
+        assert_eq!(
+            engine.handle_event(&mut wm, &focus_binding()),
+            [
+                workspace(2),
+                Command::Run("layout splith".to_owned()),
+                Command::Relayout,
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_tabbed_containers_of_the_user_alone() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(
+                NodeLayout::Tabbed,
+                [NodeBuilder::window(), NodeBuilder::window().focused()],
+            ),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        let config = Config {
+            tabbed_after: Some(3),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            LayoutEngine::new(config).handle_event(&mut wm, &focus_binding()),
+            [
+                workspace(2),
+                Command::Status(Status::Split(I3Split::Tabbed))
+            ]
+        );
+    }
+
+    fn browser_rule() -> Config {
+        Config {
+            rules: vec![Rule {
+                class: Some("firefox".to_owned()),
+                layout: Some(RuleLayout::Tabbed),
+                ..Rule::default()
+            }],
+            ..Config::default()
+        }
+    }
+
+    fn new_window(tree: &Node) -> Event {
+        let window = tree.descendants().find(|n| n.focused).unwrap().clone();
+        Event::Window(WindowEventInfo {
+            change: WindowChange::New,
+            container: Box::new(window),
+        })
+    }
+
+    #[test]
+    fn wraps_windows_of_layout_rules() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window(),
+                        NodeBuilder::window().class("firefox").focused(),
+                    ],
+                ),
+            )
+            .build();
+        let mut wm = FakeConnection::new(tree.clone());
+
+        let commands = LayoutEngine::new(browser_rule()).handle_event(&mut wm, &new_window(&tree));
+        assert_eq!(
+            commands[1..],
+            [
+                Command::Run("split vertical".to_owned()),
+                Command::Run("layout tabbed".to_owned()),
+                Command::Status(Status::Split(I3Split::Tabbed)),
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_windows_of_layout_rules_next_to_each_other() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::container(
+                            NodeLayout::Tabbed,
+                            [NodeBuilder::window().class("firefox").id(100)],
+                        ),
+                        NodeBuilder::window().class("firefox").id(200).focused(),
+                    ],
+                ),
+            )
+            .build();
+        let mut wm = FakeConnection::new(tree.clone());
+
+        let commands = LayoutEngine::new(browser_rule()).handle_event(&mut wm, &new_window(&tree));
+        assert_eq!(
+            runs(&commands),
+            [
+                "[con_id=100] mark --add _alternating_layout",
+                "[con_id=200] move window to mark _alternating_layout",
+                "[con_id=100] unmark _alternating_layout",
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_an_empty_status_without_focus() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window()]),
+        );
+
+        assert_eq!(decide(tree), [Command::Status(Status::Empty)]);
+    }
+
+    #[test]
+    fn shows_when_a_rule_keeps_a_window_from_being_split() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(
+                NodeLayout::SplitV,
+                [NodeBuilder::window().class("Gimp").focused()],
+            ),
+        );
+        let config = Config {
+            rules: vec![Rule {
+                class: Some("Gimp".to_owned()),
+                ..Rule::default()
+            }],
+            ..Config::default()
+        };
+
+        let mut wm = FakeConnection::new(tree.build());
+        assert_eq!(
+            LayoutEngine::new(config).handle_event(&mut wm, &focus_binding()),
+            [
+                workspace(1),
+                Command::Status(Status::Ignored(I3Split::Vertical))
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_floating_windows_alone() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window()])
+                .floating(NodeBuilder::window().focused()),
+        );
+
+        assert_eq!(
+            decide(tree),
+            [workspace(1), Command::Status(Status::Floating)]
+        );
+    }
+
+    #[test]
+    fn decides_lone_windows_from_the_event() {
+        // The tree doesn't have the window, so the split can only come from the event.
+        let mut wm = FakeConnection::default();
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+            )
+            .build();
+        let window = tree.descendants().find(|n| n.focused).unwrap().clone();
+        let event = Event::Window(WindowEventInfo {
+            change: WindowChange::Focus,
+            container: Box::new(window),
+        });
+
+        assert_eq!(
+            LayoutEngine::new(Config::default()).handle_event(&mut wm, &event),
+            [
+                Command::Run("split horizontal".to_owned()),
+                Command::Status(Status::Split(I3Split::Horizontal)),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_title_bars_out_of_the_aspect() {
+        let mut wm = FakeConnection::default();
+        let tree = TreeBuilder::new((0, 0, 1000, 1010))
+            .workspace(
+                "1",
+                NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+            )
+            .build();
+        let mut window = tree.descendants().find(|n| n.focused).unwrap().clone();
+        window.window_rect = (2, 22, 996, 986);
+        let event = Event::Window(WindowEventInfo {
+            change: WindowChange::Focus,
+            container: Box::new(window),
+        });
+
+        let commands = LayoutEngine::new(Config::default()).handle_event(&mut wm, &event);
+        assert_eq!(runs(&commands), ["split horizontal"]);
+    }
+
+    #[test]
+    fn pausing_keeps_the_split() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        let mut engine = LayoutEngine::new(Config::default());
+        engine.handle_control(&mut wm, control::Command::Pause(None));
+
+        assert_eq!(
+            engine.handle_event(&mut wm, &focus_binding()),
+            [workspace(1), Command::Status(Status::Paused)]
+        );
+    }
+
+    #[test]
+    fn keeps_out_of_opted_out_containers() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(
+                NodeLayout::SplitH,
+                [
+                    NodeBuilder::window(),
+                    NodeBuilder::container(
+                        NodeLayout::SplitH,
+                        [NodeBuilder::window(), NodeBuilder::window().focused()],
+                    )
+                    .mark(OPT_OUT_MARK),
+                ],
+            ),
+        );
+
+        assert!(runs(&decide(tree)).is_empty());
+    }
+
+    #[test]
+    fn marks_the_containers_windows_are_split_into() {
+        let tree = |focused: NodeBuilder| {
+            TreeBuilder::default()
+                .workspace(
+                    "1",
+                    NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window(), focused]),
+                )
+                .build()
+        };
+        let mut wm = FakeConnection::new(tree(NodeBuilder::window().id(100).focused()));
+        let mut engine = LayoutEngine::new(Config {
+            mark: true,
+            ..Config::default()
+        });
+        let commands = engine.handle_event(&mut wm, &focus_binding());
+        assert_eq!(runs(&commands), ["split horizontal"]);
+
+        wm.tree = tree(
+            NodeBuilder::container(
+                NodeLayout::SplitH,
+                [NodeBuilder::window().id(100).focused()],
+            )
+            .id(101),
+        );
+        let commands = engine.handle_event(&mut wm, &focus_binding());
+        assert_eq!(
+            runs(&commands)[0],
+            format!("[con_id=101] mark --add {MANAGED_MARK}101")
+        );
+    }
+
+    #[test]
+    fn restores_marked_containers_on_exit() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(
+                NodeLayout::SplitH,
+                [
+                    NodeBuilder::container(NodeLayout::Tabbed, [NodeBuilder::window().focused()])
+                        .id(101)
+                        .mark(&format!("{MANAGED_MARK}101")),
+                    NodeBuilder::container(NodeLayout::SplitV, [NodeBuilder::window()]).id(102),
+                ],
+            ),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+
+        let commands = LayoutEngine::new(Config::default()).restore_layouts(&mut wm);
+        assert_eq!(
+            runs(&commands),
+            [format!(
+                "[con_id=101] layout default, unmark {MANAGED_MARK}101"
+            )]
+        );
+    }
+
+    #[test]
+    fn stacks_windows_on_portrait_outputs() {
+        let tree = TreeBuilder::new((0, 0, 1080, 1920))
+            .workspace(
+                "1",
+                NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+            )
+            .build();
+        let mut wm = FakeConnection::new(tree);
+        let mut engine = LayoutEngine::new(Config {
+            ratio: 2.0,
+            orientation: Some(Orientation::Auto),
+            ..Config::default()
+        });
+
+        let commands = engine.handle_event(&mut wm, &focus_binding());
+        assert_eq!(runs(&commands), ["split vertical"]);
+    }
+
+    #[test]
+    fn stacks_windows_on_rotated_sway_outputs() {
+        let mut tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+            )
+            .build();
+        tree.nodes[0].transform = Some("270".to_owned());
+        let mut wm = FakeConnection::new(tree);
+        let mut engine = LayoutEngine::new(Config {
+            orientation: Some(Orientation::Auto),
+            ..Config::default()
+        });
+
+        let commands = engine.handle_event(&mut wm, &focus_binding());
+        assert_eq!(runs(&commands), ["split vertical"]);
+    }
+
+    #[test]
+    fn follows_the_default_orientation_of_i3() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        wm.config =
+            "# i3 config\nworkspace_layout default\ndefault_orientation vertical\n".to_owned();
+        let mut config = Config::default();
+        Defaults::detect(&mut wm).apply(&mut config);
+
+        let commands = LayoutEngine::new(config).handle_event(&mut wm, &focus_binding());
+        assert_eq!(runs(&commands), ["split vertical"]);
+    }
+
+    #[test]
+    fn decides_again_when_outputs_change() {
+        let tree = TreeBuilder::new((0, 0, 1080, 1920)).workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        let event = Event::Output(OutputEventInfo {
+            change: "unspecified".to_owned(),
+        });
+
+        let commands = LayoutEngine::new(Config::default()).handle_event(&mut wm, &event);
+        assert_eq!(runs(&commands), ["split vertical"]);
+    }
+
+    #[test]
+    fn reports_its_health() {
+        let mut wm = FakeConnection::new(TreeBuilder::default().build());
+        let mut engine = LayoutEngine::new(Config::default());
+
+        let (response, _) = engine.handle_control(&mut wm, control::Command::Health);
+        assert_eq!(response["connected"], true);
+        assert_eq!(response["last_event"], Value::Null);
+        engine.handle_event(&mut wm, &focus_binding());
+        let (response, _) = engine.handle_control(&mut wm, control::Command::Health);
+        assert!(response["last_event"].is_u64());
+    }
+
+    #[test]
+    fn counts_events_and_commands() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        let mut engine = LayoutEngine::new(Config::default());
+
+        let commands = engine.handle_event(&mut wm, &focus_binding());
+        engine.execute(&mut wm, commands);
+        engine
+            .state_mut()
+            .stats
+            .record_latency(Duration::from_millis(2));
+        let (response, _) = engine.handle_control(&mut wm, control::Command::Stats);
+        assert_eq!(response["events"], json!({ "binding": 1 }));
+        assert_eq!(response["commands"], 1);
+        assert_eq!(response["latency_ms"]["p50"], 2.0);
+        assert_eq!(response["latency_histogram"]["buckets"][0], json!([1, 0]));
+        assert_eq!(response["latency_histogram"]["buckets"][1], json!([2, 1]));
+    }
+
+    #[test]
+    fn runs_the_commands_for_clicks() {
+        let tree = TreeBuilder::default().workspace(
+            "1",
+            NodeBuilder::container(NodeLayout::SplitH, [NodeBuilder::window().focused()]),
+        );
+        let mut wm = FakeConnection::new(tree.build());
+        let mut engine = LayoutEngine::new(Config::default());
+        engine.handle_event(&mut wm, &focus_binding());
+
+        assert!(runs(&engine.handle_click(&mut wm, 1)).is_empty());
+        assert_eq!(wm.commands.last().map(String::as_str), Some("split toggle"));
+        assert!(engine.state().is_split_manually(Duration::from_secs(60)));
+        assert!(runs(&engine.handle_click(&mut wm, 5)).is_empty());
+        assert_eq!(engine.state().scheme(None), Some(Scheme::Spiral));
+        engine.handle_click(&mut wm, 4);
+        engine.handle_click(&mut wm, 4);
+        assert_eq!(engine.state().scheme(None), Some(Scheme::MasterStack));
+        engine.handle_click(&mut wm, 3);
+        assert!(engine.state().is_paused(None));
+    }
+
+    #[test]
+    fn splits_the_window_a_click_focuses() {
+        let tree = |focused| {
+            let window = |id| match NodeBuilder::window().id(id) {
+                window if id == focused => window.focused(),
+                window => window,
+            };
+            let column = NodeBuilder::container(NodeLayout::SplitV, [window(101), window(102)]);
+            TreeBuilder::default()
+                .workspace(
+                    "1",
+                    NodeBuilder::container(NodeLayout::SplitH, [window(100), column]),
+                )
+                .build()
+        };
+        let mut wm = FakeConnection::new(tree(100));
+        wm.trees.push_back(tree(101));
+        let mut engine = LayoutEngine::new(Config {
+            clicks: Clicks {
+                middle: Some("focus right".to_owned()),
+                ..Default::default()
+            },
+            ..Config::default()
+        });
+
+        // The tall window on the right is split, not the wide one on the left.
+        let commands = engine.handle_click(&mut wm, 2);
+        assert_eq!(wm.commands, ["focus right"]);
+        assert_eq!(runs(&commands), ["split vertical"]);
+    }
+
+    #[test]
+    fn shows_the_new_scheme_in_the_status() {
+        let tree = TreeBuilder::default().workspace("1", NodeBuilder::window().focused());
+        let mut wm = FakeConnection::new(tree.build());
+        let mut engine = LayoutEngine::new(Config {
+            format: Format {
+                horizontal: "{glyph} {scheme}".to_owned(),
+                ..Format::default()
+            },
+            ..Config::default()
+        });
+
+        let (_, commands) = engine.handle_control(
+            &mut wm,
+            control::Command::Scheme(Some(Scheme::Spiral), None),
+        );
+        assert_eq!(commands[0], Command::Scheme(Scheme::Spiral));
+        assert!(matches!(commands[1..], [Command::Status(_)]));
+    }
+
+    #[test]
+    fn notifies_about_mode_changes() {
+        let tree = TreeBuilder::default().workspace("1", NodeBuilder::window().focused());
+        let mut wm = FakeConnection::new(tree.build());
+        let mut engine = LayoutEngine::new(Config {
+            notify: true,
+            ..Config::default()
+        });
+
+        let (_, commands) =
+            engine.handle_control(&mut wm, control::Command::Pause(Some("2".to_owned())));
+        assert_eq!(
+            commands,
+            [Command::Notify("paused on workspace 2".to_owned())]
+        );
+        let (_, commands) = engine.handle_control(
+            &mut wm,
+            control::Command::Scheme(Some(Scheme::Spiral), None),
+        );
+        assert_eq!(commands, [Command::Notify("spiral scheme".to_owned())]);
+    }
+
+    #[test]
+    fn balances_resized_windows() {
+        let mut tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window().id(100),
+                        NodeBuilder::window().id(101),
+                        NodeBuilder::container(
+                            NodeLayout::SplitV,
+                            [NodeBuilder::window(), NodeBuilder::window().focused()],
+                        ),
+                    ],
+                ),
+            )
+            .build();
+        let workspace = &mut tree.nodes[0].nodes[0];
+        workspace.nodes[0].percent = Some(0.5);
+        workspace.nodes[1].percent = Some(0.25);
+        workspace.nodes[2].percent = Some(0.25);
+        let mut wm = FakeConnection::new(tree);
+
+        let (response, commands) = LayoutEngine::new(Config::default())
+            .handle_control(&mut wm, control::Command::Balance(None));
+        assert_eq!(response["success"], true);
+        assert_eq!(
+            runs(&commands),
+            ["[con_id=100] resize set width 33 ppt; [con_id=101] resize set width 33 ppt"]
+        );
+    }
+
+    #[test]
+    fn transposes_the_workspace() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window(),
+                        NodeBuilder::container(
+                            NodeLayout::SplitV,
+                            [NodeBuilder::window(), NodeBuilder::window().focused()],
+                        )
+                        .id(100),
+                        NodeBuilder::container(NodeLayout::Tabbed, [NodeBuilder::window()]),
+                    ],
+                ),
+            )
+            .build();
+        let workspace = tree.nodes[0].nodes[0].id;
+        let mut wm = FakeConnection::new(tree);
+
+        let mut engine = LayoutEngine::new(Config::default());
+        let (response, commands) =
+            engine.handle_control(&mut wm, control::Command::Transpose(None));
+        assert_eq!(response["success"], true);
+        assert_eq!(
+            runs(&commands),
+            [format!(
+                "[con_id={workspace}] layout splitv; [con_id=100] layout splith"
+            )]
+        );
+        assert!(commands.contains(&Command::Relayout));
+
+        let (_, commands) = engine.handle_control(&mut wm, control::Command::Undo);
+        assert_eq!(
+            runs(&commands),
+            [format!(
+                "[con_id={workspace}] layout splith; [con_id=100] layout splitv"
+            )]
+        );
+    }
+
+    #[test]
+    fn promotes_the_focused_window() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window().id(100),
+                        NodeBuilder::container(
+                            NodeLayout::SplitV,
+                            [
+                                NodeBuilder::window().id(101).focused(),
+                                NodeBuilder::window(),
+                            ],
+                        ),
+                    ],
+                ),
+            )
+            .build();
+        let mut wm = FakeConnection::new(tree);
+        let mut engine = LayoutEngine::new(Config::default());
+
+        let (_, commands) = engine.handle_control(&mut wm, control::Command::Promote);
+        assert_eq!(
+            runs(&commands),
+            ["[con_id=101] swap container with con_id 100"]
+        );
+
+        // The largest window swaps with the next largest.
+        let mut wm = FakeConnection::new(
+            TreeBuilder::default()
+                .workspace(
+                    "1",
+                    NodeBuilder::container(
+                        NodeLayout::SplitH,
+                        [
+                            NodeBuilder::window().id(100).focused(),
+                            NodeBuilder::container(
+                                NodeLayout::SplitV,
+                                [NodeBuilder::window().id(101), NodeBuilder::window()],
+                            ),
+                        ],
+                    ),
+                )
+                .build(),
+        );
+        let (_, commands) = engine.handle_control(&mut wm, control::Command::Promote);
+        assert_eq!(
+            runs(&commands),
+            ["[con_id=100] swap container with con_id 101"]
+        );
+    }
+
+    fn master_stack() -> Config {
+        Config {
+            scheme: Scheme::MasterStack,
+            master_ratio: 0.7,
+            ..Config::default()
+        }
+    }
+
+    /// A workspace of windows side by side, the focused one last.
+    fn side_by_side(ids: &[i64]) -> Node {
+        let mut windows: Vec<_> = ids.iter().map(|&id| NodeBuilder::window().id(id)).collect();
+        if let Some(last) = windows.pop() {
+            windows.push(last.focused());
+        }
+        TreeBuilder::default()
+            .workspace("1", NodeBuilder::container(NodeLayout::SplitH, windows))
+            .build()
+    }
+
+    #[test]
+    fn sizes_the_master_with_the_master_ratio() {
+        let tree = side_by_side(&[100, 101]);
+        let mut wm = FakeConnection::new(tree.clone());
+        let mut engine = LayoutEngine::new(master_stack());
+
+        let commands = engine.handle_event(&mut wm, &new_window(&tree));
+        assert_eq!(runs(&commands), ["[con_id=100] resize set width 70 ppt"]);
+        assert_eq!(engine.state().master("1"), Some(100));
+    }
+
+    #[test]
+    fn keeps_the_master_when_windows_close() {
+        let mut engine = LayoutEngine::new(master_stack());
+        let tree = side_by_side(&[100, 101]);
+        engine.handle_event(&mut FakeConnection::new(tree.clone()), &new_window(&tree));
+
+        // 102 was moved in front of the master, and then 101 closed.
+        let tree = side_by_side(&[102, 100]);
+        let closed = Event::Window(WindowEventInfo {
+            change: WindowChange::Close,
+            container: Box::new(Node {
+                id: 101,
+                ..Node::default()
+            }),
+        });
+        let commands = engine.handle_event(&mut FakeConnection::new(tree), &closed);
+        assert_eq!(runs(&commands), Vec::<&str>::new());
+        assert_eq!(engine.state().master("1"), Some(100));
+
+        // Once the master is gone, the first window takes over.
+        let tree = side_by_side(&[102]);
+        engine.handle_event(&mut FakeConnection::new(tree), &closed);
+        assert_eq!(engine.state().master("1"), Some(102));
+    }
+
+    #[test]
+    fn promoting_swaps_the_master() {
+        let mut engine = LayoutEngine::new(master_stack());
+        let tree = side_by_side(&[100, 101]);
+        let mut wm = FakeConnection::new(tree.clone());
+        engine.handle_event(&mut wm, &new_window(&tree));
+
+        let (_, commands) = engine.handle_control(&mut wm, control::Command::Promote);
+        assert_eq!(
+            runs(&commands),
+            ["[con_id=101] swap container with con_id 100"]
+        );
+        assert_eq!(engine.state().master("1"), Some(101));
+    }
+
+    #[test]
+    fn explaining_leaves_the_master_alone() {
+        let engine = LayoutEngine::new(master_stack());
+        let tree = side_by_side(&[100, 101]);
+
+        assert!(engine.explain(&tree, 101).is_some());
+        assert_eq!(engine.state().master("1"), None);
+    }
+
+    #[test]
+    fn flattens_containers_left_with_one_window() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window(),
+                        NodeBuilder::container(
+                            NodeLayout::SplitV,
+                            [NodeBuilder::container(
+                                NodeLayout::SplitH,
+                                [NodeBuilder::window().id(100)],
+                            )],
+                        ),
+                        // Just split, waiting for the next window.
+                        NodeBuilder::container(
+                            NodeLayout::SplitV,
+                            [NodeBuilder::window().id(101).focused()],
+                        ),
+                    ],
+                ),
+            )
+            .build();
+        let mut wm = FakeConnection::new(tree);
+        let event = Event::Window(WindowEventInfo {
+            change: WindowChange::Close,
+            container: Box::new(Node {
+                id: 102,
+                ..Node::default()
+            }),
+        });
+
+        let commands = LayoutEngine::new(Config::default()).handle_event(&mut wm, &event);
+        assert_eq!(
+            runs(&commands),
+            ["[con_id=100] move up; [con_id=100] move left"]
+        );
+        assert!(commands.contains(&Command::Relayout));
+    }
+
+    #[test]
+    fn moves_deeply_nested_windows_up() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window().focused(),
+                        NodeBuilder::container(
+                            NodeLayout::SplitV,
+                            [
+                                NodeBuilder::window(),
+                                NodeBuilder::container(
+                                    NodeLayout::SplitH,
+                                    [NodeBuilder::window().id(100), NodeBuilder::window()],
+                                ),
+                            ],
+                        ),
+                    ],
+                ),
+            )
+            .build();
+        let config = Config {
+            max_depth: Some(2),
+            ..Config::default()
+        };
+        let mut wm = FakeConnection::new(tree.clone());
+
+        let commands = LayoutEngine::new(config).handle_event(&mut wm, &new_window(&tree));
+        assert_eq!(runs(&commands), ["[con_id=100] move up"]);
+    }
+
+    #[test]
+    fn restores_saved_layouts() {
+        let saved = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window().class("firefox"),
+                        NodeBuilder::container(
+                            NodeLayout::SplitV,
+                            [
+                                NodeBuilder::window().class("xterm"),
+                                NodeBuilder::window().class("xterm"),
+                            ],
+                        ),
+                    ],
+                ),
+            )
+            .build();
+        let layout = Layout::of_workspace(&saved.nodes[0].nodes[0]);
+        assert_eq!(layout.layout.as_deref(), Some("splith"));
+        assert_eq!(layout.nodes[1].nodes.len(), 2);
+
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitV,
+                    [
+                        NodeBuilder::window().id(100).class("xterm").focused(),
+                        NodeBuilder::window().id(101).class("firefox"),
+                    ],
+                ),
+            )
+            .build();
+        let workspace = &tree.nodes[0].nodes[0];
+        let (contents, commands) =
+            layout.restore(workspace, std::path::Path::new("/tmp/layout.json"));
+        assert_eq!(
+            commands,
+            [
+                format!("[con_id={}] layout splith", workspace.id),
+                r#"append_layout "/tmp/layout.json""#.to_owned(),
+                "[con_id=101] move window to mark _alternating_layout_restore_0".to_owned(),
+                r#"[con_mark="^_alternating_layout_restore_0$"] kill"#.to_owned(),
+                "[con_id=100] move window to mark _alternating_layout_restore_1".to_owned(),
+                r#"[con_mark="^_alternating_layout_restore_1$"] kill"#.to_owned(),
+            ]
+        );
+        // One placeholder is left for the next xterm.
+        assert_eq!(contents.lines().count(), 2);
+        assert_eq!(contents.matches("swallows").count(), 3);
+        assert_eq!(contents.matches("marks").count(), 2);
+    }
+
+    #[test]
+    fn rotates_the_focused_container() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window().id(100),
+                        NodeBuilder::window().id(101).focused(),
+                        NodeBuilder::window().id(102),
+                    ],
+                ),
+            )
+            .build();
+        let mut wm = FakeConnection::new(tree);
+
+        let (response, commands) =
+            LayoutEngine::new(Config::default()).handle_control(&mut wm, control::Command::Rotate);
+        assert_eq!(response["success"], true);
+        assert_eq!(
+            runs(&commands),
+            ["[con_id=102] swap container with con_id 101; \
+              [con_id=102] swap container with con_id 100"]
+        );
+    }
+
+    #[test]
+    fn keeps_overrides_across_restarts() {
+        let mut wm = FakeConnection::default();
+        let mut engine = LayoutEngine::new(Config::default());
+        for command in ["pause 2", "scheme spiral 3", "scheme dwindle"] {
+            engine.handle_control(&mut wm, command.parse().unwrap());
+        }
+        let overrides = engine.state().overrides();
+        let saved = serde_json::to_string(&overrides).unwrap();
+
+        let mut restarted = LayoutEngine::new(Config::default());
+        restarted
+            .state_mut()
+            .set_overrides(serde_json::from_str(&saved).unwrap());
+        assert_eq!(restarted.state().overrides(), overrides);
+        assert!(restarted.state().is_paused(Some("2")));
+        assert_eq!(restarted.state().scheme(Some("4")), Some(Scheme::Dwindle));
+    }
+
+    #[test]
+    fn undoes_splits() {
+        let tree = TreeBuilder::default()
+            .workspace(
+                "1",
+                NodeBuilder::container(
+                    NodeLayout::SplitH,
+                    [
+                        NodeBuilder::window(),
+                        NodeBuilder::window().id(100).focused(),
+                    ],
+                ),
+            )
+            .build();
+        let mut wm = FakeConnection::new(tree.clone());
+        let mut engine = LayoutEngine::new(Config::default());
+        assert_eq!(
+            runs(&engine.handle_event(&mut wm, &focus_binding())),
+            ["split horizontal"]
+        );
+
+        // The window is in a container of its own now, which it is moved out of.
+        let (parent, split) = (&tree.nodes[0].nodes[0], &mut wm.tree.nodes[0].nodes[0]);
+        let window = split.nodes.pop().unwrap();
+        split.nodes.push(Node {
+            id: 101,
+            layout: NodeLayout::SplitH,
+            nodes: vec![window],
+            ..parent.nodes[1].clone()
+        });
+        let (response, commands) = engine.handle_control(&mut wm, control::Command::Undo);
+        assert_eq!(response["success"], true);
+        assert_eq!(runs(&commands), ["[con_id=100] move left"]);
+
+        let (response, _) = engine.handle_control(&mut wm, control::Command::Undo);
+        assert_eq!(response["success"], false);
+    }
+
+    #[test]
+    fn execution_stops_at_a_failed_command() {
+        let mut wm = FakeConnection {
+            reject: Some("bogus".to_owned()),
+            ..FakeConnection::default()
+        };
+        let commands = ["split vertical", "bogus", "split horizontal"]
+            .map(|command| Command::Run(command.to_owned()))
+            .into();
+
+        assert_eq!(
+            LayoutEngine::new(Config::default()).execute(&mut wm, commands),
+            None
+        );
+        assert_eq!(wm.commands, ["split vertical", "bogus"]);
+    }
+
+    #[test]
+    fn retries_interrupted_commands() {
+        let mut wm = FakeConnection {
+            interruptions: 1,
+            ..FakeConnection::default()
+        };
+        let commands = vec![Command::Run("split vertical".to_owned())];
+        let mut engine = LayoutEngine::new(Config::default());
+
+        assert_eq!(engine.execute(&mut wm, commands), Some(()));
+        assert_eq!(wm.commands, ["split vertical"]);
+        assert_eq!(wm.reconnects, 1);
+
+        // Only once, so a lasting problem isn't retried for ever.
+        wm.interruptions = 2;
+        let commands = vec![Command::Run("split horizontal".to_owned())];
+        assert_eq!(engine.execute(&mut wm, commands), None);
+        assert_eq!(wm.commands, ["split vertical"]);
+        assert_eq!(wm.reconnects, 2);
+    }
+
+    #[test]
+    fn splits_chained_binding_commands() {
+        assert_eq!(
+            split_commands(r#"split v; exec "a; b", focus left"#),
+            ["split v", r#"exec "a; b""#, "focus left"]
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! User scripts run when the daemon changes a layout or the status changes, see
+//! `config::Hooks`.
+//!
+//! Hooks run through `sh -c` with the details in `ALT_*` environment variables, and aren't
+//! waited for, so a slow script can't hold up the daemon.
+
+use std::{
+    process::{Command, Stdio},
+    thread,
+};
+
+use log::{debug, error, warn};
+
+/// Runs `hook` with `vars` in its environment.
+pub fn run(hook: &str, vars: &[(&str, String)]) {
+    debug!("running hook '{hook}' with {vars:?}");
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .envs(vars.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn();
+    match child {
+        // Reaped from a thread of its own.
+        Ok(mut child) => {
+            let hook = hook.to_owned();
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => warn!("hook '{hook}' failed: {status}"),
+                Ok(_) => {}
+                Err(e) => warn!("couldn't wait for hook '{hook}': {e}"),
+            });
+        }
+        Err(e) => error!("couldn't run hook '{hook}': {e}"),
+    }
+}
@@ -0,0 +1,65 @@
+//! Makes sure a single daemon manages each i3 instance, as two would fight over every window.
+//!
+//! The running daemon holds an abstract socket named after the IPC socket of its i3, which the
+//! kernel frees as soon as the daemon exits. A daemon started with `--replace` connects to it to
+//! ask the running one to exit, and takes over once it has.
+
+use std::{
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::mpsc::Sender,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::Input;
+
+/// How long to wait for the running daemon to exit when replacing it.
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Claims the i3 instance behind `wm_socket`, replacing the daemon managing it if `replace` is
+/// set. `Input::Replace` is sent to `inputs` once another daemon takes over.
+pub fn lock(wm_socket: &Path, replace: bool, inputs: Sender<Input>) -> io::Result<()> {
+    let name = format!("i3-alternating-layout:{}", wm_socket.display());
+    let address = SocketAddr::from_abstract_name(name.as_bytes())?;
+    let listener = match UnixListener::bind_addr(&address) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse && replace => {
+            UnixStream::connect_addr(&address)?;
+            let deadline = Instant::now() + REPLACE_TIMEOUT;
+            loop {
+                match UnixListener::bind_addr(&address) {
+                    Err(e) if e.kind() == io::ErrorKind::AddrInUse && Instant::now() < deadline => {
+                        thread::sleep(Duration::from_millis(50))
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "the running instance didn't exit",
+                        ))
+                    }
+                    listener => break listener?,
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another instance is managing this i3, pass --replace to take over",
+            ))
+        }
+        listener => listener?,
+    };
+
+    // The listener is kept until the process exits, so the lock is held while shutting down.
+    thread::spawn(move || {
+        for _ in listener.incoming().flatten() {
+            let _ = inputs.send(Input::Replace);
+        }
+    });
+
+    Ok(())
+}
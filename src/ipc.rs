@@ -0,0 +1,128 @@
+//! The connection to the window manager, and what the compositors it can talk to share. Each
+//! compositor is a module implementing `Backend`: `i3` for i3 and sway, which speak the same
+//! protocol, and `hyprland`.
+//!
+//! Replies and events are deserialized leniently: unknown fields are ignored and unknown enum
+//! values map to an `Unknown` variant, so newer i3 releases and sway's extensions never break
+//! parsing.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+pub mod event;
+#[doc(hidden)]
+pub mod fake;
+#[cfg(feature = "hyprland")]
+pub mod hyprland;
+pub mod i3;
+pub mod reply;
+
+pub use i3::{Compositor, I3Connection, I3EventListener};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The compositor didn't report a socket path.
+    SocketPath(String),
+    /// A command or subscription was rejected.
+    Rejected(String),
+    /// A request couldn't be sent for a reason that may pass, before any of it reached the
+    /// compositor.
+    Unsent(io::Error),
+    UnknownEvent(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IPC error: {e}"),
+            Error::Json(e) => write!(f, "malformed IPC message: {e}"),
+            Error::SocketPath(e) => write!(f, "couldn't determine the socket path: {e}"),
+            Error::Rejected(e) => write!(f, "request rejected: {e}"),
+            Error::Unsent(e) => write!(f, "couldn't send the request: {e}"),
+            Error::UnknownEvent(event_type) => write!(f, "unknown event type {event_type}"),
+        }
+    }
+}
+
+impl Error {
+    /// Whether the request may succeed when sent again, which can't run it twice as the
+    /// compositor never got it.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Unsent(_))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// What the daemon needs from the window manager, so it can be swapped for the in-memory
+/// `fake::FakeConnection`.
+pub trait WmConnection {
+    /// The events of a subscription.
+    type Events: Iterator<Item = Result<event::Event, Error>> + Send + 'static;
+
+    fn get_tree(&mut self) -> Result<reply::Node, Error>;
+
+    fn run_command(&mut self, command: &str) -> Result<(), Error>;
+
+    fn get_version(&mut self) -> Result<reply::Version, Error>;
+
+    /// Connects afresh, e.g. after a request couldn't be sent on the current connection.
+    fn reconnect(&mut self) -> Result<(), Error>;
+
+    /// The contents of the config file the compositor loaded last.
+    fn get_config(&mut self) -> Result<String, Error>;
+
+    /// Subscribes to `events`, which arrive separately from the replies to requests.
+    fn subscribe(&mut self, events: &[Subscription]) -> Result<Self::Events, Error>;
+}
+
+/// A compositor the daemon can run on: events in through `subscribe`, commands out through
+/// `run_command` and the layout as i3's tree through `get_tree`. The daemon makes the
+/// connection itself, and makes it again if it is lost.
+pub trait Backend: WmConnection + Sized {
+    /// Connects to the socket at `path`, or to that of the detected compositor.
+    fn connect(path: Option<&Path>) -> Result<Self, Error>;
+
+    /// The socket `connect` connects to for `path`, without connecting.
+    fn socket_path(path: Option<&Path>) -> Result<PathBuf, Error>;
+
+    /// The socket the connection was made to.
+    fn path(&self) -> &Path;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subscription {
+    Output,
+    Mode,
+    Window,
+    Binding,
+    Shutdown,
+    Tick,
+}
+
+impl Subscription {
+    fn name(self) -> &'static str {
+        match self {
+            Subscription::Output => "output",
+            Subscription::Mode => "mode",
+            Subscription::Window => "window",
+            Subscription::Binding => "binding",
+            Subscription::Shutdown => "shutdown",
+            Subscription::Tick => "tick",
+        }
+    }
+}
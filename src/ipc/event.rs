@@ -0,0 +1,112 @@
+//! Events from i3, as far as this crate subscribes to them.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::reply::Node;
+
+/// Serialized by name, e.g. `{"window": {...}}`, for recordings.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Output(OutputEventInfo),
+    Mode(ModeEventInfo),
+    Window(WindowEventInfo),
+    Binding(BindingEventInfo),
+    Shutdown(ShutdownEventInfo),
+    Tick(TickEventInfo),
+}
+
+impl Event {
+    /// The name of the event type, as i3 has it for subscribing.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Output(_) => "output",
+            Event::Mode(_) => "mode",
+            Event::Window(_) => "window",
+            Event::Binding(_) => "binding",
+            Event::Shutdown(_) => "shutdown",
+            Event::Tick(_) => "tick",
+        }
+    }
+}
+
+/// Sent when outputs are added, removed or changed, e.g. on docking.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OutputEventInfo {
+    /// Always `unspecified`.
+    pub change: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModeEventInfo {
+    /// The name of the binding mode that was entered, `default` when leaving the others.
+    pub change: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WindowEventInfo {
+    pub change: WindowChange,
+    /// The window the event is about.
+    pub container: Box<Node>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowChange {
+    New,
+    Close,
+    Focus,
+    Title,
+    FullscreenMode,
+    Move,
+    Floating,
+    Urgent,
+    Mark,
+    #[serde(other)]
+    Unknown,
+}
+
+impl FromStr for WindowChange {
+    type Err = String;
+
+    /// Parses the name i3 uses, e.g. `fullscreen_mode`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match serde_json::from_value(s.into()) {
+            Ok(WindowChange::Unknown) | Err(_) => Err(format!("unknown window change '{s}'")),
+            Ok(change) => Ok(change),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BindingEventInfo {
+    pub binding: Binding,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Binding {
+    pub command: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ShutdownEventInfo {
+    pub change: ShutdownChange,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownChange {
+    Restart,
+    Exit,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TickEventInfo {
+    /// The payload given to `send_tick`, empty for the tick sent after subscribing.
+    #[serde(default)]
+    pub payload: String,
+}
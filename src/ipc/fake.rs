@@ -0,0 +1,282 @@
+//! An in-memory stand-in for i3 and a builder for its layout trees, for testing the daemon
+//! without a running window manager. Public only for the integration tests and benchmarks, which
+//! can't see `cfg(test)` items.
+
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    vec,
+};
+
+use super::{
+    event::Event,
+    reply::{Node, NodeLayout, NodeType, Version, WindowProperties},
+    Error, Subscription, WmConnection,
+};
+
+/// Serves a fixed tree and events, and records the commands it is sent.
+#[derive(Debug, Default)]
+pub struct FakeConnection {
+    pub tree: Node,
+    /// The trees the next commands leave behind, replacing `tree` one per command.
+    pub trees: VecDeque<Node>,
+    /// The contents of the config file of the fake i3.
+    pub config: String,
+    /// The events the next subscription receives.
+    pub events: VecDeque<Event>,
+    /// The commands run so far, in order.
+    pub commands: Vec<String>,
+    pub subscriptions: Vec<Subscription>,
+    /// Commands containing this are rejected, like i3 rejects invalid ones.
+    pub reject: Option<String>,
+    /// How many of the next commands can't be sent, as if a signal interrupted writing them.
+    pub interruptions: u32,
+    /// How often the daemon connected afresh.
+    pub reconnects: u32,
+}
+
+impl FakeConnection {
+    pub fn new(tree: Node) -> Self {
+        FakeConnection {
+            tree,
+            ..FakeConnection::default()
+        }
+    }
+}
+
+impl WmConnection for FakeConnection {
+    type Events = vec::IntoIter<Result<Event, Error>>;
+
+    fn get_tree(&mut self) -> Result<Node, Error> {
+        Ok(self.tree.clone())
+    }
+
+    fn run_command(&mut self, command: &str) -> Result<(), Error> {
+        if self.interruptions > 0 {
+            self.interruptions -= 1;
+            return Err(Error::Unsent(io::Error::from(ErrorKind::Interrupted)));
+        }
+        self.commands.push(command.to_owned());
+        if let Some(tree) = self.trees.pop_front() {
+            self.tree = tree;
+        }
+        match &self.reject {
+            Some(rejected) if command.contains(rejected.as_str()) => {
+                Err(Error::Rejected(format!("invalid command '{command}'")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn get_version(&mut self) -> Result<Version, Error> {
+        Ok(Version {
+            human_readable: "fake".to_owned(),
+            ..Version::default()
+        })
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.reconnects += 1;
+        Ok(())
+    }
+
+    fn get_config(&mut self) -> Result<String, Error> {
+        Ok(self.config.clone())
+    }
+
+    fn subscribe(&mut self, events: &[Subscription]) -> Result<Self::Events, Error> {
+        self.subscriptions.extend_from_slice(events);
+        let events: Vec<_> = self.events.drain(..).map(Ok).collect();
+        Ok(events.into_iter())
+    }
+}
+
+/// Builds a container for `TreeBuilder`. Ids, rects, percents and focus lists are filled in when
+/// the tree is built.
+#[derive(Debug, Clone)]
+pub struct NodeBuilder(Node);
+
+impl NodeBuilder {
+    /// A window.
+    pub fn window() -> Self {
+        NodeBuilder(Node {
+            nodetype: NodeType::Con,
+            ..Node::default()
+        })
+    }
+
+    /// A split, tabbed or stacked container holding `children`.
+    pub fn container(layout: NodeLayout, children: impl IntoIterator<Item = NodeBuilder>) -> Self {
+        let mut builder = NodeBuilder::window();
+        builder.0.layout = layout;
+        builder.0.nodes = children.into_iter().map(|child| child.0).collect();
+        builder
+    }
+
+    pub fn id(mut self, id: i64) -> Self {
+        self.0.id = id;
+        self
+    }
+
+    /// Focuses the window. Without a focused window, nothing is focused.
+    pub fn focused(mut self) -> Self {
+        self.0.focused = true;
+        self
+    }
+
+    pub fn class(mut self, class: &str) -> Self {
+        self.0.window_properties = Some(WindowProperties {
+            class: Some(class.to_owned()),
+            ..WindowProperties::default()
+        });
+        self
+    }
+
+    pub fn window_type(mut self, window_type: &str) -> Self {
+        self.0.window_type = Some(window_type.to_owned());
+        self
+    }
+
+    pub fn mark(mut self, mark: &str) -> Self {
+        self.0.marks.push(mark.to_owned());
+        self
+    }
+
+    pub fn fullscreen(mut self) -> Self {
+        self.0.fullscreen_mode = 1;
+        self
+    }
+
+    /// Adds `window` floating above the container, wrapped in a floating container like i3
+    /// does.
+    pub fn floating(mut self, window: NodeBuilder) -> Self {
+        let mut wrapper = NodeBuilder::container(NodeLayout::SplitH, [window]).0;
+        wrapper.nodetype = NodeType::FloatingCon;
+        wrapper.floating = Some("user_on".to_owned());
+        self.0.floating_nodes.push(wrapper);
+        self
+    }
+}
+
+/// Builds layout trees like i3's, with a root, one output and its workspaces.
+#[derive(Debug, Clone)]
+pub struct TreeBuilder {
+    rect: (i32, i32, i32, i32),
+    workspaces: Vec<Node>,
+}
+
+impl Default for TreeBuilder {
+    fn default() -> Self {
+        TreeBuilder::new((0, 0, 1920, 1080))
+    }
+}
+
+/// The height of title bars and tabs.
+const DECORATION: i32 = 20;
+
+impl TreeBuilder {
+    /// A tree with an output covering `rect`, as (x, y, width, height).
+    pub fn new(rect: (i32, i32, i32, i32)) -> Self {
+        TreeBuilder {
+            rect,
+            workspaces: Vec::new(),
+        }
+    }
+
+    /// Adds a workspace made of `container`.
+    pub fn workspace(mut self, name: &str, container: NodeBuilder) -> Self {
+        let NodeBuilder(mut workspace) = container;
+        workspace.nodetype = NodeType::Workspace;
+        workspace.name = Some(name.to_owned());
+        self.workspaces.push(workspace);
+        self
+    }
+
+    pub fn build(self) -> Node {
+        let output = Node {
+            nodetype: NodeType::Output,
+            layout: NodeLayout::Output,
+            name: Some("eDP-1".to_owned()),
+            nodes: self.workspaces,
+            ..Node::default()
+        };
+        let mut root = Node {
+            nodetype: NodeType::Root,
+            name: Some("root".to_owned()),
+            nodes: vec![output],
+            ..Node::default()
+        };
+
+        let mut next_id = 1;
+        lay_out(&mut root, self.rect, &mut next_id);
+        // The workspaces of an output are all as large as it is.
+        for workspace in &mut root.nodes[0].nodes {
+            lay_out(workspace, self.rect, &mut next_id);
+        }
+        focus(&mut root);
+        root
+    }
+}
+
+/// Gives `node` and the nodes below it their rects and percents, and ids unless they have one.
+fn lay_out(node: &mut Node, rect: (i32, i32, i32, i32), next_id: &mut i64) {
+    if node.id == 0 {
+        node.id = *next_id;
+        *next_id += 1;
+    }
+    node.rect = rect;
+    if node.nodetype == NodeType::Output {
+        return;
+    }
+
+    let (x, y, width, height) = rect;
+    let count = node.nodes.len() as i32;
+    let layout = node.layout.clone();
+    for (i, child) in node.nodes.iter_mut().enumerate() {
+        let i = i as i32;
+        let rect = match layout {
+            NodeLayout::SplitV => (x, y + height * i / count, width, height / count),
+            NodeLayout::Tabbed | NodeLayout::Stacked => {
+                let offset = match layout {
+                    NodeLayout::Stacked => DECORATION * count,
+                    _ => DECORATION,
+                };
+                child.deco_rect = (0, 0, width, DECORATION);
+                (x, y + offset, width, height - offset)
+            }
+            _ => (x + width * i / count, y, width / count, height),
+        };
+        child.percent = Some(1.0 / f64::from(count));
+        lay_out(child, rect, next_id);
+    }
+    for floating in &mut node.floating_nodes {
+        let (width, height) = (width / 2, height / 2);
+        lay_out(
+            floating,
+            (x + width / 2, y + height / 2, width, height),
+            next_id,
+        );
+    }
+}
+
+/// Fills in the focus lists, most recently focused first for the branch holding the focused
+/// window. Returns whether it is below `node`.
+fn focus(node: &mut Node) -> bool {
+    let mut focused = None;
+    for child in node.nodes.iter_mut().chain(&mut node.floating_nodes) {
+        if focus(child) {
+            focused = Some(child.id);
+        }
+    }
+    node.focus = node
+        .nodes
+        .iter()
+        .chain(&node.floating_nodes)
+        .map(|child| child.id)
+        .collect();
+    if let Some(id) = focused {
+        node.focus.retain(|&child| child != id);
+        node.focus.insert(0, id);
+    }
+    node.focused || focused.is_some()
+}
@@ -0,0 +1,536 @@
+//! A connection to Hyprland, which looks like i3 to the rest of the crate. Only built with the
+//! `hyprland` feature.
+//!
+//! Hyprland has no layout tree, so `get_tree` makes one up from its monitors, workspaces and
+//! clients, with the windows of each workspace side by side. The split commands of the engine
+//! become preselections of the dwindle layout and the window events of its event socket become
+//! i3's, so the decisions are the same as on i3.
+//!
+//! Hyprland has no marks, so the commands marking containers are left out. Only splits have an
+//! equivalent: tabbed and stacked layouts, as with `tabbed_after` and the layouts of rules, and
+//! the moves, resizes and swaps of restructuring and of control commands like `balance` fail
+//! with an error.
+
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+
+use super::{
+    event::{Event, ModeEventInfo, OutputEventInfo, WindowChange, WindowEventInfo},
+    reply::{Node, NodeLayout, NodeType, Version},
+    Backend, Error, Subscription, WmConnection,
+};
+
+/// Node ids of workspaces are this plus the workspace id, so they can't clash with the
+/// addresses of windows or the ids of monitors.
+const WORKSPACE_IDS: i64 = -(1 << 32);
+
+/// Whether the daemon runs inside Hyprland, which exports its instance to its children.
+pub fn is_running() -> bool {
+    env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some_and(|signature| !signature.is_empty())
+}
+
+/// The directory of the sockets of the running instance, in `XDG_RUNTIME_DIR` since 0.40 and in
+/// `/tmp` before.
+fn socket_dir() -> Result<PathBuf, Error> {
+    let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE")
+        .filter(|signature| !signature.is_empty())
+        .ok_or_else(|| Error::SocketPath("HYPRLAND_INSTANCE_SIGNATURE isn't set".to_owned()))?;
+    let runtime = env::var_os("XDG_RUNTIME_DIR").map(|dir| Path::new(&dir).join("hypr"));
+    runtime
+        .into_iter()
+        .chain([PathBuf::from("/tmp/hypr")])
+        .map(|dir| dir.join(&signature))
+        .find(|dir| dir.join(".socket.sock").exists())
+        .ok_or_else(|| Error::SocketPath("Hyprland's sockets don't exist".to_owned()))
+}
+
+/// Runs requests on the command socket of Hyprland, which takes one per connection.
+pub struct HyprlandConnection {
+    dir: PathBuf,
+    /// The command socket, for claiming the instance.
+    path: PathBuf,
+}
+
+impl HyprlandConnection {
+    /// Connects to the instance whose sockets are in `dir`.
+    fn connect_to(dir: PathBuf) -> Result<Self, Error> {
+        let path = dir.join(".socket.sock");
+        // Requests connect afresh, so only check that Hyprland is listening.
+        UnixStream::connect(&path)?;
+        Ok(HyprlandConnection { dir, path })
+    }
+
+    fn request(&self, request: &str) -> Result<String, Error> {
+        let mut stream = UnixStream::connect(&self.path).map_err(Error::Unsent)?;
+        stream.write_all(request.as_bytes())?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        Ok(reply)
+    }
+
+    fn query<T: DeserializeOwned>(&self, request: &str) -> Result<T, Error> {
+        Ok(serde_json::from_str(
+            &self.request(&format!("j/{request}"))?,
+        )?)
+    }
+}
+
+impl Backend for HyprlandConnection {
+    /// `path` is the directory of the sockets of the instance.
+    fn connect(path: Option<&Path>) -> Result<Self, Error> {
+        match path {
+            Some(dir) => Self::connect_to(dir.to_owned()),
+            None => Self::connect_to(socket_dir()?),
+        }
+    }
+
+    fn socket_path(path: Option<&Path>) -> Result<PathBuf, Error> {
+        let dir = match path {
+            Some(dir) => dir.to_owned(),
+            None => socket_dir()?,
+        };
+        Ok(dir.join(".socket.sock"))
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Monitor {
+    id: i64,
+    name: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    /// The space bars take up, as left, top, right and bottom.
+    reserved: [i32; 4],
+    focused: bool,
+    #[serde(rename = "activeWorkspace")]
+    active_workspace: WorkspaceRef,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct WorkspaceRef {
+    id: i64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Workspace {
+    id: i64,
+    name: String,
+    monitor: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Client {
+    address: String,
+    mapped: bool,
+    at: [i32; 2],
+    size: [i32; 2],
+    workspace: WorkspaceRef,
+    floating: bool,
+    class: String,
+    title: String,
+    /// A bool before 0.42 and the fullscreen mode since.
+    fullscreen: Value,
+    /// How long ago the window was focused, 0 for the focused one.
+    #[serde(rename = "focusHistoryID")]
+    focus_history_id: i64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ActiveWindow {
+    address: String,
+}
+
+/// The id of the window at `address`, which is hex with or without `0x`.
+fn window_id(address: &str) -> i64 {
+    i64::from_str_radix(address.trim_start_matches("0x"), 16).unwrap_or_default()
+}
+
+impl Client {
+    fn node(&self, focused: bool) -> Node {
+        let [x, y] = self.at;
+        let [width, height] = self.size;
+        let fullscreen = match &self.fullscreen {
+            Value::Bool(fullscreen) => *fullscreen,
+            mode => mode.as_u64().is_some_and(|mode| mode != 0),
+        };
+        Node {
+            id: window_id(&self.address),
+            name: Some(self.title.clone()),
+            nodetype: if self.floating {
+                NodeType::FloatingCon
+            } else {
+                NodeType::Con
+            },
+            rect: (x, y, width, height),
+            window_rect: (0, 0, width, height),
+            focused,
+            fullscreen_mode: u8::from(fullscreen),
+            app_id: Some(self.class.clone()),
+            ..Node::default()
+        }
+    }
+}
+
+/// i3's tree for the state of Hyprland: monitors become outputs with their workspaces, each
+/// holding its windows side by side. Special workspaces are left out.
+fn tree(
+    monitors: &[Monitor],
+    workspaces: &[Workspace],
+    mut clients: Vec<Client>,
+    active: &ActiveWindow,
+) -> Node {
+    // Most recently focused first, as in the focus lists of i3.
+    clients.sort_by_key(|client| client.focus_history_id);
+    let outputs = monitors.iter().map(|monitor| {
+        let [left, top, right, bottom] = monitor.reserved;
+        let rect = (
+            monitor.x + left,
+            monitor.y + top,
+            monitor.width - left - right,
+            monitor.height - top - bottom,
+        );
+        let workspaces = workspaces
+            .iter()
+            .filter(|ws| ws.monitor == monitor.name && ws.id > 0)
+            .map(|ws| {
+                let windows: Vec<_> = clients
+                    .iter()
+                    .filter(|client| client.mapped && client.workspace.id == ws.id)
+                    .collect();
+                let has_focus = windows
+                    .iter()
+                    .any(|client| client.address == active.address);
+                let (floating, tiled): (Vec<_>, Vec<_>) =
+                    windows.iter().partition(|client| client.floating);
+                let node = |client: &&&Client| client.node(client.address == active.address);
+                Node {
+                    id: WORKSPACE_IDS + ws.id,
+                    name: Some(ws.name.clone()),
+                    nodetype: NodeType::Workspace,
+                    layout: NodeLayout::SplitH,
+                    rect,
+                    focus: windows
+                        .iter()
+                        .map(|client| window_id(&client.address))
+                        .collect(),
+                    // Like on i3, an empty workspace is focused itself.
+                    focused: monitor.focused && monitor.active_workspace.id == ws.id && !has_focus,
+                    nodes: tiled.iter().map(node).collect(),
+                    floating_nodes: floating.iter().map(node).collect(),
+                    ..Node::default()
+                }
+            })
+            .collect();
+        Node {
+            id: -1 - monitor.id,
+            name: Some(monitor.name.clone()),
+            nodetype: NodeType::Output,
+            layout: NodeLayout::Output,
+            rect: (monitor.x, monitor.y, monitor.width, monitor.height),
+            focus: vec![WORKSPACE_IDS + monitor.active_workspace.id],
+            nodes: workspaces,
+            ..Node::default()
+        }
+    });
+    Node {
+        name: Some("root".to_owned()),
+        nodetype: NodeType::Root,
+        focus: monitors
+            .iter()
+            .filter(|monitor| monitor.focused)
+            .map(|monitor| -1 - monitor.id)
+            .collect(),
+        nodes: outputs.collect(),
+        ..Node::default()
+    }
+}
+
+impl WmConnection for HyprlandConnection {
+    type Events = HyprlandEvents;
+
+    fn get_tree(&mut self) -> Result<Node, Error> {
+        Ok(tree(
+            &self.query::<Vec<Monitor>>("monitors")?,
+            &self.query::<Vec<Workspace>>("workspaces")?,
+            self.query("clients")?,
+            &self.query("activewindow")?,
+        ))
+    }
+
+    /// Runs the Hyprland equivalents of the i3 commands in `command`, failing at the first
+    /// without one.
+    fn run_command(&mut self, command: &str) -> Result<(), Error> {
+        for command in command.split(';').map(str::trim) {
+            if command.is_empty() || command.starts_with("nop") {
+                continue;
+            }
+            let action = command
+                .strip_prefix('[')
+                .and_then(|command| command.split_once(']'))
+                .map_or(command, |(_, action)| action.trim_start());
+            if action.starts_with("mark ") || action.starts_with("unmark") {
+                debug!("leaving out '{command}', as Hyprland has no marks");
+                continue;
+            }
+            let dispatch = match command {
+                "split vertical" | "split v" | "splitv" => "layoutmsg preselect d",
+                "split horizontal" | "split h" | "splith" => "layoutmsg preselect r",
+                "split toggle" | "split t" => "layoutmsg togglesplit",
+                _ => {
+                    return Err(Error::Rejected(format!(
+                        "'{command}' has no Hyprland equivalent"
+                    )))
+                }
+            };
+            let reply = self.request(&format!("dispatch {dispatch}"))?;
+            if reply.trim() != "ok" {
+                return Err(Error::Rejected(reply.trim().to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_version(&mut self) -> Result<Version, Error> {
+        #[derive(Deserialize)]
+        struct HyprlandVersion {
+            tag: String,
+        }
+
+        let version: HyprlandVersion = self.query("version")?;
+        let mut numbers = version
+            .tag
+            .trim_start_matches('v')
+            .split(['.', '-'])
+            .map(|n| n.parse().unwrap_or_default());
+        Ok(Version {
+            major: numbers.next().unwrap_or_default(),
+            minor: numbers.next().unwrap_or_default(),
+            patch: numbers.next().unwrap_or_default(),
+            human_readable: version.tag,
+            variant: Some("hyprland".to_owned()),
+            loaded_config_file_name: None,
+        })
+    }
+
+    /// Every request connects afresh anyway.
+    fn reconnect(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Hyprland doesn't hand out its config, so the defaults stay as they are.
+    fn get_config(&mut self) -> Result<String, Error> {
+        Ok(String::new())
+    }
+
+    /// Hyprland sends all events on its event socket, so `events` is only filtered by what can
+    /// be mapped to i3's.
+    fn subscribe(&mut self, _: &[Subscription]) -> Result<HyprlandEvents, Error> {
+        let stream = UnixStream::connect(self.dir.join(".socket2.sock"))?;
+        Ok(HyprlandEvents {
+            lines: BufReader::new(stream),
+        })
+    }
+}
+
+/// The events of Hyprland's event socket that i3 has an equivalent for.
+pub struct HyprlandEvents {
+    lines: BufReader<UnixStream>,
+}
+
+/// i3's event for a line `name>>data` of the event socket, if there is one.
+fn parse_event(line: &str) -> Option<Event> {
+    let (name, data) = line.split_once(">>")?;
+    let window = |change, address: &str| {
+        Some(Event::Window(WindowEventInfo {
+            change,
+            container: Box::new(Node {
+                id: window_id(address),
+                nodetype: NodeType::Con,
+                ..Node::default()
+            }),
+        }))
+    };
+    let address = data.split(',').next().unwrap_or_default();
+    match name {
+        "openwindow" => window(WindowChange::New, address),
+        "closewindow" => window(WindowChange::Close, address),
+        "activewindowv2" if !address.is_empty() => window(WindowChange::Focus, address),
+        "movewindowv2" => window(WindowChange::Move, address),
+        "changefloatingmode" => window(WindowChange::Floating, address),
+        // Only says whether fullscreen started or ended, not for which window.
+        "fullscreen" => window(WindowChange::FullscreenMode, ""),
+        "submap" => Some(Event::Mode(ModeEventInfo {
+            change: if data.is_empty() { "default" } else { data }.to_owned(),
+        })),
+        "monitoraddedv2" | "monitorremoved" => Some(Event::Output(OutputEventInfo {
+            change: "unspecified".to_owned(),
+        })),
+        _ => None,
+    }
+}
+
+impl Iterator for HyprlandEvents {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.lines.read_line(&mut line) {
+                Ok(0) => {
+                    let closed = io::Error::new(io::ErrorKind::UnexpectedEof, "Hyprland exited");
+                    return Some(Err(Error::Io(closed)));
+                }
+                Ok(_) => {
+                    if let Some(event) = parse_event(line.trim_end()) {
+                        return Some(Ok(event));
+                    }
+                }
+                Err(e) => return Some(Err(Error::Io(e))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn window(event: Option<Event>) -> Option<(WindowChange, i64)> {
+        match event? {
+            Event::Window(e) => Some((e.change, e.container.id)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn maps_events_to_those_of_i3() {
+        assert_eq!(
+            window(parse_event("openwindow>>55a1,1,kitty,~")),
+            Some((WindowChange::New, 0x55a1))
+        );
+        assert_eq!(
+            window(parse_event("activewindowv2>>55a1")),
+            Some((WindowChange::Focus, 0x55a1))
+        );
+        // Sent when the focus goes to an empty workspace.
+        assert!(parse_event("activewindowv2>>").is_none());
+        assert!(matches!(
+            parse_event("submap>>resize"),
+            Some(Event::Mode(e)) if e.change == "resize"
+        ));
+        assert!(matches!(
+            parse_event("submap>>"),
+            Some(Event::Mode(e)) if e.change == "default"
+        ));
+        assert!(matches!(
+            parse_event("monitoraddedv2>>1,DP-2,Dell"),
+            Some(Event::Output(_))
+        ));
+        assert!(matches!(
+            parse_event("monitorremoved>>DP-2"),
+            Some(Event::Output(_))
+        ));
+        assert!(parse_event("workspacev2>>1,1").is_none());
+        assert!(parse_event("garbage").is_none());
+    }
+
+    #[test]
+    fn reads_window_addresses() {
+        assert_eq!(window_id("0x55a1"), 0x55a1);
+        assert_eq!(window_id("55a1"), 0x55a1);
+        assert_eq!(window_id(""), 0);
+        assert_eq!(window_id("0xzz"), 0);
+    }
+
+    #[test]
+    fn makes_up_a_tree_like_that_of_i3() {
+        let monitors = json!([{
+            "id": 0, "name": "DP-1", "x": 0, "y": 0, "width": 1920, "height": 1080,
+            "reserved": [0, 30, 0, 0], "focused": true, "activeWorkspace": { "id": 1 },
+        }]);
+        let workspaces = json!([
+            { "id": 1, "name": "1", "monitor": "DP-1" },
+            { "id": -98, "name": "special:scratch", "monitor": "DP-1" },
+        ]);
+        let client = |address: &str, workspace: i64, floating: bool, fullscreen, history: i64| {
+            json!({
+                "address": address, "mapped": true, "at": [0, 30], "size": [960, 1050],
+                "workspace": { "id": workspace }, "floating": floating, "class": "kitty",
+                "title": "~", "fullscreen": fullscreen, "focusHistoryID": history,
+            })
+        };
+        let clients = json!([
+            client("0x55a1", 1, false, json!(true), 1),
+            client("0x55a2", 1, false, json!(0), 0),
+            client("0x55a3", -98, false, json!(false), 2),
+            client("0x55a4", 1, true, json!(2), 3),
+        ]);
+        let tree = tree(
+            &serde_json::from_value::<Vec<Monitor>>(monitors).unwrap(),
+            &serde_json::from_value::<Vec<Workspace>>(workspaces).unwrap(),
+            serde_json::from_value(clients).unwrap(),
+            &ActiveWindow {
+                address: "0x55a2".to_owned(),
+            },
+        );
+
+        let output = &tree.nodes[0];
+        assert_eq!(output.rect, (0, 0, 1920, 1080));
+        let [workspace] = &output.nodes[..] else {
+            panic!("special workspaces aren't left out: {:?}", output.nodes);
+        };
+        // The bar is left out of the workspace.
+        assert_eq!(workspace.rect, (0, 30, 1920, 1050));
+        assert!(!workspace.focused);
+        let tiled: Vec<_> = workspace
+            .nodes
+            .iter()
+            .map(|n| (n.id, n.focused, n.fullscreen_mode))
+            .collect();
+        assert_eq!(tiled, [(0x55a2, true, 0), (0x55a1, false, 1)]);
+        let floating: Vec<_> = workspace
+            .floating_nodes
+            .iter()
+            .map(|n| (n.id, n.nodetype.clone(), n.fullscreen_mode))
+            .collect();
+        assert_eq!(floating, [(0x55a4, NodeType::FloatingCon, 1)]);
+    }
+
+    #[test]
+    fn leaves_out_marks() {
+        let mut hyprland = HyprlandConnection {
+            dir: PathBuf::from("/nonexistent"),
+            path: PathBuf::from("/nonexistent/.socket.sock"),
+        };
+
+        assert!(hyprland
+            .run_command("[con_id=1] mark --add _alt_managed_1; [con_id=1] unmark _alt_managed_1")
+            .is_ok());
+        assert!(matches!(
+            hyprland.run_command("layout tabbed"),
+            Err(Error::Rejected(_))
+        ));
+    }
+}
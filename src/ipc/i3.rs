@@ -0,0 +1,257 @@
+//! A minimal client for the i3 IPC protocol, which sway speaks as well.
+
+use std::{
+    env,
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use super::{event::Event, reply, Backend, Error, Subscription, WmConnection};
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+
+const RUN_COMMAND: u32 = 0;
+const SUBSCRIBE: u32 = 2;
+const GET_TREE: u32 = 4;
+const GET_VERSION: u32 = 7;
+const GET_CONFIG: u32 = 9;
+
+/// Event message types have the highest bit set.
+const EVENT_BIT: u32 = 1 << 31;
+
+const OUTPUT: u32 = 1;
+const MODE: u32 = 2;
+const WINDOW: u32 = 3;
+const BINDING: u32 = 5;
+const SHUTDOWN: u32 = 6;
+const TICK: u32 = 7;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Compositor {
+    I3,
+    Sway,
+}
+
+impl Compositor {
+    /// Sway exports `SWAYSOCK` to its children, i3 never does.
+    pub fn detect() -> Self {
+        if env::var_os("SWAYSOCK").is_some_and(|path| !path.is_empty()) {
+            Compositor::Sway
+        } else {
+            Compositor::I3
+        }
+    }
+
+    fn socket_path(self) -> Result<PathBuf, Error> {
+        let (var, binary) = match self {
+            Compositor::I3 => ("I3SOCK", "i3"),
+            Compositor::Sway => ("SWAYSOCK", "sway"),
+        };
+        if let Some(path) = env::var_os(var).filter(|path| !path.is_empty()) {
+            return Ok(path.into());
+        }
+
+        let output = Command::new(binary).arg("--get-socketpath").output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::SocketPath(format!(
+                "{binary} --get-socketpath failed: {}",
+                stderr.trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim_end().into())
+    }
+}
+
+fn send_message(stream: &mut UnixStream, message_type: u32, payload: &str) -> Result<(), Error> {
+    let mut message = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    // Once part of the message is out, sending it again would garble the stream.
+    match stream.write(&message) {
+        Ok(written) => Ok(stream.write_all(&message[written..])?),
+        Err(e) => match e.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => Err(Error::Unsent(e)),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+fn receive_message(stream: &mut UnixStream) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid IPC message header",
+        ));
+    }
+    let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+    let message_type = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+
+    let mut payload = vec![0; length as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((message_type, payload))
+}
+
+fn request<T: DeserializeOwned>(
+    stream: &mut UnixStream,
+    message_type: u32,
+    payload: &str,
+) -> Result<T, Error> {
+    send_message(stream, message_type, payload)?;
+    loop {
+        let (reply_type, reply) = receive_message(stream)?;
+        if reply_type == message_type {
+            return Ok(serde_json::from_slice(&reply)?);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Outcome {
+    success: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// A connection for running commands and querying state.
+pub struct I3Connection {
+    stream: UnixStream,
+    /// The socket, for connecting the event listener to the same compositor.
+    path: PathBuf,
+}
+
+impl I3Connection {
+    /// Connects to the socket of the running compositor.
+    pub fn connect() -> Result<Self, Error> {
+        Self::connect_to(&Compositor::detect().socket_path()?)
+    }
+
+    pub fn connect_to(path: &Path) -> Result<Self, Error> {
+        Ok(I3Connection {
+            stream: UnixStream::connect(path)?,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Backend for I3Connection {
+    fn connect(path: Option<&Path>) -> Result<Self, Error> {
+        I3Connection::connect_to(&Self::socket_path(path)?)
+    }
+
+    fn socket_path(path: Option<&Path>) -> Result<PathBuf, Error> {
+        match path {
+            Some(path) => Ok(path.to_owned()),
+            None => Compositor::detect().socket_path(),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl WmConnection for I3Connection {
+    type Events = I3EventListener;
+
+    fn get_tree(&mut self) -> Result<reply::Node, Error> {
+        request(&mut self.stream, GET_TREE, "")
+    }
+
+    fn run_command(&mut self, command: &str) -> Result<(), Error> {
+        let outcomes: Vec<Outcome> = request(&mut self.stream, RUN_COMMAND, command)?;
+        match outcomes.into_iter().find(|outcome| !outcome.success) {
+            Some(failed) => Err(Error::Rejected(failed.error.unwrap_or_default())),
+            None => Ok(()),
+        }
+    }
+
+    fn get_version(&mut self) -> Result<reply::Version, Error> {
+        request(&mut self.stream, GET_VERSION, "")
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.stream = UnixStream::connect(&self.path).map_err(Error::Unsent)?;
+        Ok(())
+    }
+
+    fn get_config(&mut self) -> Result<String, Error> {
+        #[derive(Deserialize)]
+        struct Config {
+            config: String,
+        }
+
+        let reply: Config = request(&mut self.stream, GET_CONFIG, "")?;
+        Ok(reply.config)
+    }
+
+    /// Opens a second connection for the events, as i3 sends them on the connection that
+    /// subscribed.
+    fn subscribe(&mut self, events: &[Subscription]) -> Result<I3EventListener, Error> {
+        let mut listener = I3EventListener::connect_to(&self.path)?;
+        listener.subscribe(events)?;
+        Ok(listener)
+    }
+}
+
+/// A connection that receives the events it is subscribed to.
+pub struct I3EventListener {
+    stream: UnixStream,
+}
+
+impl I3EventListener {
+    pub fn connect_to(path: &Path) -> Result<Self, Error> {
+        Ok(I3EventListener {
+            stream: UnixStream::connect(path)?,
+        })
+    }
+
+    pub fn subscribe(&mut self, events: &[Subscription]) -> Result<(), Error> {
+        let names: Vec<_> = events.iter().map(|event| event.name()).collect();
+        let payload = serde_json::to_string(&names)?;
+        let outcome: Outcome = request(&mut self.stream, SUBSCRIBE, &payload)?;
+        if outcome.success {
+            Ok(())
+        } else {
+            Err(Error::Rejected(format!("subscribing to {names:?}")))
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Event, Error> {
+        loop {
+            let (message_type, payload) = receive_message(&mut self.stream)?;
+            if message_type & EVENT_BIT != 0 {
+                return parse_event(message_type & !EVENT_BIT, &payload);
+            }
+        }
+    }
+}
+
+/// Iterates over subscribed events forever.
+impl Iterator for I3EventListener {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+fn parse_event(event_type: u32, payload: &[u8]) -> Result<Event, Error> {
+    match event_type {
+        OUTPUT => Ok(Event::Output(serde_json::from_slice(payload)?)),
+        MODE => Ok(Event::Mode(serde_json::from_slice(payload)?)),
+        WINDOW => Ok(Event::Window(serde_json::from_slice(payload)?)),
+        BINDING => Ok(Event::Binding(serde_json::from_slice(payload)?)),
+        SHUTDOWN => Ok(Event::Shutdown(serde_json::from_slice(payload)?)),
+        TICK => Ok(Event::Tick(serde_json::from_slice(payload)?)),
+        other => Err(Error::UnknownEvent(other)),
+    }
+}
@@ -0,0 +1,200 @@
+//! Replies from i3, as far as this crate uses them.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeType {
+    Root,
+    Output,
+    Con,
+    FloatingCon,
+    Workspace,
+    Dockarea,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub enum NodeLayout {
+    #[serde(rename = "splith")]
+    SplitH,
+    #[serde(rename = "splitv")]
+    SplitV,
+    #[serde(rename = "stacked")]
+    Stacked,
+    #[serde(rename = "tabbed")]
+    Tabbed,
+    #[serde(rename = "dockarea")]
+    DockArea,
+    #[serde(rename = "output")]
+    Output,
+    /// Includes sway's `none`, used for leaf views.
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScratchpadState {
+    /// Never been in the scratchpad.
+    #[default]
+    None,
+    Fresh,
+    Changed,
+    #[serde(other)]
+    Unknown,
+}
+
+/// A container in the layout tree, as returned by `get_tree`. Fields that are missing or `null`
+/// get their defaults, as not all compositors and versions send all of them.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Node {
+    pub id: i64,
+    #[serde(deserialize_with = "null_as_default")]
+    pub nodes: Vec<Node>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub floating_nodes: Vec<Node>,
+    /// The ids of the children, most recently focused first.
+    #[serde(deserialize_with = "null_as_default")]
+    pub focus: Vec<i64>,
+    pub name: Option<String>,
+    #[serde(rename = "type", deserialize_with = "null_as_default")]
+    pub nodetype: NodeType,
+    #[serde(deserialize_with = "null_as_default")]
+    pub layout: NodeLayout,
+    /// (x, y, width, height)
+    #[serde(
+        deserialize_with = "deserialize_rect",
+        serialize_with = "serialize_rect"
+    )]
+    pub rect: (i32, i32, i32, i32),
+    /// The title bar or tab of the container, relative to its parent.
+    #[serde(
+        deserialize_with = "deserialize_rect",
+        serialize_with = "serialize_rect"
+    )]
+    pub deco_rect: (i32, i32, i32, i32),
+    /// The client area of a window, relative to the container, i.e. without its borders and
+    /// title bar. Empty for split containers.
+    #[serde(
+        deserialize_with = "deserialize_rect",
+        serialize_with = "serialize_rect"
+    )]
+    pub window_rect: (i32, i32, i32, i32),
+    /// The share of its parent the container takes up, absent for some containers.
+    pub percent: Option<f64>,
+    /// i3's floating state, e.g. `auto_off` or `user_on`. On sway, floating windows are of the
+    /// `FloatingCon` type instead.
+    pub floating: Option<String>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub focused: bool,
+    #[serde(deserialize_with = "null_as_default")]
+    pub scratchpad_state: ScratchpadState,
+    /// 0 if the container isn't fullscreen, 1 if it fills its output and 2 if it fills all of
+    /// them.
+    #[serde(deserialize_with = "null_as_default")]
+    pub fullscreen_mode: u8,
+    /// X11 window properties, absent for split containers and native wayland windows.
+    pub window_properties: Option<WindowProperties>,
+    /// The `_NET_WM_WINDOW_TYPE` of an X11 window, e.g. `normal` or `dialog`.
+    pub window_type: Option<String>,
+    /// The wayland app id of a sway window.
+    pub app_id: Option<String>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub marks: Vec<String>,
+    /// The rotation of a sway output, e.g. `normal`, `90` or `flipped-270`.
+    pub transform: Option<String>,
+}
+
+impl Node {
+    /// Iterates over the node and all nodes below it, floating ones included. The tree is
+    /// walked with a stack of its own, so deeply nested layouts can't overflow the call stack.
+    pub fn descendants(&self) -> impl Iterator<Item = &Node> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.floating_nodes.iter().rev());
+            stack.extend(node.nodes.iter().rev());
+            Some(node)
+        })
+    }
+
+    pub fn is_floating(&self) -> bool {
+        self.nodetype == NodeType::FloatingCon
+            || self
+                .floating
+                .as_ref()
+                .is_some_and(|floating| floating.ends_with("_on"))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// The full version, e.g. `4.23 (2023-10-29)`.
+    pub human_readable: String,
+    /// `sway` on sway, absent on i3.
+    pub variant: Option<String>,
+    pub loaded_config_file_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WindowProperties {
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Reads `null` as the default, which `#[serde(default)]` only does for missing fields.
+fn null_as_default<'de, D: Deserializer<'de>, T: Default + Deserialize<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+fn deserialize_rect<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(i32, i32, i32, i32), D::Error> {
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct Rect {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    }
+
+    let rect: Rect = null_as_default(deserializer)?;
+    Ok((rect.x, rect.y, rect.width, rect.height))
+}
+
+/// Writes a rect the way i3 does, for `deserialize_rect`.
+fn serialize_rect<S: Serializer>(
+    rect: &(i32, i32, i32, i32),
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Rect {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    }
+
+    let &(x, y, width, height) = rect;
+    Rect {
+        x,
+        y,
+        width,
+        height,
+    }
+    .serialize(serializer)
+}
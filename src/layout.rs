@@ -0,0 +1,230 @@
+//! Saved workspace layouts, restored with i3's `append_layout`.
+//!
+//! A layout is the tree of the tiled containers of a workspace, with their layouts and sizes
+//! and the class, instance and title of the windows in them. Restoring one appends placeholders
+//! for its windows to the focused workspace, moves the windows that are already there into the
+//! placeholders they match and leaves the others to swallow the windows opened later.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::ipc::reply::{Node, NodeLayout, NodeType};
+
+/// Marks the placeholders windows are moved to while restoring, followed by a number.
+const MARK: &str = "_alternating_layout_restore_";
+
+/// A container, or a window if it has no children.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<Layout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// `$XDG_DATA_HOME/i3-alternating-layout/layouts`, falling back to `~/.local/share`.
+pub fn directory() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+
+    Some(data_home.join("i3-alternating-layout").join("layouts"))
+}
+
+fn path(name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return Err(format!("invalid layout name '{name}'"));
+    }
+    let directory = directory().ok_or("couldn't determine the layout directory")?;
+    Ok(directory.join(format!("{name}.json")))
+}
+
+/// Escapes `s` for a PCRE, as i3 matches criteria with them.
+fn regex(s: &str) -> String {
+    let mut regex = String::from("^");
+    for c in s.chars() {
+        if r"\^$.|?*+()[]{}".contains(c) {
+            regex.push('\\');
+        }
+        regex.push(c);
+    }
+    regex.push('$');
+    regex
+}
+
+impl Layout {
+    /// The tiled containers of `workspace`. Windows without a class or title can't be matched,
+    /// so they are left out.
+    pub fn of_workspace(workspace: &Node) -> Self {
+        fn layout(node: &Node) -> Option<String> {
+            let name = match node.layout {
+                NodeLayout::SplitH => "splith",
+                NodeLayout::SplitV => "splitv",
+                NodeLayout::Tabbed => "tabbed",
+                NodeLayout::Stacked => "stacked",
+                _ => return None,
+            };
+            Some(name.to_owned())
+        }
+
+        fn container(node: &Node) -> Option<Layout> {
+            let percent = node.percent;
+            if node.nodes.is_empty() {
+                let properties = node.window_properties.as_ref()?;
+                return (properties.class.is_some() || properties.title.is_some()).then(|| {
+                    Layout {
+                        percent,
+                        class: properties.class.clone(),
+                        instance: properties.instance.clone(),
+                        title: properties
+                            .title
+                            .clone()
+                            .filter(|_| properties.class.is_none()),
+                        ..Layout::default()
+                    }
+                });
+            }
+            let nodes: Vec<_> = node.nodes.iter().filter_map(container).collect();
+            (!nodes.is_empty()).then(|| Layout {
+                layout: layout(node),
+                percent,
+                nodes,
+                ..Layout::default()
+            })
+        }
+
+        Layout {
+            layout: layout(workspace),
+            nodes: workspace.nodes.iter().filter_map(container).collect(),
+            ..Layout::default()
+        }
+    }
+
+    pub fn load(name: &str) -> Result<Self, String> {
+        let path = path(name)?;
+        let contents = fs::read_to_string(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => format!("no layout called '{name}'"),
+            _ => format!("couldn't read {}: {e}", path.display()),
+        })?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("couldn't parse {}: {e}", path.display()))
+    }
+
+    pub fn save(&self, name: &str) -> Result<(), String> {
+        let path = path(name)?;
+        let write = || {
+            fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+            fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+        };
+        write().map_err(|e: std::io::Error| format!("couldn't write {}: {e}", path.display()))
+    }
+
+    /// Writes the layout file for restoring the layout on `workspace`, returning the commands
+    /// doing so.
+    pub fn apply(&self, workspace: &Node) -> Result<Vec<String>, String> {
+        if self.nodes.is_empty() {
+            return Err("the layout has no windows".to_owned());
+        }
+        let directory = directory().ok_or("couldn't determine the layout directory")?;
+        let file = directory.join(".restore.json");
+        let (contents, commands) = self.restore(workspace, &file);
+        fs::create_dir_all(&directory)
+            .and_then(|_| fs::write(&file, contents))
+            .map_err(|e| format!("couldn't write {}: {e}", file.display()))?;
+        Ok(commands)
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        let Some(properties) = &node.window_properties else {
+            return false;
+        };
+        let matches = |expected: &Option<String>, actual: &Option<String>| {
+            expected.is_none() || expected == actual
+        };
+        matches(&self.class, &properties.class)
+            && matches(&self.instance, &properties.instance)
+            && matches(&self.title, &properties.title)
+    }
+
+    /// The contents of the layout file for `append_layout`, and the commands appending it from
+    /// `file` to `workspace` and moving the windows there into their placeholders.
+    pub fn restore(&self, workspace: &Node, file: &Path) -> (String, Vec<String>) {
+        /// Converts `layout` to i3's format, marking the placeholders of the windows in
+        /// `windows`, which are taken out of it.
+        fn convert(layout: &Layout, windows: &mut Vec<&Node>, moves: &mut Vec<String>) -> Value {
+            let mut container = json!({ "type": "con" });
+            if let Some(percent) = layout.percent {
+                container["percent"] = percent.into();
+            }
+            if !layout.nodes.is_empty() {
+                container["layout"] = layout.layout.as_deref().unwrap_or("splith").into();
+                container["nodes"] = layout
+                    .nodes
+                    .iter()
+                    .map(|node| convert(node, windows, moves))
+                    .collect();
+                return container;
+            }
+
+            let mut swallows = json!({});
+            for (key, value) in [
+                ("class", &layout.class),
+                ("instance", &layout.instance),
+                ("title", &layout.title),
+            ] {
+                if let Some(value) = value {
+                    swallows[key] = regex(value).into();
+                }
+            }
+            container["swallows"] = json!([swallows]);
+            if let Some(i) = windows.iter().position(|window| layout.matches(window)) {
+                let mark = format!("{MARK}{}", moves.len() / 2);
+                container["marks"] = json!([mark]);
+                moves.push(format!(
+                    "[con_id={}] move window to mark {mark}",
+                    windows.remove(i).id
+                ));
+                moves.push(format!("[con_mark=\"^{mark}$\"] kill"));
+            }
+            container
+        }
+
+        let mut windows: Vec<_> = workspace
+            .nodes
+            .iter()
+            .flat_map(Node::descendants)
+            .filter(|n| n.nodetype == NodeType::Con && n.nodes.is_empty())
+            .collect();
+        let mut moves = Vec::new();
+        let contents: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| convert(node, &mut windows, &mut moves).to_string())
+            .collect();
+
+        let mut commands = Vec::new();
+        if let Some(layout @ ("splith" | "splitv")) = self.layout.as_deref() {
+            commands.push(format!("[con_id={}] layout {layout}", workspace.id));
+        }
+        let file = file.display().to_string().replace('"', "\\\"");
+        commands.push(format!("append_layout \"{file}\""));
+        commands.extend(moves);
+        // i3 takes the containers of a layout file one after another.
+        (contents.join("\n") + "\n", commands)
+    }
+}
@@ -0,0 +1,92 @@
+//! Splits i3 and sway windows along their longer side, with the layout decisions made by
+//! [`LayoutEngine`] and the daemon around it in the binary.
+
+use std::{str::FromStr, sync::mpsc::Sender};
+
+use ipc::event::Event;
+use serde_json::Value;
+use signal::Signal;
+
+pub mod cli;
+pub mod config;
+pub mod control;
+pub mod dbus;
+pub mod debounce;
+pub mod defaults;
+pub mod doctor;
+pub mod dump;
+pub mod engine;
+pub mod hooks;
+pub mod instance;
+pub mod ipc;
+pub mod layout;
+pub mod logger;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod output;
+pub mod record;
+pub mod scheme;
+pub mod signal;
+pub mod state;
+pub mod stats;
+pub mod systemd;
+pub mod toml;
+
+pub use engine::LayoutEngine;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum I3Split {
+    Vertical,
+    Horizontal,
+    Tabbed,
+    Stacked,
+    Toggle,
+}
+
+#[derive(Debug)]
+pub struct I3SplitParseError;
+
+impl FromStr for I3Split {
+    type Err = I3SplitParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v" | "vertical" => Ok(I3Split::Vertical),
+            "h" | "horizontal" => Ok(I3Split::Horizontal),
+            "tabbed" => Ok(I3Split::Tabbed),
+            "stacked" | "stacking" => Ok(I3Split::Stacked),
+            "t" | "toggle" => Ok(I3Split::Toggle),
+            _ => Err(I3SplitParseError),
+        }
+    }
+}
+
+impl I3Split {
+    pub fn name(&self) -> &'static str {
+        match self {
+            I3Split::Vertical => "vertical",
+            I3Split::Horizontal => "horizontal",
+            I3Split::Tabbed => "tabbed",
+            I3Split::Stacked => "stacked",
+            I3Split::Toggle => "toggle",
+        }
+    }
+}
+
+/// Everything the main loop reacts to. Every source, i.e. i3 events, the control socket, D-Bus,
+/// signals and the config watcher, sends its inputs from a thread of its own over one channel,
+/// so the loop waits on all of them at once and timers are timeouts on receiving.
+pub enum Input {
+    Event(Result<Event, ipc::Error>),
+    /// A command from the control socket and where to send the response to.
+    Control(control::Command, Sender<Value>),
+    Signal(Signal),
+    /// The config file changed.
+    Reload,
+    /// Another instance of the daemon is taking over.
+    Replace,
+    /// The blocks of a status line of the wrapped status command, see `output::wrap`.
+    StatusLine(Vec<Value>),
+    /// The status was clicked with this button, see `output::read_clicks`.
+    Click(u32),
+}
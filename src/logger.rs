@@ -0,0 +1,325 @@
+//! A `log` backend writing to stderr, a file, journald or syslog.
+//!
+//! Levels are set with `RUST_LOG`-style directives: a default level and/or `target=level`
+//! pairs, e.g. `warn,i3_alternating_layout::ipc=trace`. Messages logged while handling an
+//! input carry its span, e.g. `window{change=New workspace=1}: running 'split horizontal'`.
+//! journald gets the span's fields as fields of their own, e.g. `EVENT=window` and
+//! `WORKSPACE=1`.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::net::UnixDatagram,
+    path::Path,
+    process,
+    str::FromStr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const IDENTIFIER: &str = "i3-alternating-layout";
+
+thread_local! {
+    static SPAN: RefCell<Option<Span>> = const { RefCell::new(None) };
+}
+
+/// Where the log goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogTarget {
+    /// stderr, or the log file if there is one.
+    #[default]
+    Stderr,
+    Journald,
+    Syslog,
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(LogTarget::Stderr),
+            "journald" => Ok(LogTarget::Journald),
+            "syslog" => Ok(LogTarget::Syslog),
+            _ => Err(format!("unknown log target '{s}'")),
+        }
+    }
+}
+
+/// What is being handled, and what is known about it so far.
+struct Span {
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            let separator = if i == 0 { '{' } else { ' ' };
+            write!(f, "{separator}{key}={value}")?;
+        }
+        if !self.fields.is_empty() {
+            write!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Which messages are logged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            default: LevelFilter::Warn,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(directives: &str) -> Result<Self, String> {
+        let mut filter = Filter::default();
+        for directive in directives.split(',').map(str::trim) {
+            let level = |level: &str| {
+                level
+                    .parse()
+                    .map_err(|_| format!("invalid log level '{level}'"))
+            };
+            match directive.split_once('=') {
+                _ if directive.is_empty() => {}
+                Some((target, level_name)) => {
+                    filter.targets.push((target.to_owned(), level(level_name)?))
+                }
+                None => filter.default = level(directive)?,
+            }
+        }
+        // The most specific directive for a target wins.
+        filter
+            .targets
+            .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+
+        Ok(filter)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+enum Sink {
+    Stderr,
+    File(Mutex<File>),
+    Journald(UnixDatagram),
+    Syslog(UnixDatagram),
+}
+
+struct Logger {
+    filter: Filter,
+    sink: Sink,
+}
+
+/// The syslog severity of `level`.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Appends a field in the native journal protocol, which allows any value as long as its length
+/// is given up front.
+fn journal_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        SPAN.with(|span| {
+            let span = span.borrow();
+            let prefix = span.as_ref().map(|span| format!("{span}: "));
+            let prefix = prefix.unwrap_or_default();
+            let level = record.level();
+            let _ = match &self.sink {
+                Sink::Stderr => writeln!(io::stderr(), "{level:<5} {prefix}{}", record.args()),
+                Sink::File(file) => {
+                    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                    writeln!(
+                        file,
+                        "{} {level:<5} {prefix}{}",
+                        Timestamp::now(),
+                        record.args()
+                    )
+                }
+                Sink::Journald(socket) => {
+                    let mut entry = Vec::new();
+                    journal_field(&mut entry, "MESSAGE", &record.args().to_string());
+                    journal_field(&mut entry, "PRIORITY", &severity(level).to_string());
+                    journal_field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+                    journal_field(&mut entry, "TARGET", record.target());
+                    if let Some(span) = span.as_ref() {
+                        journal_field(&mut entry, "EVENT", span.name);
+                        for (key, value) in &span.fields {
+                            journal_field(&mut entry, &key.to_ascii_uppercase(), value);
+                        }
+                    }
+                    socket.send(&entry).map(drop)
+                }
+                Sink::Syslog(socket) => {
+                    // The user facility.
+                    let priority = 8 + severity(level);
+                    let message = format!(
+                        "<{priority}>{IDENTIFIER}[{}]: {prefix}{}",
+                        process::id(),
+                        record.args()
+                    );
+                    socket.send(message.as_bytes()).map(drop)
+                }
+            };
+        });
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &self.sink {
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).flush();
+        }
+    }
+}
+
+/// Installs the logger. With the `Stderr` target, the log is appended to `file` if given.
+pub fn init(filter: Filter, target: LogTarget, file: Option<&Path>) -> io::Result<()> {
+    let datagram = |path: &str| {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))?;
+        Ok::<_, io::Error>(socket)
+    };
+    let sink = match (target, file) {
+        (LogTarget::Stderr, None) => Sink::Stderr,
+        (LogTarget::Stderr, Some(path)) => Sink::File(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        (LogTarget::Journald, _) => Sink::Journald(datagram(JOURNALD_SOCKET)?),
+        (LogTarget::Syslog, _) => Sink::Syslog(datagram(SYSLOG_SOCKET)?),
+    };
+
+    log::set_max_level(filter.max_level());
+    log::set_boxed_logger(Box::new(Logger { filter, sink }))
+        .map_err(|e| io::Error::new(io::ErrorKind::AlreadyExists, e.to_string()))
+}
+
+/// Attaches `name` and `fields` to messages logged on this thread until the guard is dropped,
+/// which brings back the enclosing span.
+pub fn span<const N: usize>(name: &'static str, fields: [(&'static str, String); N]) -> SpanGuard {
+    SpanGuard(SPAN.replace(Some(Span {
+        name,
+        fields: fields.into(),
+    })))
+}
+
+/// Sets a field of the current span, if there is one.
+pub fn record(key: &'static str, value: impl fmt::Display) {
+    SPAN.with(|span| {
+        if let Some(span) = span.borrow_mut().as_mut() {
+            let value = value.to_string();
+            match span.fields.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = value,
+                None => span.fields.push((key, value)),
+            }
+        }
+    });
+}
+
+pub struct SpanGuard(Option<Span>);
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        SPAN.set(self.0.take());
+    }
+}
+
+/// The current UTC time, formatted like `2024-01-31T12:34:56.789Z`.
+struct Timestamp(std::time::Duration);
+
+impl Timestamp {
+    fn now() -> Self {
+        Timestamp(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let (days, time) = (secs / 86400, secs % 86400);
+
+        // Howard Hinnant's civil_from_days.
+        let z = days as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+            time / 3600,
+            time % 3600 / 60,
+            time % 60,
+            self.0.subsec_millis()
+        )
+    }
+}
@@ -1,137 +1,582 @@
-use std::{cell::RefCell, str::FromStr};
-
-use i3ipc::{
-    event::BindingEventInfo,
-    reply::{Node, NodeLayout},
-    I3Connection, I3EventListener, Subscription,
+use std::{
+    env, fs, io,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-#[derive(PartialEq)]
-enum I3Split {
-    Vertical,
-    Horizontal,
-    Tabbed,
-    Stacked,
-    Toggle,
-}
+use i3_alternating_layout::{
+    cli::Args,
+    config::{self, Config, ConfigError},
+    control::{self, Command},
+    dbus,
+    debounce::Debounce,
+    defaults::Defaults,
+    doctor, dump,
+    engine::LayoutEngine,
+    instance,
+    ipc::{
+        self,
+        event::{Event, ShutdownChange},
+        Backend, I3Connection, Subscription, WmConnection,
+    },
+    logger::{self, Filter},
+    output::{self, OutputMode},
+    record,
+    signal::{self, Signal},
+    state::Overrides,
+    systemd, Input,
+};
+use log::{error, info, trace, warn};
 
-struct I3SplitParseError;
+const RECONNECT_ATTEMPTS: u32 = 10;
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How many malformed events in a row it takes to subscribe again.
+const EVENT_ERRORS: u32 = 5;
+/// How long to wait for the last status to be written when exiting.
+const OUTPUT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
 
-impl FromStr for I3Split {
-    type Err = I3SplitParseError;
+/// Whether events from i3 are being forwarded, for the health check.
+static SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+/// Counts the subscriptions, so the threads forwarding earlier ones stop.
+static SUBSCRIPTION: AtomicU32 = AtomicU32::new(0);
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "v" | "vertical" => Ok(I3Split::Vertical),
-            "h" | "horizontal" => Ok(I3Split::Horizontal),
-            "tabbed" => Ok(I3Split::Tabbed),
-            "stacked" | "stacking" => Ok(I3Split::Stacked),
-            "t" | "toggle" => Ok(I3Split::Toggle),
-            _ => Err(I3SplitParseError),
+fn main() {
+    let args = Args::parse();
+    let mut config = load_config(&args).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        process::exit(1)
+    });
+    if let Some(command) = &args.ctl {
+        #[cfg(feature = "hyprland")]
+        if ipc::hyprland::is_running() {
+            return ctl::<ipc::hyprland::HyprlandConnection>(&config, &args, command);
         }
+        return ctl::<I3Connection>(&config, &args, command);
     }
-}
+    if args.menu {
+        return println!("{}", control::menu().join("\n"));
+    }
+    if args.doctor {
+        process::exit(if doctor::run(&config, args.socket.as_deref()) {
+            0
+        } else {
+            1
+        })
+    }
+    if args.dump_tree {
+        #[cfg(feature = "hyprland")]
+        if ipc::hyprland::is_running() {
+            let dumped =
+                dump::run::<ipc::hyprland::HyprlandConnection>(config, args.socket.as_deref());
+            process::exit(if dumped { 0 } else { 1 })
+        }
+        let dumped = dump::run::<I3Connection>(config, args.socket.as_deref());
+        process::exit(if dumped { 0 } else { 1 })
+    }
+    if args.replay.is_some() {
+        config.dry_run = true;
+    }
+    let filter = args
+        .log_level
+        .clone()
+        .or_else(|| env::var("RUST_LOG").ok().filter(|s| !s.is_empty()))
+        .or_else(|| config.log_level.clone())
+        // The point of a dry run is seeing the commands, which are logged at the info level, as
+        // are the explanations.
+        .or_else(|| (config.dry_run || config.explain).then(|| "info".to_owned()))
+        .map_or_else(
+            || Ok(Filter::default()),
+            |directives| Filter::parse(&directives),
+        )
+        .and_then(|filter| {
+            logger::init(filter, config.log_target, config.log_file.as_deref())
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = filter {
+        eprintln!("Error: couldn't set up logging: {e}");
+        process::exit(1)
+    }
+    if let Some(path) = &args.replay {
+        return record::replay(config, path).unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            process::exit(1)
+        });
+    }
+    if let Some(path) = &args.record {
+        if let Err(e) = record::start(path) {
+            eprintln!("Error: couldn't record to {}: {e}", path.display());
+            process::exit(1)
+        }
+    }
+    // Before the output starts its threads, so they inherit the blocked signals.
+    let (sender, inputs) = mpsc::channel();
+    let signals = [Signal::Usr1, Signal::Hup, Signal::Term, Signal::Int];
+    if let Err(e) = signal::forward(&signals, sender.clone()) {
+        error!("couldn't set up signal handling: {e}");
+    }
+    if config.wrap {
+        config.output = OutputMode::I3bar;
+    }
+    // When wrapping, the clicks go to the start of the pipeline, not to the daemon.
+    let clicks = !config.quiet
+        && !config.wrap
+        && matches!(config.output, OutputMode::Plain | OutputMode::I3bar);
+    if !config.quiet {
+        if let Err(e) = config.output.start(clicks) {
+            eprintln!("Error: couldn't start the output: {e}");
+            process::exit(1)
+        }
+    }
+    systemd::init();
 
-thread_local! {
-    static PREVIOUS_SPLIT: RefCell<I3Split> = RefCell::new(I3Split::Horizontal);
+    #[cfg(feature = "hyprland")]
+    if ipc::hyprland::is_running() {
+        return run::<ipc::hyprland::HyprlandConnection>(config, &args, clicks, sender, inputs);
+    }
+    run::<I3Connection>(config, &args, clicks, sender, inputs)
 }
 
-fn main() {
-    let mut i3 = I3Connection::connect().expect("Problem connecting to i3");
-    let mut i3_events = I3EventListener::connect().expect("Problem connecting to i3");
+/// Runs the daemon with connections of type `C` until it exits.
+fn run<C: Backend>(
+    mut config: Config,
+    args: &Args,
+    clicks: bool,
+    sender: Sender<Input>,
+    inputs: Receiver<Input>,
+) {
+    let socket_path = args.socket.as_deref();
+    let mut connections = connect::<C>(socket_path).unwrap_or_else(|e| {
+        error!("problem connecting to i3: {e}");
+        process::exit(1)
+    });
 
-    i3_events
-        .subscribe(&[Subscription::Window, Subscription::Binding])
-        .expect("Problem subscribing to events");
+    if let Err(e) = instance::lock(connections.0.path(), args.replace, sender.clone()) {
+        error!("{e}");
+        process::exit(1)
+    }
 
-    i3_events.listen().for_each(|event| {
-        let Ok(event) = event else {
-            eprintln!("Error: {event:?}");
-            return;
-        };
+    let socket = config
+        .control_socket
+        .clone()
+        .or_else(|| control::default_socket_path(connections.0.path()))
+        .and_then(|path| match control::serve(&path, sender.clone()) {
+            Ok(()) => Some(path),
+            Err(e) => {
+                error!("couldn't listen on {}: {e}", path.display());
+                None
+            }
+        });
+    if config.dbus {
+        match dbus::serve(sender.clone()) {
+            Ok(()) => {}
+            // The daemon of another i3 on the same session bus has it.
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                warn!("not offering the D-Bus service: {e}")
+            }
+            Err(e) => error!("couldn't offer the D-Bus service: {e}"),
+        }
+    }
+    if let Some(port) = config.metrics_port {
+        #[cfg(feature = "metrics")]
+        if let Err(e) = i3_alternating_layout::metrics::serve(port, sender.clone()) {
+            error!("couldn't serve metrics on port {port}: {e}");
+        }
+        #[cfg(not(feature = "metrics"))]
+        warn!("not serving metrics on port {port}: built without the metrics feature");
+    }
+    if config.wrap {
+        output::wrap(sender.clone());
+    }
+    if clicks {
+        output::read_clicks(sender.clone());
+    }
+    if config.watch {
+        match args.config.clone().or_else(Config::default_path) {
+            Some(path) => config::watch(path, sender.clone()),
+            None => warn!("there is no config file to watch"),
+        }
+    }
+
+    systemd::notify("READY=1\nSTATUS=connected to i3");
 
-        match event {
-            i3ipc::event::Event::WindowEvent(_) => set_layout(&mut i3),
-            i3ipc::event::Event::BindingEvent(e) => handle_keybind(&mut i3, e),
-            _ => unreachable!(),
+    Defaults::detect(&mut connections.0).apply(&mut config);
+    // Kept across reconnects, so restarting i3 doesn't lose its state, and the overrides in it
+    // are saved so restarting the daemon doesn't either.
+    let mut engine = LayoutEngine::new(config);
+    let state_path = Overrides::default_path();
+    match state_path.as_deref().map(Overrides::load) {
+        Some(Ok(overrides)) => engine.state_mut().set_overrides(overrides),
+        Some(Err(e)) => warn!("starting afresh: {e}"),
+        None => warn!("there is nowhere to save the state to"),
+    }
+    let mut resubscribe_delay = RECONNECT_DELAY;
+    loop {
+        let (mut i3, i3_events) = connections;
+        forward_events::<C>(i3_events, sender.clone());
+        let delay = match listen(&mut i3, &inputs, &mut engine, args, state_path.as_deref()) {
+            Stop::Exit => {
+                systemd::notify("STOPPING=1");
+                output::flush(OUTPUT_FLUSH_TIMEOUT);
+                if let Some(socket) = socket {
+                    let _ = fs::remove_file(socket);
+                }
+                return;
+            }
+            Stop::Reconnect => {
+                resubscribe_delay = RECONNECT_DELAY;
+                RECONNECT_DELAY
+            }
+            // Waiting longer each time, in case the events keep failing however often it is
+            // subscribed to them.
+            Stop::Resubscribe => {
+                let delay = resubscribe_delay;
+                resubscribe_delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                delay
+            }
         };
-    })
+
+        connections = reconnect::<C>(socket_path, delay).unwrap_or_else(|| {
+            error!("giving up on reconnecting to i3");
+            process::exit(1)
+        });
+        engine.state_mut().stats.reconnects += 1;
+    }
 }
 
-fn set_layout(i3: &mut I3Connection) -> Option<()> {
-    fn find_focused_parent(node: &Node) -> Option<&Node> {
-        if node.nodes.iter().any(|n| n.focused) {
-            Some(node)
-        } else {
-            node.nodes.iter().find_map(find_focused_parent)
+/// Loads the config file given on the command line or the default one, with the command line
+/// options applied on top.
+fn load_config(args: &Args) -> Result<Config, ConfigError> {
+    let mut config = match &args.config {
+        Some(path) => Config::from_path(path),
+        None => Config::load(),
+    }?;
+    args.apply(&mut config);
+
+    Ok(config)
+}
+
+/// Replaces the config of `engine` with the current contents of the config file, keeping it if
+/// they are invalid.
+fn reload_config(i3: &mut impl WmConnection, engine: &mut LayoutEngine, args: &Args) {
+    match load_config(args) {
+        Ok(mut reloaded) => {
+            let output = engine.config().output.clone();
+            if reloaded.output != output {
+                warn!("the output mode can only be changed by restarting");
+                reloaded.output = output;
+            }
+            Defaults::detect(i3).apply(&mut reloaded);
+            info!("reloaded the config");
+            let commands = engine.set_config(reloaded);
+            engine.execute(i3, commands);
         }
+        Err(e) => error!("keeping the current config: {e}"),
     }
+}
 
-    let tree = i3.get_tree().ok()?;
-    let parent = find_focused_parent(&tree);
-    match parent {
-        Some(parent) => {
-            if matches!(parent.layout, NodeLayout::Tabbed | NodeLayout::Stacked) {
-                print_status(match parent.layout {
-                    NodeLayout::Tabbed => I3Split::Tabbed,
-                    NodeLayout::Stacked => I3Split::Stacked,
-                    _ => unreachable!(),
+/// Sends `command` to the daemon managing the i3 behind connections of type `C`, printing the
+/// response to `status`.
+fn ctl<C: Backend>(config: &Config, args: &Args, command: &str) {
+    let path = match &config.control_socket {
+        Some(path) => Ok(path.clone()),
+        None => C::socket_path(args.socket.as_deref())
+            .map_err(|e| format!("couldn't find i3: {e}"))
+            .and_then(|wm_socket| {
+                control::default_socket_path(&wm_socket).ok_or_else(|| {
+                    "XDG_RUNTIME_DIR isn't set, so there is no control socket".to_owned()
                 })
-            } else if parent.rect.2 > parent.rect.3 {
-                // rect: (x, y, width, height)
-                i3.run_command("split horizontal").ok()?;
-                print_status(I3Split::Horizontal)
-            } else {
-                i3.run_command("split vertical").ok()?;
-                print_status(I3Split::Vertical)
+            }),
+    };
+    let response = path.and_then(|path| {
+        control::send(&path, command)
+            .map_err(|e| format!("couldn't reach the daemon at {}: {e}", path.display()))
+    });
+
+    match response {
+        Ok(response) if response["success"] == true => {
+            if matches!(
+                command.parse(),
+                Ok(Command::Status(_) | Command::Health | Command::Stats)
+            ) {
+                println!("{response}");
+            }
+        }
+        Ok(response) => {
+            eprintln!(
+                "Error: {}",
+                response["error"].as_str().unwrap_or("command failed")
+            );
+            process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            process::exit(1)
+        }
+    }
+}
+
+enum Stop {
+    Exit,
+    Reconnect,
+    /// Connect again as the subscription keeps failing.
+    Resubscribe,
+}
+
+/// Connects to the compositor at `socket`, or to the detected one.
+fn connect<C: Backend>(socket: Option<&Path>) -> Result<(C, C::Events), ipc::Error> {
+    let mut i3 = C::connect(socket)?;
+    let i3_events = i3.subscribe(&[
+        Subscription::Output,
+        Subscription::Mode,
+        Subscription::Window,
+        Subscription::Binding,
+        Subscription::Shutdown,
+        Subscription::Tick,
+    ])?;
+
+    Ok((i3, i3_events))
+}
+
+/// Connects again after waiting for `first_delay`, waiting longer after each failed attempt.
+fn reconnect<C: Backend>(socket: Option<&Path>, first_delay: Duration) -> Option<(C, C::Events)> {
+    systemd::notify("STATUS=reconnecting to i3");
+    let mut delay = first_delay;
+    for _ in 0..RECONNECT_ATTEMPTS {
+        thread::sleep(delay);
+        systemd::ping_watchdog();
+        match connect::<C>(socket) {
+            Ok(connections) => {
+                info!("reconnected to i3");
+                systemd::notify("STATUS=connected to i3");
+                return Some(connections);
             }
+            Err(e) => warn!("problem reconnecting to i3: {e}"),
         }
-        None => println!(),
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
     }
 
-    Some(())
+    None
 }
 
-fn handle_keybind(i3: &mut I3Connection, e: BindingEventInfo) -> Option<()> {
-    let mut binding = e.binding.command.split(' ');
-    match binding.next()? {
-        "split" => print_status(binding.next()?.parse().ok()?),
-        "move" | "focus" | "workspace" => set_layout(i3)?,
-        "layout" => {
-            let command = binding.next()?;
-            let split = if command.starts_with("split") {
-                // layout splith, splitv
-                command.chars().last()?.to_string()
-            } else {
-                command.to_owned()
-            };
+/// Forwards events to the main loop from a thread of their own, until the connection is lost or
+/// i3 shuts down.
+fn forward_events<C: WmConnection>(i3_events: C::Events, inputs: Sender<Input>) {
+    let subscription = SUBSCRIPTION.fetch_add(1, Ordering::Relaxed) + 1;
+    let current = move || SUBSCRIPTION.load(Ordering::Relaxed) == subscription;
+    SUBSCRIBED.store(true, Ordering::Relaxed);
+    thread::spawn(move || {
+        for event in i3_events {
+            // A newer subscription replaced this one, so its events would come twice.
+            if !current() {
+                return;
+            }
+            let last = matches!(event, Ok(Event::Shutdown(_)) | Err(ipc::Error::Io(_)));
+            if inputs.send(Input::Event(event)).is_err() || last {
+                break;
+            }
+        }
+        if current() {
+            SUBSCRIBED.store(false, Ordering::Relaxed);
+        }
+    });
+}
+
+fn listen(
+    i3: &mut impl WmConnection,
+    inputs: &Receiver<Input>,
+    engine: &mut LayoutEngine,
+    args: &Args,
+    state_path: Option<&Path>,
+) -> Stop {
+    let mut pending = Debounce::default();
+    let mut saved = engine.state().overrides();
+    // The malformed events received since the last good one.
+    let mut errors = 0;
+    loop {
+        save_state(engine, state_path, &mut saved);
+
+        // Wake up in time to ping the watchdog even if nothing happens.
+        let timeout = [
+            systemd::watchdog_interval(),
+            pending
+                .deadline()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now())),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let input = match timeout {
+            Some(timeout) => inputs.recv_timeout(timeout).ok(),
+            None => inputs.recv().ok(),
+        };
+        systemd::ping_watchdog();
+        match &input {
+            Some(Input::Event(Ok(_))) => errors = 0,
+            Some(Input::Event(Err(_))) => errors += 1,
+            _ => {}
+        }
+
+        let input = match input {
+            Some(Input::Event(Ok(Event::Window(e))))
+                if !engine.config().debounce().is_zero()
+                    && engine.config().events.contains(&e.change) =>
+            {
+                pending.hold(e, Instant::now() + engine.config().debounce());
+                continue;
+            }
+            input => input,
+        };
+        if input.is_some() || pending.is_due(Instant::now()) {
+            if let Some(e) = pending.take() {
+                let window = Input::Event(Ok(Event::Window(e)));
+                if let Some(stop) = handle_input(i3, engine, args, window) {
+                    return stop;
+                }
+            }
+        }
 
-            print_status(split.parse().ok()?)
+        if let Some(input) = input {
+            if let Some(stop) = handle_input(i3, engine, args, input) {
+                return stop;
+            }
+        }
+        if errors == EVENT_ERRORS {
+            warn!("the events from i3 keep failing, subscribing again");
+            return Stop::Resubscribe;
         }
-        _ => {}
     }
+}
 
-    Some(())
+/// Saves the overrides of `engine` to `path` if they changed since they were `saved`.
+fn save_state(engine: &LayoutEngine, path: Option<&Path>, saved: &mut Overrides) {
+    let overrides = engine.state().overrides();
+    if overrides == *saved {
+        return;
+    }
+    if let Some(path) = path {
+        if let Err(e) = overrides.save(path) {
+            error!("{e}");
+        }
+    }
+    *saved = overrides;
 }
 
-fn print_status(split: I3Split) {
-    match split {
-        I3Split::Tabbed => println!("t"),
-        I3Split::Stacked => println!("s"),
-        I3Split::Vertical => PREVIOUS_SPLIT.with(|prev| {
-            *prev.borrow_mut() = I3Split::Vertical;
-            println!(" ↓")
-        }),
-        I3Split::Horizontal => PREVIOUS_SPLIT.with(|prev| {
-            *prev.borrow_mut() = I3Split::Horizontal;
-            println!("→")
-        }),
-        I3Split::Toggle => PREVIOUS_SPLIT.with(|prev| {
-            if *prev.borrow() == I3Split::Vertical {
-                print_status(I3Split::Horizontal)
-            } else if *prev.borrow() == I3Split::Horizontal {
-                print_status(I3Split::Vertical)
+/// Handles a single input, returning whether to stop listening.
+fn handle_input(
+    i3: &mut impl WmConnection,
+    engine: &mut LayoutEngine,
+    args: &Args,
+    input: Input,
+) -> Option<Stop> {
+    let event = match input {
+        Input::Event(event) => event,
+        Input::Control(command, reply) => {
+            let _span = logger::span("control", [("command", format!("{command:?}"))]);
+            let health = command == Command::Health;
+            let (mut response, commands) = engine.handle_control(i3, command);
+            if health {
+                let subscribed = SUBSCRIBED.load(Ordering::Relaxed);
+                response["subscribed"] = subscribed.into();
+                if !subscribed && response["success"] == true {
+                    response["success"] = false.into();
+                    response["error"] = "not receiving events from i3".into();
+                }
             }
-        }),
+            let _ = reply.send(response);
+            engine.execute(i3, commands);
+            return None;
+        }
+        Input::Signal(Signal::Usr1) => {
+            let _span = logger::span("signal", [("signal", "USR1".to_owned())]);
+            let (_, commands) = engine.handle_control(i3, Command::Toggle(None));
+            engine.execute(i3, commands);
+            return None;
+        }
+        Input::Signal(signal @ (Signal::Term | Signal::Int)) => {
+            let name = if signal == Signal::Term {
+                "SIGTERM"
+            } else {
+                "SIGINT"
+            };
+            info!("exiting on {name}");
+            if engine.config().restore_on_exit {
+                let commands = engine.restore_layouts(i3);
+                engine.execute(i3, commands);
+            }
+            let config = engine.config();
+            config.output.print_disabled(config);
+            return Some(Stop::Exit);
+        }
+        Input::Replace => {
+            info!("another instance is taking over");
+            return Some(Stop::Exit);
+        }
+        Input::StatusLine(blocks) => {
+            output::set_wrapped(engine.config(), blocks);
+            return None;
+        }
+        Input::Click(button) => {
+            let _span = logger::span("click", [("button", button.to_string())]);
+            let commands = engine.handle_click(i3, button);
+            engine.execute(i3, commands);
+            return None;
+        }
+        Input::Signal(Signal::Hup) | Input::Reload => {
+            let _span = logger::span("reload", []);
+            reload_config(i3, engine, args);
+            return None;
+        }
+    };
+    let event = match event {
+        Ok(event) => event,
+        Err(ipc::Error::Io(e)) => {
+            warn!("lost connection to i3: {e}");
+            return Some(Stop::Reconnect);
+        }
+        Err(e) => {
+            error!("{e}");
+            return None;
+        }
+    };
+    trace!("{event:?}");
+
+    let _span = match &event {
+        Event::Output(e) => logger::span("output", [("change", e.change.clone())]),
+        Event::Mode(e) => logger::span("mode", [("change", e.change.clone())]),
+        Event::Window(e) => logger::span("window", [("change", format!("{:?}", e.change))]),
+        Event::Binding(e) => {
+            logger::span("binding", [("command", format!("{:?}", e.binding.command))])
+        }
+        Event::Shutdown(e) => logger::span("shutdown", [("change", format!("{:?}", e.change))]),
+        Event::Tick(e) => logger::span("tick", [("payload", format!("{:?}", e.payload))]),
+    };
+    match event {
+        Event::Shutdown(e) if e.change == ShutdownChange::Exit => {
+            info!("i3 is exiting");
+            let config = engine.config();
+            config.output.print_disabled(config);
+            return Some(Stop::Exit);
+        }
+        Event::Shutdown(_) => {
+            // The engine and its state are kept across the reconnect.
+            info!("i3 is restarting, reconnecting once it is back");
+            systemd::notify("STATUS=waiting for i3 to restart");
+            return Some(Stop::Reconnect);
+        }
+        event => {
+            let start = Instant::now();
+            let commands = engine.handle_event(i3, &event);
+            engine.execute(i3, commands);
+            engine.state_mut().stats.record_latency(start.elapsed());
+        }
     }
+
+    None
 }
@@ -0,0 +1,129 @@
+//! Serves the counters of the `stats` control command at `/metrics` on a localhost port, in the
+//! Prometheus text format. Only built with the `metrics` feature.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::mpsc::{self, Sender},
+    thread,
+    time::Duration,
+};
+
+use log::warn;
+use serde_json::Value;
+
+use crate::{control::Command, Input};
+
+const PREFIX: &str = "i3_alternating_layout";
+/// How long to wait for the main loop to hand over the counters.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Listens on `port` of the loopback interface, getting the counters from the main loop through
+/// `inputs`.
+pub fn serve(port: u16, inputs: Sender<Input>) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &inputs) {
+                warn!("couldn't serve the metrics: {e}");
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, inputs: &Sender<Input>) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let (status, body) = if request.starts_with("GET /metrics ") {
+        let (reply, response) = mpsc::channel();
+        let _ = inputs.send(Input::Control(Command::Stats, reply));
+        match response.recv_timeout(TIMEOUT) {
+            Ok(stats) => ("200 OK", render(&stats)),
+            Err(_) => (
+                "503 Service Unavailable",
+                "the daemon didn't answer\n".to_owned(),
+            ),
+        }
+    } else {
+        ("404 Not Found", "only /metrics is served\n".to_owned())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// The response of the `stats` command as Prometheus metrics, with the latencies in seconds.
+fn render(stats: &Value) -> String {
+    let mut metrics = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, Value)>| {
+        metrics += &format!("# HELP {PREFIX}_{name} {help}\n# TYPE {PREFIX}_{name} {kind}\n");
+        for (labels, value) in samples {
+            metrics += &format!("{PREFIX}_{name}{labels} {value}\n");
+        }
+    };
+    let single = |key: &str| vec![(String::new(), stats[key].clone())];
+
+    metric(
+        "uptime_seconds",
+        "gauge",
+        "Seconds since the daemon started.",
+        single("uptime"),
+    );
+    let events = stats["events"].as_object().into_iter().flatten();
+    metric(
+        "events_total",
+        "counter",
+        "Events handled, by type.",
+        events
+            .map(|(event, count)| (format!("{{type=\"{event}\"}}"), count.clone()))
+            .collect(),
+    );
+    metric(
+        "commands_total",
+        "counter",
+        "Commands sent to i3.",
+        single("commands"),
+    );
+    metric(
+        "failed_commands_total",
+        "counter",
+        "Commands i3 rejected.",
+        single("failed_commands"),
+    );
+    metric(
+        "reconnects_total",
+        "counter",
+        "Reconnections to i3.",
+        single("reconnects"),
+    );
+
+    let histogram = &stats["latency_histogram"];
+    let mut samples: Vec<_> = histogram["buckets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|bucket| {
+            let bound = bucket[0].as_f64().unwrap_or_default() / 1000.0;
+            (format!("_bucket{{le=\"{bound}\"}}"), bucket[1].clone())
+        })
+        .collect();
+    samples.push((
+        "_bucket{le=\"+Inf\"}".to_owned(),
+        histogram["count"].clone(),
+    ));
+    samples.push(("_count".to_owned(), histogram["count"].clone()));
+    let sum = histogram["sum_ms"].as_f64().unwrap_or_default() / 1000.0;
+    samples.push(("_sum".to_owned(), sum.into()));
+    metric(
+        "latency_seconds",
+        "histogram",
+        "How long events took to decide and carry out.",
+        samples,
+    );
+    metrics
+}
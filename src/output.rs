@@ -0,0 +1,608 @@
+use std::{
+    cell::{Cell, RefCell},
+    ffi::CString,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, Write},
+    iter,
+    os::{
+        raw::{c_char, c_int},
+        unix::{ffi::OsStrExt, fs::FileTypeExt},
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{debug, error, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{config::Config, dbus, engine::Status, scheme::Scheme, I3Split, Input};
+
+/// How the status is written.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum OutputMode {
+    /// One glyph per line, e.g. for i3blocks or bars tailing a script.
+    #[default]
+    Plain,
+    /// The i3bar protocol, so the daemon can be used as a `status_command` directly.
+    I3bar,
+    /// JSON lines for a waybar custom module with `return-type = "json"`.
+    Waybar,
+    /// Sends the status to a polybar `custom/ipc` module through `polybar-msg`.
+    Polybar,
+    /// A JSON object per line with the state and the focused workspace, for scripts.
+    Json,
+    /// Plain lines written to a FIFO instead of stdout, for readers that come and go.
+    Fifo(PathBuf),
+    /// Sets the name of the X root window with `xsetroot`, which dwm-style bars show.
+    Rootname,
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(OutputMode::Plain),
+            "i3bar" => Ok(OutputMode::I3bar),
+            "waybar" => Ok(OutputMode::Waybar),
+            "polybar" => Ok(OutputMode::Polybar),
+            "json" => Ok(OutputMode::Json),
+            "rootname" => Ok(OutputMode::Rootname),
+            _ => match s.strip_prefix("fifo:") {
+                Some(path) if !path.is_empty() => Ok(OutputMode::Fifo(path.into())),
+                _ => Err(format!("unknown output mode '{s}'")),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for OutputMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Passes the statuses to the thread writing them to the FIFO.
+static FIFO: OnceLock<Sender<String>> = OnceLock::new();
+
+extern "C" {
+    fn mkfifo(path: *const c_char, mode: u32) -> c_int;
+}
+
+/// Passes what is written to stdout or run for the bar to the thread doing it.
+static TASKS: OnceLock<Sender<Task>> = OnceLock::new();
+
+/// Something for the output thread to do.
+enum Task {
+    /// A line written in any case, e.g. the header of the i3bar protocol.
+    Line(String),
+    /// A status, which is skipped if another arrives before it is written.
+    Status(Sink, String),
+    /// Answered once everything before it is written.
+    Flush(Sender<()>),
+}
+
+/// Where a status goes.
+enum Sink {
+    Stdout,
+    /// A status line of the endless i3bar array on stdout.
+    I3bar,
+    /// The `custom/ipc` module of polybar with this name.
+    Polybar(String),
+    Rootname,
+}
+
+/// Hands `task` to the output thread, starting it first if needed.
+fn send(task: Task) {
+    let tasks = TASKS.get_or_init(|| {
+        let (sender, tasks) = mpsc::channel();
+        thread::spawn(move || run_tasks(tasks));
+        sender
+    });
+    let _ = tasks.send(task);
+}
+
+/// Does the tasks in order. If the bar reads slower than statuses are written, the statuses it
+/// missed are skipped instead of piling up.
+fn run_tasks(tasks: Receiver<Task>) {
+    let mut first_block = true;
+    while let Ok(task) = tasks.recv() {
+        let mut status = None;
+        let mut flushed = Vec::new();
+        for task in iter::once(task).chain(tasks.try_iter()) {
+            match task {
+                Task::Line(line) => write_line(&line),
+                Task::Status(sink, text) => status = Some((sink, text)),
+                Task::Flush(done) => flushed.push(done),
+            }
+        }
+        match status {
+            Some((Sink::Stdout, text)) => write_line(&text),
+            Some((Sink::I3bar, text)) => {
+                let separator = if first_block { "" } else { "," };
+                first_block = false;
+                write_line(&format!("{separator}{text}"));
+            }
+            Some((Sink::Polybar(module), text)) => send_to_polybar(&module, &text),
+            Some((Sink::Rootname, text)) => set_root_name(&text),
+            None => {}
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Writes `line` to stdout. Failing to, e.g. because the bar exited, is reported once.
+fn write_line(line: &str) {
+    let mut stdout = io::stdout().lock();
+    match writeln!(stdout, "{line}").and_then(|()| stdout.flush()) {
+        Ok(()) => STDOUT_BROKEN.set(false),
+        Err(e) if !STDOUT_BROKEN.replace(true) => error!("couldn't write the status: {e}"),
+        Err(_) => {}
+    }
+}
+
+/// Waits up to `timeout` for the output thread to write what it was given, e.g. before exiting.
+pub fn flush(timeout: Duration) {
+    if TASKS.get().is_some() {
+        let (done, flushed) = mpsc::channel();
+        send(Task::Flush(done));
+        let _ = flushed.recv_timeout(timeout);
+    }
+}
+
+thread_local! {
+    static STDOUT_BROKEN: Cell<bool> = const { Cell::new(false) };
+    static POLYBAR_MSG_MISSING: Cell<bool> = const { Cell::new(false) };
+    static XSETROOT_MISSING: Cell<bool> = const { Cell::new(false) };
+    /// The last status written, so that unchanged ones can be skipped.
+    static LAST_STATUS: RefCell<Option<String>> = const { RefCell::new(None) };
+    static PLACEHOLDERS: RefCell<Placeholders> = const {
+        RefCell::new(Placeholders {
+            workspace: String::new(),
+            windows: 0,
+            container_windows: 0,
+            scheme: "",
+        })
+    };
+    /// The blocks of the latest status line of the wrapped i3status, as JSON.
+    static WRAPPED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// What the placeholders other than `{glyph}` are replaced with.
+struct Placeholders {
+    workspace: String,
+    windows: usize,
+    container_windows: usize,
+    scheme: &'static str,
+}
+
+/// Creates a FIFO at `path`, replacing one left behind by an earlier run.
+fn create_fifo(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a FIFO", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `c_path` is a valid NUL-terminated string for the duration of the call.
+    if unsafe { mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Writes the statuses sent on the returned channel to a FIFO created at `path`, one per line.
+/// Opening the FIFO waits for a reader, which gets the latest status first, and is done again
+/// once writing fails because the reader went away.
+fn serve_fifo(path: PathBuf) -> io::Result<Sender<String>> {
+    create_fifo(&path)?;
+    let (sender, statuses) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        let Ok(mut latest) = statuses.recv() else {
+            return;
+        };
+        loop {
+            let mut fifo = match OpenOptions::new().write(true).open(&path) {
+                Ok(fifo) => fifo,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    warn!("{} was removed, creating it again", path.display());
+                    if let Err(e) = create_fifo(&path) {
+                        error!("couldn't create {}: {e}", path.display());
+                        return;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    error!("couldn't open {}: {e}", path.display());
+                    return;
+                }
+            };
+            loop {
+                // Only the latest of the statuses written while waiting for a reader matters.
+                if let Some(status) = statuses.try_iter().last() {
+                    latest = status;
+                }
+                if writeln!(fifo, "{latest}").is_err() {
+                    debug!("the reader of {} went away", path.display());
+                    break;
+                }
+                match statuses.recv() {
+                    Ok(status) => latest = status,
+                    Err(_) => return,
+                }
+            }
+        }
+    });
+    Ok(sender)
+}
+
+/// Reads the i3bar stream of i3status or i3status-rust from stdin, sending each status line as
+/// `Input::StatusLine` so the status can be added to it.
+pub fn wrap(inputs: Sender<Input>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("couldn't read the wrapped status: {e}");
+                    return;
+                }
+            };
+            // The header and the start of the endless array go before the first status line.
+            let line = line.trim().trim_start_matches(',');
+            if line.is_empty() || line == "[" || line.starts_with('{') {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(blocks) => {
+                    if inputs.send(Input::StatusLine(blocks)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warn!("skipping a malformed status line: {e}"),
+            }
+        }
+        warn!("the wrapped status command exited");
+    });
+}
+
+/// Reads the click events i3bar and i3blocks write to stdin, sending the button of each as
+/// `Input::Click`.
+pub fn read_clicks(inputs: Sender<Input>) {
+    #[derive(Deserialize)]
+    struct Click {
+        button: u32,
+    }
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            // i3bar sends an endless array, one click per line.
+            let line = line.trim().trim_start_matches(',');
+            if line.is_empty() || line == "[" {
+                continue;
+            }
+            match serde_json::from_str::<Click>(line) {
+                Ok(click) => {
+                    if inputs.send(Input::Click(click.button)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => debug!("ignoring '{line}' on stdin: {e}"),
+            }
+        }
+    });
+}
+
+/// Replaces the blocks of the wrapped status, writing them with the latest status.
+pub fn set_wrapped(config: &Config, blocks: Vec<Value>) {
+    WRAPPED.set(blocks.iter().map(Value::to_string).collect());
+    if let Some(status) = LAST_STATUS.with_borrow(Clone::clone) {
+        if !config.quiet {
+            print_i3bar(&status);
+        }
+    }
+}
+
+/// Writes a status line of i3bar with the block for `status` and those of the wrapped status.
+fn print_i3bar(status: &str) {
+    WRAPPED.with_borrow(|wrapped| {
+        let blocks: Vec<_> = iter::once(status)
+            .chain(wrapped.iter().map(String::as_str))
+            .collect();
+        send(Task::Status(Sink::I3bar, format!("[{}]", blocks.join(","))));
+    });
+}
+
+/// Remembers the focused workspace for the placeholders of the following statuses, with the
+/// number of windows on it and in the focused container.
+pub fn set_workspace(name: &str, windows: usize, container_windows: usize, scheme: Scheme) {
+    PLACEHOLDERS.with_borrow_mut(|placeholders| {
+        placeholders.workspace.clear();
+        placeholders.workspace.push_str(name);
+        placeholders.windows = windows;
+        placeholders.container_windows = container_windows;
+        placeholders.scheme = scheme.name();
+    });
+}
+
+/// Remembers the scheme of the focused workspace after it changed.
+pub fn set_scheme(scheme: Scheme) {
+    PLACEHOLDERS.with_borrow_mut(|placeholders| placeholders.scheme = scheme.name());
+}
+
+/// Fills in the placeholders of `template`. Unknown placeholders are left as they are, and `{{`
+/// and `}}` stand for literal braces.
+fn render(template: &str, glyph: &str) -> String {
+    PLACEHOLDERS.with_borrow(|placeholders| {
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                text.push_str(&rest[..1]);
+                rest = &rest[2..];
+                continue;
+            }
+
+            let end = rest.find('}').filter(|_| rest.starts_with('{'));
+            let value = end.and_then(|end| match &rest[1..end] {
+                "glyph" => Some(glyph.to_owned()),
+                "workspace" => Some(placeholders.workspace.clone()),
+                "windows" => Some(placeholders.windows.to_string()),
+                "container_windows" => Some(placeholders.container_windows.to_string()),
+                "scheme" => Some(placeholders.scheme.to_owned()),
+                _ => None,
+            });
+            match (end, value) {
+                (Some(end), Some(value)) => {
+                    text.push_str(&value);
+                    rest = &rest[end + 1..];
+                }
+                _ => {
+                    text.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        text.push_str(rest);
+        text
+    })
+}
+
+impl OutputMode {
+    /// Writes whatever the protocol expects before the first status, asking for click events
+    /// if `clicks` is set, or creates the FIFO.
+    pub fn start(&self, clicks: bool) -> io::Result<()> {
+        match self {
+            OutputMode::Plain
+            | OutputMode::Waybar
+            | OutputMode::Polybar
+            | OutputMode::Json
+            | OutputMode::Rootname => {}
+            OutputMode::I3bar => {
+                send(Task::Line(
+                    json!({ "version": 1, "click_events": clicks }).to_string(),
+                ));
+                send(Task::Line("[".to_owned()));
+            }
+            OutputMode::Fifo(path) => {
+                let _ = FIFO.set(serve_fifo(path.clone())?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the status for `split`, or an empty status if there is nothing to show.
+    pub fn print(&self, config: &Config, split: Option<&I3Split>) {
+        self.print_split(config, split, false)
+    }
+
+    /// Writes the status for `split`, in the color for windows left alone if `ignored` is set.
+    fn print_split(&self, config: &Config, split: Option<&I3Split>, ignored: bool) {
+        let text = split.map_or_else(String::new, |split| {
+            render(config.format.get(split), config.glyphs.get(split))
+        });
+        let color = split.and_then(|split| config.colors.get(split));
+        let color = config
+            .colors
+            .ignored
+            .as_deref()
+            .filter(|_| ignored)
+            .or(color);
+        let tooltip = match split {
+            Some(split @ (I3Split::Tabbed | I3Split::Stacked)) => {
+                format!("Layout: {}", split.name())
+            }
+            Some(split) if ignored => format!("Not splitting, the layout is {}", split.name()),
+            Some(split) => format!("Next split: {}", split.name()),
+            None => String::new(),
+        };
+        self.write(
+            config,
+            text,
+            color,
+            split.map_or("none", I3Split::name),
+            tooltip,
+        );
+    }
+
+    /// Writes `status` with the function for its kind.
+    pub fn print_status(&self, config: &Config, status: &Status) {
+        match status {
+            Status::Split(split) => self.print(config, Some(split)),
+            Status::Ignored(split) => self.print_split(config, Some(split), true),
+            Status::Paused => self.print_paused(config),
+            Status::Floating => self.print_floating(config),
+            Status::Empty => self.print(config, None),
+        }
+    }
+
+    /// Writes that automatic splitting is paused.
+    pub fn print_paused(&self, config: &Config) {
+        let text = render(&config.format.paused, &config.glyphs.paused);
+        let color = config.colors.paused.as_deref();
+        self.write(config, text, color, "paused", "Paused".to_owned());
+    }
+
+    /// Writes an empty status for when the daemon exits.
+    pub fn print_disabled(&self, config: &Config) {
+        self.write(
+            config,
+            String::new(),
+            None,
+            "disabled",
+            "Not running".to_owned(),
+        );
+    }
+
+    /// Writes that a floating window is focused, if there is a glyph for it.
+    pub fn print_floating(&self, config: &Config) {
+        if let Some(glyph) = &config.glyphs.floating {
+            let text = render(&config.format.floating, glyph);
+            let color = config.colors.floating.as_deref();
+            self.write(config, text, color, "floating", "Floating".to_owned());
+        }
+    }
+
+    /// Writes `text` in the format of the output mode. `class` names the state for waybar, D-Bus
+    /// and the JSON output, and `tooltip` is only used by waybar.
+    fn write(
+        &self,
+        config: &Config,
+        text: String,
+        color: Option<&str>,
+        class: &str,
+        tooltip: String,
+    ) {
+        let status = match self {
+            OutputMode::Plain | OutputMode::Fifo(_) | OutputMode::Rootname => text,
+            OutputMode::Polybar => match color {
+                Some(color) => format!("%{{F{color}}}{text}%{{F-}}"),
+                None => text,
+            },
+            OutputMode::I3bar => {
+                let mut block = json!({
+                    "name": "alternating_layout",
+                    "full_text": text,
+                    "short_text": text.trim(),
+                });
+                if let Some(color) = color {
+                    block["color"] = color.into();
+                }
+                if config.markup {
+                    block["markup"] = "pango".into();
+                }
+                block.to_string()
+            }
+            OutputMode::Waybar => {
+                json!({ "text": text, "class": class, "tooltip": tooltip }).to_string()
+            }
+            OutputMode::Json => PLACEHOLDERS.with_borrow(|placeholders| {
+                let paused = class == "paused";
+                json!({
+                    "split": (!paused).then_some(class),
+                    "workspace": placeholders.workspace,
+                    "windows": placeholders.windows,
+                    "container_windows": placeholders.container_windows,
+                    "scheme": placeholders.scheme,
+                    "paused": paused,
+                })
+                .to_string()
+            }),
+        };
+
+        let previous = LAST_STATUS.replace(Some(status.clone()));
+        if previous.as_ref() == Some(&status) && !config.force {
+            return;
+        }
+
+        // Quiet only silences the output, the D-Bus signal is still sent.
+        dbus::layout_changed(class);
+        if config.quiet {
+            return;
+        }
+
+        match self {
+            OutputMode::Plain | OutputMode::Waybar | OutputMode::Json => {
+                send(Task::Status(Sink::Stdout, status))
+            }
+            OutputMode::I3bar => print_i3bar(&status),
+            OutputMode::Polybar => send(Task::Status(
+                Sink::Polybar(config.polybar_module.clone()),
+                status,
+            )),
+            OutputMode::Fifo(_) => {
+                if let Some(fifo) = FIFO.get() {
+                    let _ = fifo.send(status);
+                }
+            }
+            OutputMode::Rootname => send(Task::Status(Sink::Rootname, status)),
+        }
+    }
+}
+
+/// Runs `polybar-msg action "#<module>.send.<text>"`. Failures because polybar isn't running
+/// are expected (e.g. while it restarts) and ignored.
+fn send_to_polybar(module: &str, text: &str) {
+    if POLYBAR_MSG_MISSING.get() {
+        return;
+    }
+
+    let status = Command::new("polybar-msg")
+        .arg("action")
+        .arg(format!("#{module}.send.{text}"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if let Err(e) = status {
+        if e.kind() == io::ErrorKind::NotFound {
+            POLYBAR_MSG_MISSING.set(true);
+        }
+        error!("couldn't run polybar-msg: {e}");
+    }
+}
+
+/// Runs `xsetroot -name <text>`. Not having xsetroot is reported once.
+fn set_root_name(text: &str) {
+    if XSETROOT_MISSING.get() {
+        return;
+    }
+
+    let status = Command::new("xsetroot")
+        .arg("-name")
+        .arg(text)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if !status.success() => warn!("xsetroot failed: {status}"),
+        Ok(_) => {}
+        Err(e) => {
+            if e.kind() == io::ErrorKind::NotFound {
+                XSETROOT_MISSING.set(true);
+            }
+            error!("couldn't run xsetroot: {e}");
+        }
+    }
+}
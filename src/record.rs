@@ -0,0 +1,132 @@
+//! Recording the events the daemon handles and the trees it fetches, and replaying them
+//! without i3, so a bug report can come with a trace that reproduces it.
+//!
+//! A recording has one JSON object per line: `{"event": ...}` for each event handled, followed
+//! by `{"tree": ...}` for each tree fetched while handling it.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    vec,
+};
+
+use log::warn;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    config::Config,
+    engine::LayoutEngine,
+    ipc::{
+        event::Event,
+        reply::{Node, Version},
+        Error, Subscription, WmConnection,
+    },
+};
+
+/// The file being recorded to, if any.
+static RECORDING: OnceLock<Mutex<File>> = OnceLock::new();
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Event(Event),
+    Tree(Box<Node>),
+}
+
+/// Starts appending what is handled to `path`.
+pub fn start(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    RECORDING
+        .set(Mutex::new(file))
+        .map_err(|_| io::Error::new(io::ErrorKind::AlreadyExists, "already recording"))
+}
+
+/// Records `event`, if recording.
+pub fn event(event: &Event) {
+    write(|| json!({ "event": event }));
+}
+
+/// Records a tree fetched from i3, if recording.
+pub fn tree(tree: &Node) {
+    write(|| json!({ "tree": tree }));
+}
+
+/// Appends the line made by `entry`, which is only made while recording.
+fn write(entry: impl FnOnce() -> Value) {
+    let Some(file) = RECORDING.get() else {
+        return;
+    };
+    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = writeln!(file, "{}", entry()) {
+        warn!("couldn't record: {e}");
+    }
+}
+
+/// Feeds the events recorded at `path` to an engine with `config`, serving each the trees that
+/// were fetched while handling it. The commands are logged instead of run.
+pub fn replay(mut config: Config, path: &Path) -> Result<(), String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+    let mut events = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let entry = serde_json::from_str(line)
+            .map_err(|e| format!("{}:{}: {e}", path.display(), number + 1))?;
+        match entry {
+            Entry::Event(event) => events.push((event, VecDeque::new())),
+            Entry::Tree(tree) => match events.last_mut() {
+                Some((_, trees)) => trees.push_back(*tree),
+                None => warn!("skipping a tree recorded before the first event"),
+            },
+        }
+    }
+
+    config.dry_run = true;
+    let mut engine = LayoutEngine::new(config);
+    for (event, trees) in events {
+        let mut wm = Replay { trees };
+        let commands = engine.handle_event(&mut wm, &event);
+        engine.execute(&mut wm, commands);
+    }
+    Ok(())
+}
+
+/// Serves recorded trees in order, repeating the last one.
+struct Replay {
+    trees: VecDeque<Node>,
+}
+
+impl WmConnection for Replay {
+    type Events = vec::IntoIter<Result<Event, Error>>;
+
+    fn get_tree(&mut self) -> Result<Node, Error> {
+        match self.trees.len() {
+            0 => Err(Error::Rejected("no tree was recorded".to_owned())),
+            1 => Ok(self.trees[0].clone()),
+            _ => Ok(self.trees.pop_front().unwrap_or_default()),
+        }
+    }
+
+    fn run_command(&mut self, _: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_version(&mut self) -> Result<Version, Error> {
+        Ok(Version::default())
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_config(&mut self) -> Result<String, Error> {
+        Ok(String::new())
+    }
+
+    fn subscribe(&mut self, _: &[Subscription]) -> Result<Self::Events, Error> {
+        Ok(Vec::new().into_iter())
+    }
+}
@@ -0,0 +1,270 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ipc::reply::{Node, NodeLayout},
+    I3Split,
+};
+
+/// How new windows are arranged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scheme {
+    /// Split along the longer side of the container.
+    #[default]
+    Alternating,
+    /// Alternate by depth, placing every new window so they wind inwards like a Fibonacci spiral.
+    Spiral,
+    /// Alternate by depth, so every new window takes half of the most recent one.
+    Dwindle,
+    /// Keep the first window in a large master area and stack the others in a column next to it.
+    MasterStack,
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alternating" => Ok(Scheme::Alternating),
+            "spiral" => Ok(Scheme::Spiral),
+            "dwindle" => Ok(Scheme::Dwindle),
+            "master_stack" | "master-stack" => Ok(Scheme::MasterStack),
+            _ => Err(format!(
+                "unknown scheme '{s}', expected one of {}",
+                Scheme::NAMES
+            )),
+        }
+    }
+}
+
+/// What a scheme gets to look at to make its decisions.
+pub struct Context<'a> {
+    pub focused: &'a Node,
+    pub parent: &'a Node,
+    pub workspace: Option<&'a Node>,
+    /// How many containers deep below its workspace the focused window is.
+    pub depth: usize,
+    pub ratio: f64,
+    /// The master window the workspace had so far, for `MasterStack`, see `master`.
+    pub master: Option<i64>,
+    /// The share of the workspace width given to the master window.
+    pub master_ratio: f64,
+    /// The inner gaps, see `usable_size`.
+    pub gaps: u32,
+}
+
+impl Context<'_> {
+    /// The master window of the workspace: the one it had so far while that is still there, or
+    /// else its first window.
+    pub fn master(&self) -> Option<i64> {
+        fn first_window(mut node: &Node) -> &Node {
+            while let Some(first) = node.nodes.first() {
+                node = first;
+            }
+            node
+        }
+
+        let workspace = self.workspace?;
+        match self.master {
+            Some(id) if workspace.descendants().any(|n| n.id == id) => Some(id),
+            _ => Some(first_window(workspace).id),
+        }
+    }
+}
+
+/// The width and height `node` has for its windows: the client area of a window, without its
+/// borders and title bar, or the rect of a container, less half of the `gaps` on every side.
+pub fn usable_size(node: &Node, gaps: u32) -> (i32, i32) {
+    // rect: (x, y, width, height)
+    let (_, _, width, height) = match node.window_rect {
+        (_, _, width, height) if width > 0 && height > 0 => node.window_rect,
+        _ => node.rect,
+    };
+    let gaps = gaps.min(i32::MAX as u32) as i32;
+    ((width - gaps).max(0), (height - gaps).max(0))
+}
+
+impl Scheme {
+    pub const NAMES: &'static str = "alternating, spiral, dwindle, master_stack";
+    const ALL: [Scheme; 4] = [
+        Scheme::Alternating,
+        Scheme::Spiral,
+        Scheme::Dwindle,
+        Scheme::MasterStack,
+    ];
+
+    /// The scheme after this one in `NAMES`, or the one before it, wrapping around.
+    pub fn cycle(self, backwards: bool) -> Scheme {
+        let index = Scheme::ALL
+            .iter()
+            .position(|&s| s == self)
+            .unwrap_or_default();
+        let step = if backwards { Scheme::ALL.len() - 1 } else { 1 };
+        Scheme::ALL[(index + step) % Scheme::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scheme::Alternating => "alternating",
+            Scheme::Spiral => "spiral",
+            Scheme::Dwindle => "dwindle",
+            Scheme::MasterStack => "master_stack",
+        }
+    }
+
+    /// The orientation to split the focused window in, if it should be split at all.
+    pub fn split(self, context: &Context) -> Option<I3Split> {
+        match self {
+            Scheme::Alternating => {
+                let (width, height) = usable_size(context.parent, context.gaps);
+                if f64::from(width) * context.ratio > f64::from(height) {
+                    Some(I3Split::Horizontal)
+                } else {
+                    Some(I3Split::Vertical)
+                }
+            }
+            Scheme::Spiral | Scheme::Dwindle if context.depth % 2 == 1 => Some(I3Split::Horizontal),
+            Scheme::Spiral | Scheme::Dwindle => Some(I3Split::Vertical),
+            // A lone master makes room for the stack to its right, and the first stacked window
+            // starts the column. Splitting anything else would only nest containers.
+            Scheme::MasterStack if context.depth == 1 && context.parent.nodes.len() == 1 => {
+                Some(I3Split::Horizontal)
+            }
+            Scheme::MasterStack
+                if context.depth == 1
+                    && context.parent.nodes.len() == 2
+                    && context.master() != Some(context.focused.id) =>
+            {
+                Some(I3Split::Vertical)
+            }
+            Scheme::MasterStack => None,
+        }
+    }
+
+    /// Commands moving a newly opened (and focused) window into place.
+    pub fn placement(self, context: &Context) -> Option<String> {
+        match self {
+            Scheme::Alternating | Scheme::Dwindle => None,
+            // Windows open to the right of or below the focused one. Every other pair of
+            // windows is moved to the other side, so the spiral turns left and then up.
+            Scheme::Spiral if context.depth >= 4 && context.depth % 4 < 2 => {
+                match context.parent.layout {
+                    NodeLayout::SplitH => Some("move left".to_owned()),
+                    NodeLayout::SplitV => Some("move up".to_owned()),
+                    _ => None,
+                }
+            }
+            Scheme::Spiral => None,
+            Scheme::MasterStack if context.depth == 1 => {
+                let master = context.master()?;
+                match context.parent.nodes.len() {
+                    // The master and the first stacked window: size the master.
+                    2 if master != context.focused.id => Some(format!(
+                        "[con_id={master}] resize set width {} ppt",
+                        (context.master_ratio * 100.0).round()
+                    )),
+                    // Opened next to the master: move it into the stack column.
+                    n if n > 2 => Some("move right".to_owned()),
+                    _ => None,
+                }
+            }
+            Scheme::MasterStack => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{
+        fake::{NodeBuilder, TreeBuilder},
+        reply::NodeType,
+    };
+
+    /// A workspace with the focused window `depth` containers deep, next to a window at every
+    /// level. At depth 0 the workspace is empty and focused itself.
+    fn nested(depth: usize) -> Node {
+        let layout = |level| match level % 2 {
+            0 => NodeLayout::SplitH,
+            _ => NodeLayout::SplitV,
+        };
+        let workspace = match depth {
+            0 => NodeBuilder::container(NodeLayout::SplitH, []).focused(),
+            _ => {
+                let mut node = NodeBuilder::window().focused();
+                for level in (1..depth).rev() {
+                    node = NodeBuilder::container(layout(level), [NodeBuilder::window(), node]);
+                }
+                NodeBuilder::container(layout(0), [NodeBuilder::window(), node])
+            }
+        };
+        TreeBuilder::default().workspace("1", workspace).build()
+    }
+
+    /// What `scheme` decides for the focused window of `tree`, which is `depth` deep.
+    fn decide(scheme: Scheme, tree: &Node, depth: usize) -> (Option<I3Split>, Option<String>) {
+        let focused = tree.descendants().find(|n| n.focused).unwrap();
+        let context = Context {
+            focused,
+            parent: tree
+                .descendants()
+                .find(|n| n.nodes.iter().any(|child| child.id == focused.id))
+                .unwrap(),
+            workspace: tree
+                .descendants()
+                .find(|n| n.nodetype == NodeType::Workspace),
+            depth,
+            ratio: 1.0,
+            master: None,
+            master_ratio: 0.6,
+            gaps: 0,
+        };
+        (scheme.split(&context), scheme.placement(&context))
+    }
+
+    #[test]
+    fn spiral_and_dwindle_alternate_by_depth() {
+        let splits = [
+            I3Split::Vertical,
+            I3Split::Horizontal,
+            I3Split::Vertical,
+            I3Split::Horizontal,
+        ];
+        for (depth, split) in splits.into_iter().enumerate() {
+            let tree = nested(depth);
+            for scheme in [Scheme::Spiral, Scheme::Dwindle] {
+                assert_eq!(
+                    decide(scheme, &tree, depth),
+                    (Some(split), None),
+                    "{scheme:?} at depth {depth}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dwindle_keeps_going_where_spiral_turns() {
+        // Spiral moves every other pair of new windows to the other side of the one before
+        // them, up in vertical containers and left in horizontal ones.
+        let spiral = [
+            Some("move up"),
+            Some("move left"),
+            None,
+            None,
+            Some("move up"),
+            Some("move left"),
+        ];
+        for (depth, placement) in (4..).zip(spiral) {
+            let tree = nested(depth);
+            let (_, spiral) = decide(Scheme::Spiral, &tree, depth);
+            assert_eq!(spiral.as_deref(), placement, "spiral at depth {depth}");
+            assert_eq!(
+                decide(Scheme::Dwindle, &tree, depth).1,
+                None,
+                "dwindle at depth {depth}"
+            );
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Delivers Unix signals to the main loop as inputs, from a thread waiting for them with
+//! `sigwait` instead of running code in a signal handler.
+
+use std::{io, os::raw::c_int, ptr, sync::mpsc::Sender, thread};
+
+use crate::Input;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    /// Toggles pausing.
+    Usr1,
+    /// Reloads the config file.
+    Hup,
+    /// Both exit cleanly.
+    Term,
+    Int,
+}
+
+impl Signal {
+    /// The Linux signal number.
+    fn number(self) -> c_int {
+        match self {
+            Signal::Usr1 => 10,
+            Signal::Hup => 1,
+            Signal::Term => 15,
+            Signal::Int => 2,
+        }
+    }
+}
+
+/// `sigset_t`, which is 128 bytes for both glibc and musl.
+#[repr(C)]
+struct SigSet([u64; 16]);
+
+const SIG_BLOCK: c_int = 0;
+
+extern "C" {
+    fn sigemptyset(set: *mut SigSet) -> c_int;
+    fn sigaddset(set: *mut SigSet, signal: c_int) -> c_int;
+    fn pthread_sigmask(how: c_int, set: *const SigSet, old: *mut SigSet) -> c_int;
+    fn sigwait(set: *const SigSet, signal: *mut c_int) -> c_int;
+}
+
+/// Blocks `signals` and forwards them to `inputs` once they are pending. Has to be called before
+/// any other thread is spawned, as only threads spawned afterwards inherit the signal mask.
+pub fn forward(signals: &[Signal], inputs: Sender<Input>) -> io::Result<()> {
+    let mut set = SigSet([0; 16]);
+    // SAFETY: `set` is a valid, initialized sigset_t for the duration of the calls.
+    let e = unsafe {
+        sigemptyset(&mut set);
+        for signal in signals {
+            sigaddset(&mut set, signal.number());
+        }
+        pthread_sigmask(SIG_BLOCK, &set, ptr::null_mut())
+    };
+    if e != 0 {
+        return Err(io::Error::from_raw_os_error(e));
+    }
+
+    let signals = signals.to_vec();
+    thread::spawn(move || loop {
+        let mut number = 0;
+        // SAFETY: as above, and `number` is a valid place to write the signal number to.
+        if unsafe { sigwait(&set, &mut number) } != 0 {
+            return;
+        }
+        let signal = signals.iter().find(|signal| signal.number() == number);
+        if let Some(&signal) = signal {
+            if inputs.send(Input::Signal(signal)).is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
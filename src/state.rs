@@ -0,0 +1,425 @@
+//! What the daemon remembers between events, kept per workspace.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{scheme::Scheme, stats::Stats, I3Split};
+
+/// The binding mode i3 starts in.
+const DEFAULT_MODE: &str = "default";
+
+/// How many automatic changes can be undone.
+const HISTORY: usize = 16;
+
+/// How to reverse an automatic change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Undo {
+    /// Run this command.
+    Run(String),
+    /// Move the window with this id out of the container it was split into.
+    Unwrap(i64),
+}
+
+/// The state of a single workspace.
+#[derive(Debug, Default)]
+pub struct WorkspaceState {
+    /// A scheme chosen at runtime for this workspace, taking precedence over the global one.
+    pub scheme: Option<Scheme>,
+    /// Whether splitting was paused or resumed for this workspace in particular.
+    pub paused: Option<bool>,
+    /// The last split of each container on the workspace, by id.
+    pub splits: HashMap<i64, I3Split>,
+    /// When containers on the workspace were last split by hand, by id.
+    pub manual_splits: HashMap<i64, Instant>,
+    /// The status last printed while the workspace was focused.
+    pub status: Option<I3Split>,
+    /// The containers on the workspace made tabbed for holding too many windows, by id.
+    pub auto_tabbed: HashSet<i64>,
+    /// The container id of the master window, for `MasterStack`.
+    pub master: Option<i64>,
+}
+
+impl WorkspaceState {
+    /// Whether there is anything worth keeping once the workspace is gone.
+    fn is_overridden(&self) -> bool {
+        self.scheme.is_some() || self.paused.is_some()
+    }
+}
+
+/// The schemes and pauses chosen at runtime, which are kept across restarts in a state file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overrides {
+    pub scheme: Option<Scheme>,
+    pub paused: bool,
+    pub workspaces: BTreeMap<String, WorkspaceOverrides>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<Scheme>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused: Option<bool>,
+}
+
+impl Overrides {
+    /// `$XDG_STATE_HOME/i3-alternating-layout/state.json`, falling back to `~/.local/state`.
+    pub fn default_path() -> Option<PathBuf> {
+        let state_home = env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+
+        Some(state_home.join("i3-alternating-layout").join("state.json"))
+    }
+
+    /// Loads the overrides saved at `path`, which there are none of if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("couldn't parse {}: {e}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Overrides::default()),
+            Err(e) => Err(format!("couldn't read {}: {e}", path.display())),
+        }
+    }
+
+    /// Saves the overrides to `path`, replacing the file at once so it is never half written.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let temporary = path.with_extension("json.tmp");
+        let write = || {
+            fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+            fs::write(&temporary, serde_json::to_string_pretty(self)? + "\n")?;
+            fs::rename(&temporary, path)
+        };
+        write().map_err(|e: io::Error| format!("couldn't write {}: {e}", path.display()))
+    }
+}
+
+#[derive(Debug)]
+pub struct State {
+    /// A scheme chosen at runtime for all workspaces, taking precedence over the configured ones.
+    scheme: Option<Scheme>,
+    /// Whether splitting has been paused everywhere.
+    paused: bool,
+    /// The active binding mode, `default` unless one was entered.
+    pub mode: String,
+    /// Whether a container was opted out with a mark when the tree was last read, so windows
+    /// can't be split from their events alone.
+    pub opted_out: bool,
+    /// When the latest event from i3 was handled.
+    pub last_event: Option<SystemTime>,
+    /// Windows that were split into new containers, which are marked as managed once the tree
+    /// shows them.
+    pub unmarked: HashSet<i64>,
+    pub stats: Stats,
+    workspaces: HashMap<String, WorkspaceState>,
+    /// The focused workspace and container, as of the last time the tree was read.
+    focused_workspace: Option<String>,
+    focused: Option<i64>,
+    /// How to undo the latest automatic changes, the most recent last.
+    history: VecDeque<Undo>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            scheme: None,
+            paused: false,
+            mode: DEFAULT_MODE.to_owned(),
+            opted_out: false,
+            last_event: None,
+            unmarked: HashSet::new(),
+            stats: Stats::default(),
+            workspaces: HashMap::new(),
+            focused_workspace: None,
+            focused: None,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl State {
+    /// Records what is focused, forgetting the containers not in `ids` anymore.
+    pub fn focus(&mut self, workspace: Option<&str>, container: Option<i64>, ids: &HashSet<i64>) {
+        self.focused_workspace = workspace.map(str::to_owned);
+        self.focused = container;
+        for ws in self.workspaces.values_mut() {
+            ws.splits.retain(|id, _| ids.contains(id));
+            ws.manual_splits.retain(|id, _| ids.contains(id));
+            ws.auto_tabbed.retain(|id| ids.contains(id));
+            ws.master = ws.master.filter(|id| ids.contains(id));
+        }
+        let focused = self.focused_workspace.as_deref();
+        self.workspaces.retain(|name, ws| {
+            Some(name.as_str()) == focused
+                || !ws.splits.is_empty()
+                || !ws.manual_splits.is_empty()
+                || !ws.auto_tabbed.is_empty()
+                || ws.master.is_some()
+                || ws.is_overridden()
+        });
+    }
+
+    /// Records the focused container, assuming the workspace is still the same.
+    pub fn focus_container(&mut self, container: i64) {
+        self.focused = Some(container);
+    }
+
+    /// Whether all workspaces are in the same state, with nothing overridden for any of them
+    /// and no recent manual splits.
+    pub fn is_uniform(&self) -> bool {
+        self.workspaces
+            .values()
+            .all(|ws| !ws.is_overridden() && ws.manual_splits.is_empty())
+    }
+
+    pub fn focused_workspace(&self) -> Option<&str> {
+        self.focused_workspace.as_deref()
+    }
+
+    /// The state of `workspace`, or of the focused one for `None`.
+    pub fn workspace(&self, workspace: Option<&str>) -> Option<&WorkspaceState> {
+        self.workspaces
+            .get(workspace.or(self.focused_workspace.as_deref())?)
+    }
+
+    fn focused_mut(&mut self) -> Option<&mut WorkspaceState> {
+        let name = self.focused_workspace.clone()?;
+        Some(self.workspaces.entry(name).or_default())
+    }
+
+    /// Whether splitting is paused on `workspace`, or on the focused one for `None`.
+    pub fn is_paused(&self, workspace: Option<&str>) -> bool {
+        self.workspace(workspace)
+            .and_then(|ws| ws.paused)
+            .unwrap_or(self.paused)
+    }
+
+    /// Pauses or resumes splitting on `workspace`, or everywhere for `None`.
+    pub fn set_paused(&mut self, workspace: Option<&str>, paused: bool) {
+        match workspace {
+            Some(name) => self.workspaces.entry(name.to_owned()).or_default().paused = Some(paused),
+            None => {
+                self.paused = paused;
+                self.workspaces.values_mut().for_each(|ws| ws.paused = None);
+            }
+        }
+    }
+
+    /// The scheme chosen at runtime for `workspace`, or for the focused one for `None`.
+    pub fn scheme(&self, workspace: Option<&str>) -> Option<Scheme> {
+        self.workspace(workspace)
+            .and_then(|ws| ws.scheme)
+            .or(self.scheme)
+    }
+
+    /// Overrides the scheme of `workspace`, or of all workspaces for `None`.
+    pub fn set_scheme(&mut self, workspace: Option<&str>, scheme: Option<Scheme>) {
+        match workspace {
+            Some(name) => self.workspaces.entry(name.to_owned()).or_default().scheme = scheme,
+            None => {
+                self.scheme = scheme;
+                self.workspaces.values_mut().for_each(|ws| ws.scheme = None);
+            }
+        }
+    }
+
+    pub fn overrides(&self) -> Overrides {
+        Overrides {
+            scheme: self.scheme,
+            paused: self.paused,
+            workspaces: self
+                .workspaces
+                .iter()
+                .filter(|(_, ws)| ws.is_overridden())
+                .map(|(name, ws)| {
+                    let overrides = WorkspaceOverrides {
+                        scheme: ws.scheme,
+                        paused: ws.paused,
+                    };
+                    (name.clone(), overrides)
+                })
+                .collect(),
+        }
+    }
+
+    /// Replaces the overrides, e.g. with the ones saved by the previous instance.
+    pub fn set_overrides(&mut self, overrides: Overrides) {
+        self.scheme = overrides.scheme;
+        self.paused = overrides.paused;
+        for ws in self.workspaces.values_mut() {
+            (ws.scheme, ws.paused) = (None, None);
+        }
+        for (name, overrides) in overrides.workspaces {
+            let ws = self.workspaces.entry(name).or_default();
+            (ws.scheme, ws.paused) = (overrides.scheme, overrides.paused);
+        }
+    }
+
+    /// The last split of the focused container, assuming horizontal if there was none yet.
+    pub fn previous_split(&self) -> I3Split {
+        self.focused
+            .and_then(|id| self.workspace(None)?.splits.get(&id).copied())
+            .unwrap_or(I3Split::Horizontal)
+    }
+
+    /// Records the split of the focused container.
+    pub fn set_split(&mut self, split: I3Split) {
+        if let Some(id) = self.focused {
+            if let Some(ws) = self.focused_mut() {
+                ws.splits.insert(id, split);
+            }
+        }
+    }
+
+    /// Records that the focused container was just split by hand.
+    pub fn split_manually(&mut self) {
+        if let Some(id) = self.focused {
+            if let Some(ws) = self.focused_mut() {
+                ws.manual_splits.insert(id, Instant::now());
+            }
+        }
+    }
+
+    /// Whether the focused container was split by hand less than `grace_period` ago.
+    pub fn is_split_manually(&self, grace_period: Duration) -> bool {
+        self.focused
+            .and_then(|id| self.workspace(None)?.manual_splits.get(&id))
+            .is_some_and(|time| time.elapsed() < grace_period)
+    }
+
+    /// Forgets about the manual splits on the focused workspace, e.g. once a new window appears.
+    pub fn forget_manual_splits(&mut self) {
+        if let Some(ws) = self.focused_mut() {
+            ws.manual_splits.clear();
+        }
+    }
+
+    /// Whether container `id` on the focused workspace was made tabbed automatically.
+    pub fn is_auto_tabbed(&self, id: i64) -> bool {
+        self.workspace(None)
+            .is_some_and(|ws| ws.auto_tabbed.contains(&id))
+    }
+
+    /// Records whether container `id` on the focused workspace is tabbed automatically.
+    pub fn set_auto_tabbed(&mut self, id: i64, tabbed: bool) {
+        if let Some(ws) = self.focused_mut() {
+            if tabbed {
+                ws.auto_tabbed.insert(id);
+            } else {
+                ws.auto_tabbed.remove(&id);
+            }
+        }
+    }
+
+    /// The master window of `workspace`, for `MasterStack`.
+    pub fn master(&self, workspace: &str) -> Option<i64> {
+        self.workspaces.get(workspace)?.master
+    }
+
+    /// The master windows of all workspaces that have one, by the names of the workspaces.
+    pub fn masters(&self) -> impl Iterator<Item = (&str, i64)> {
+        self.workspaces
+            .iter()
+            .filter_map(|(name, ws)| Some((name.as_str(), ws.master?)))
+    }
+
+    pub fn set_master(&mut self, workspace: &str, master: Option<i64>) {
+        self.workspaces
+            .entry(workspace.to_owned())
+            .or_default()
+            .master = master;
+    }
+
+    /// Keeps the master of `workspace` in the master area after windows `a` and `b` swapped
+    /// places.
+    pub fn swap_master(&mut self, workspace: &str, a: i64, b: i64) {
+        if let Some(ws) = self.workspaces.get_mut(workspace) {
+            match ws.master {
+                Some(master) if master == a => ws.master = Some(b),
+                Some(master) if master == b => ws.master = Some(a),
+                _ => {}
+            }
+        }
+    }
+
+    /// Remembers how to undo an automatic change, forgetting the oldest ones.
+    pub fn record(&mut self, undo: Undo) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(undo);
+    }
+
+    /// Takes how to undo the most recent automatic change.
+    pub fn take_undo(&mut self) -> Option<Undo> {
+        self.history.pop_back()
+    }
+
+    /// The status last printed for `workspace`, or for the focused one for `None`.
+    pub fn status(&self, workspace: Option<&str>) -> I3Split {
+        self.workspace(workspace)
+            .and_then(|ws| ws.status)
+            .unwrap_or_else(|| self.previous_split())
+    }
+
+    pub fn set_status(&mut self, split: I3Split) {
+        if let Some(ws) = self.focused_mut() {
+            ws.status = Some(split);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_manual_splits_of_other_workspaces() {
+        let ids = HashSet::from([100, 200]);
+        let mut state = State::default();
+        state.focus(Some("1"), Some(100), &ids);
+        state.split_manually();
+
+        state.focus(Some("2"), Some(200), &ids);
+        assert!(!state.is_split_manually(Duration::from_secs(60)));
+        state.focus(Some("1"), Some(100), &ids);
+        assert!(state.is_split_manually(Duration::from_secs(60)));
+
+        // Until the container is gone.
+        state.focus(Some("2"), Some(200), &HashSet::from([200]));
+        assert!(state.workspace(Some("1")).is_none());
+    }
+
+    #[test]
+    fn keeps_the_state_of_each_workspace() {
+        let ids = HashSet::from([100, 200]);
+        let mut state = State::default();
+        state.focus(Some("1"), Some(100), &ids);
+        state.set_master("1", Some(100));
+        state.set_scheme(Some("1"), Some(Scheme::MasterStack));
+        state.focus(Some("2"), Some(200), &ids);
+        state.set_paused(None, true);
+        state.set_paused(Some("2"), false);
+
+        assert_eq!(state.master("1"), Some(100));
+        assert_eq!(state.master("2"), None);
+        assert_eq!(state.scheme(Some("1")), Some(Scheme::MasterStack));
+        assert_eq!(state.scheme(None), None);
+        assert!(state.is_paused(Some("1")));
+        assert!(!state.is_paused(None));
+
+        // The master goes with its window, while the overrides stay.
+        state.focus(Some("2"), Some(200), &HashSet::from([200]));
+        assert_eq!(state.master("1"), None);
+        assert_eq!(state.scheme(Some("1")), Some(Scheme::MasterStack));
+    }
+}
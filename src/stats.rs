@@ -0,0 +1,98 @@
+//! Counters of what the daemon did since it started, for the `stats` control command.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::ipc::event::Event;
+
+/// How many of the latest events the latency percentiles are taken over.
+const LATENCIES: usize = 1000;
+/// The upper bounds of the latency histogram, in milliseconds.
+const BUCKETS: [u64; 10] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000];
+
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    /// The events handled, by type.
+    events: BTreeMap<&'static str, u64>,
+    /// The commands sent to i3, and how many of them it rejected.
+    pub commands: u64,
+    pub failed_commands: u64,
+    pub reconnects: u64,
+    /// How long the latest events took to decide and carry out, the oldest first.
+    latencies: VecDeque<Duration>,
+    /// How many events of all took at most as long as each of `BUCKETS`, and in total.
+    buckets: [u64; BUCKETS.len()],
+    latency_count: u64,
+    latency_sum: Duration,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            started: Instant::now(),
+            events: BTreeMap::new(),
+            commands: 0,
+            failed_commands: 0,
+            reconnects: 0,
+            latencies: VecDeque::new(),
+            buckets: [0; BUCKETS.len()],
+            latency_count: 0,
+            latency_sum: Duration::ZERO,
+        }
+    }
+}
+
+impl Stats {
+    pub fn count_event(&mut self, event: &Event) {
+        *self.events.entry(event.name()).or_default() += 1;
+    }
+
+    /// Records how long handling an event took.
+    pub fn record_latency(&mut self, latency: Duration) {
+        if self.latencies.len() == LATENCIES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            *bucket += u64::from(latency <= Duration::from_millis(bound));
+        }
+        self.latency_count += 1;
+        self.latency_sum += latency;
+    }
+
+    /// The counters, with the latencies in milliseconds. The histogram counts each event in
+    /// all buckets it fits in, as Prometheus has it.
+    pub fn to_json(&self) -> Value {
+        let mut latencies: Vec<_> = self.latencies.iter().collect();
+        latencies.sort();
+        let percentile = |p: usize| {
+            let index = (latencies.len().max(1) - 1) * p / 100;
+            latencies
+                .get(index)
+                .map(|latency| latency.as_secs_f64() * 1000.0)
+        };
+        json!({
+            "uptime": self.started.elapsed().as_secs(),
+            "events": self.events,
+            "commands": self.commands,
+            "failed_commands": self.failed_commands,
+            "reconnects": self.reconnects,
+            "latency_ms": {
+                "p50": percentile(50),
+                "p90": percentile(90),
+                "p99": percentile(99),
+                "max": percentile(100),
+            },
+            "latency_histogram": {
+                "buckets": BUCKETS.iter().zip(self.buckets).collect::<Vec<_>>(),
+                "count": self.latency_count,
+                "sum_ms": self.latency_sum.as_secs_f64() * 1000.0,
+            },
+        })
+    }
+}
@@ -0,0 +1,87 @@
+//! Readiness and watchdog notifications for `Type=notify` systemd units, see sd_notify(3).
+
+use std::{
+    env,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+struct Notifier {
+    socket: UnixDatagram,
+    /// How often to ping the watchdog, if it is enabled.
+    watchdog: Option<Duration>,
+    last_ping: Mutex<Instant>,
+}
+
+/// Connects to `$NOTIFY_SOCKET`. Does nothing unless the daemon is run by systemd with
+/// `NotifyAccess` set.
+pub fn init() {
+    let Some(path) = env::var_os("NOTIFY_SOCKET").filter(|path| !path.is_empty()) else {
+        return;
+    };
+    let address = match path.as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(&path),
+    };
+    let socket =
+        UnixDatagram::unbound().and_then(|socket| socket.connect_addr(&address?).map(|()| socket));
+    let socket = match socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("couldn't connect to NOTIFY_SOCKET: {e}");
+            return;
+        }
+    };
+
+    let watchdog = env::var("WATCHDOG_USEC")
+        .ok()
+        .filter(|_| env::var("WATCHDOG_PID").map_or(true, |pid| pid == process::id().to_string()))
+        .and_then(|usec| usec.parse().ok())
+        // Ping twice per timeout, as recommended.
+        .map(|usec| Duration::from_micros(usec) / 2);
+
+    let _ = NOTIFIER.set(Notifier {
+        socket,
+        watchdog,
+        last_ping: Mutex::new(Instant::now()),
+    });
+}
+
+/// Sends `state`, e.g. `READY=1`.
+pub fn notify(state: &str) {
+    if let Some(notifier) = NOTIFIER.get() {
+        if let Err(e) = notifier.socket.send(state.as_bytes()) {
+            warn!("couldn't notify systemd: {e}");
+        }
+    }
+}
+
+/// How long the main loop may wait for inputs before it has to ping the watchdog.
+pub fn watchdog_interval() -> Option<Duration> {
+    NOTIFIER.get()?.watchdog
+}
+
+/// Pings the watchdog if it is due.
+pub fn ping_watchdog() {
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+    let Some(interval) = notifier.watchdog else {
+        return;
+    };
+
+    let mut last_ping = notifier.last_ping.lock().unwrap_or_else(|e| e.into_inner());
+    if last_ping.elapsed() >= interval {
+        *last_ping = Instant::now();
+        notify("WATCHDOG=1");
+    }
+}
@@ -0,0 +1,549 @@
+//! A parser for the subset of TOML used by the config file.
+//!
+//! Documents are parsed into a `serde_json::Value` so that the config types can simply derive
+//! `Deserialize`. Supported: comments, `[tables]`, `[[arrays of tables]]`, dotted and quoted
+//! keys, basic/literal (and multi-line) strings, integers, floats, booleans, arrays and inline
+//! tables. Dates and times are not supported.
+
+use std::fmt;
+
+use serde_json::{Map, Number, Value};
+
+#[derive(Debug)]
+pub struct ParseError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub fn parse(input: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let mut root = Map::new();
+    let mut table: Vec<String> = Vec::new();
+    // The `[tables]` opened so far, in the current tables of the arrays they belong to.
+    let mut opened: Vec<Vec<String>> = Vec::new();
+
+    loop {
+        parser.skip_blank_lines();
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.bump();
+                let array = parser.eat('[');
+                let path = parser.key_path()?;
+                parser.expect(']')?;
+                if array {
+                    parser.expect(']')?;
+                    parser.push_array_table(&mut root, &path)?;
+                    opened.retain(|opened| !opened.starts_with(&path));
+                } else if opened.contains(&path) {
+                    return parser.error(format!("`{}` is defined more than once", path.join(".")));
+                } else {
+                    parser.open_table(&mut root, &path)?;
+                    opened.push(path.clone());
+                }
+                table = path;
+            }
+            Some(_) => {
+                let path = parser.key_path()?;
+                parser.skip_whitespace();
+                parser.expect('=')?;
+                let value = parser.value()?;
+                let current = parser.resolve(&mut root, &table)?;
+                parser.insert(current, &path, value)?;
+            }
+        }
+        parser.end_of_line()?;
+    }
+
+    Ok(Value::Object(root))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_str(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.eat(c) {
+            Ok(())
+        } else {
+            match self.peek() {
+                Some(found) => self.error(format!("expected `{c}`, found `{found}`")),
+                None => self.error(format!("expected `{c}`, found end of file")),
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Skips whitespace, comments and newlines, as allowed between lines and inside arrays.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_whitespace();
+            self.skip_comment();
+            if !(self.eat('\n') || self.eat('\r')) {
+                break;
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        self.skip_comment();
+        self.eat('\r');
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("unexpected `{c}` after value")),
+        }
+    }
+
+    fn key_path(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut path = vec![self.key()?];
+        loop {
+            self.skip_whitespace();
+            if !self.eat('.') {
+                return Ok(path);
+            }
+            path.push(self.key()?);
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.bump();
+                }
+                if start == self.pos {
+                    return self.error("expected a key");
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') if self.peek_str("\"\"\"") => {
+                self.multiline_basic_string().map(Value::String)
+            }
+            Some('\'') if self.peek_str("'''") => {
+                self.multiline_literal_string().map(Value::String)
+            }
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some('t') if self.peek_str("true") => {
+                self.pos += 4;
+                Ok(Value::Bool(true))
+            }
+            Some('f') if self.peek_str("false") => {
+                self.pos += 5;
+                Ok(Value::Bool(false))
+            }
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' => self.number(),
+            Some(c) => self.error(format!("unexpected `{c}`, expected a value")),
+            None => self.error("expected a value, found end of file"),
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, ParseError> {
+        let unicode = |parser: &mut Self, len: usize| {
+            let digits: String = (0..len).filter_map(|_| parser.bump()).collect();
+            u32::from_str_radix(&digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .map_or_else(
+                    || parser.error(format!("invalid unicode escape `{digits}`")),
+                    Ok,
+                )
+        };
+
+        match self.bump() {
+            Some('b') => Ok('\u{8}'),
+            Some('t') => Ok('\t'),
+            Some('n') => Ok('\n'),
+            Some('f') => Ok('\u{c}'),
+            Some('r') => Ok('\r'),
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('u') => unicode(self, 4),
+            Some('U') => unicode(self, 8),
+            Some(c) => self.error(format!("invalid escape `\\{c}`")),
+            None => self.error("unterminated string"),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(self.escape()?),
+                Some('\n') | None => return self.error("unterminated string"),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(s),
+                Some('\n') | None => return self.error("unterminated string"),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn multiline_basic_string(&mut self) -> Result<String, ParseError> {
+        self.pos += 3;
+        self.eat('\r');
+        self.eat('\n');
+        let mut s = String::new();
+        loop {
+            if self.peek_str("\"\"\"") {
+                self.pos += 3;
+                return Ok(s);
+            }
+            match self.bump() {
+                Some('\\') if matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) => {
+                    // A line-ending backslash trims all whitespace up to the next content.
+                    while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                        self.bump();
+                    }
+                }
+                Some('\\') => s.push(self.escape()?),
+                Some(c) => s.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn multiline_literal_string(&mut self) -> Result<String, ParseError> {
+        self.pos += 3;
+        self.eat('\r');
+        self.eat('\n');
+        let mut s = String::new();
+        loop {
+            if self.peek_str("'''") {
+                self.pos += 3;
+                return Ok(s);
+            }
+            match self.bump() {
+                Some(c) => s.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "+-._".contains(c)) {
+            self.bump();
+        }
+        let text: String = self.chars[start..self.pos]
+            .iter()
+            .filter(|&&c| c != '_')
+            .collect();
+
+        if let Ok(int) = text.parse::<i64>() {
+            return Ok(Value::Number(int.into()));
+        }
+        match text.parse::<f64>().ok().and_then(Number::from_f64) {
+            Some(float) if !text.contains(['i', 'n']) => Ok(Value::Number(float)),
+            _ => self.error(format!("invalid number `{text}`")),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.bump();
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank_lines();
+            if !self.eat(',') {
+                self.skip_blank_lines();
+                self.expect(']')?;
+                return Ok(Value::Array(values));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, ParseError> {
+        self.bump();
+        let mut table = Map::new();
+        self.skip_whitespace();
+        if self.eat('}') {
+            return Ok(Value::Object(table));
+        }
+        loop {
+            let path = self.key_path()?;
+            self.expect('=')?;
+            let value = self.value()?;
+            self.insert(&mut table, &path, value)?;
+            self.skip_whitespace();
+            if !self.eat(',') {
+                self.expect('}')?;
+                return Ok(Value::Object(table));
+            }
+        }
+    }
+
+    /// Walks to the table at `path`, descending into the last element of arrays of tables.
+    fn resolve<'a>(
+        &self,
+        mut table: &'a mut Map<String, Value>,
+        path: &[String],
+    ) -> Result<&'a mut Map<String, Value>, ParseError> {
+        for key in path {
+            let entry = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            let entry = match entry {
+                Value::Array(array) => array.last_mut(),
+                other => Some(other),
+            };
+            table = match entry {
+                Some(Value::Object(child)) => child,
+                _ => return self.error(format!("`{key}` is not a table")),
+            };
+        }
+        Ok(table)
+    }
+
+    fn open_table(&self, root: &mut Map<String, Value>, path: &[String]) -> Result<(), ParseError> {
+        let Some((last, parents)) = path.split_last() else {
+            return self.error("expected a key");
+        };
+        let parent = self.resolve(root, parents)?;
+        match parent.get(last) {
+            None => {
+                parent.insert(last.clone(), Value::Object(Map::new()));
+                Ok(())
+            }
+            Some(Value::Object(_)) => Ok(()),
+            Some(_) => self.error(format!("`{last}` is already defined")),
+        }
+    }
+
+    fn push_array_table(
+        &self,
+        root: &mut Map<String, Value>,
+        path: &[String],
+    ) -> Result<(), ParseError> {
+        let Some((last, parents)) = path.split_last() else {
+            return self.error("expected a key");
+        };
+        let parent = self.resolve(root, parents)?;
+        match parent
+            .entry(last.clone())
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            Value::Array(array) => {
+                array.push(Value::Object(Map::new()));
+                Ok(())
+            }
+            _ => self.error(format!("`{last}` is not an array of tables")),
+        }
+    }
+
+    fn insert(
+        &self,
+        table: &mut Map<String, Value>,
+        path: &[String],
+        value: Value,
+    ) -> Result<(), ParseError> {
+        let Some((last, parents)) = path.split_last() else {
+            return self.error("expected a key");
+        };
+        let table = self.resolve(table, parents)?;
+        if table.contains_key(last) {
+            return self.error(format!("`{last}` is defined more than once"));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn error(input: &str) -> String {
+        parse(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_tables_and_arrays_of_tables() {
+        let input = r#"
+            top = 1
+
+            [table]
+            key = "value"
+
+            [[rules]]
+            class = "firefox"
+
+            [rules.layout]
+            split = "vertical"
+
+            [[rules]]
+            class = "kitty"
+
+            [rules.layout]
+            split = "horizontal"
+        "#;
+
+        assert_eq!(
+            parse(input).unwrap(),
+            json!({
+                "top": 1,
+                "table": { "key": "value" },
+                "rules": [
+                    { "class": "firefox", "layout": { "split": "vertical" } },
+                    { "class": "kitty", "layout": { "split": "horizontal" } },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn parses_keys_and_values() {
+        let input = r#"
+            a.b = true
+            "quoted key" = 'literal \n'
+            'single.quoted' = -1_000
+            inline = { x = 1.5, y.z = [1, 2,] }
+            multiline = """
+            one \
+              two"""
+            literal = '''
+            raw \n'''
+            escapes = "\té" # comment
+        "#;
+
+        assert_eq!(
+            parse(input).unwrap(),
+            json!({
+                "a": { "b": true },
+                "quoted key": "literal \\n",
+                "single.quoted": -1000,
+                "inline": { "x": 1.5, "y": { "z": [1, 2] } },
+                "multiline": "            one two",
+                "literal": "            raw \\n",
+                "escapes": "\té",
+            })
+        );
+    }
+
+    #[test]
+    fn parses_crlf_line_endings() {
+        let input = "[table]\r\nkey = 1\r\nbasic = \"\"\"\r\nline\"\"\"\r\n\
+                     literal = '''\r\nline'''\r\n";
+
+        assert_eq!(
+            parse(input).unwrap(),
+            json!({ "table": { "key": 1, "basic": "line", "literal": "line" } })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        assert_eq!(
+            error("a = 1\na = 2"),
+            "line 2: `a` is defined more than once"
+        );
+        assert_eq!(error("a = 1\n[a.b]"), "line 2: `a` is not a table");
+        assert_eq!(
+            error("[a.b]\nc = 1\n[a.b]"),
+            "line 3: `a.b` is defined more than once"
+        );
+        assert_eq!(error("[a]\n[[a]]"), "line 2: `a` is not an array of tables");
+        assert_eq!(error("a = inf"), "line 1: unexpected `i`, expected a value");
+        assert_eq!(error("a = nan"), "line 1: unexpected `n`, expected a value");
+        assert_eq!(error("a = +inf"), "line 1: invalid number `+inf`");
+        assert_eq!(error("a = 1 2"), "line 1: unexpected `2` after value");
+        assert_eq!(error("a = \"open"), "line 1: unterminated string");
+    }
+}
@@ -0,0 +1,225 @@
+//! End-to-end tests against a real i3 on a headless X server, with xterm windows.
+//!
+//! They need Xvfb, i3 and xterm, so they are ignored by default. Run them with
+//! `cargo test --test e2e -- --ignored`.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use i3_alternating_layout::ipc::{
+    reply::{Node, NodeLayout, NodeType},
+    I3Connection, WmConnection,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The display numbers of the sessions started by this process.
+static DISPLAY: AtomicU32 = AtomicU32::new(0);
+
+/// A window, with the layout of its parent and its rect.
+type Window = (NodeLayout, (i32, i32, i32, i32));
+
+/// Xvfb, i3 and the daemon, stopped when dropped.
+struct Session {
+    dir: PathBuf,
+    display: String,
+    children: Vec<Child>,
+    i3: I3Connection,
+}
+
+impl Session {
+    fn start(args: &[&str]) -> Self {
+        for tool in ["Xvfb", "i3", "xterm"] {
+            let found = env::var_os("PATH")
+                .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(tool).exists()));
+            assert!(found, "the end-to-end tests need {tool}");
+        }
+
+        let number = 90 + process::id() % 1000 * 10 + DISPLAY.fetch_add(1, Ordering::Relaxed);
+        let display = format!(":{number}");
+        let dir = env::temp_dir().join(format!("i3-alternating-layout-e2e-{number}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("i3.sock");
+        fs::write(
+            dir.join("i3.config"),
+            format!(
+                "ipc-socket {}\nfont pango:monospace 8\ndefault_border pixel 1\n",
+                socket.display()
+            ),
+        )
+        .unwrap();
+
+        let mut children = vec![Command::new("Xvfb")
+            .args([&display, "-screen", "0", "1920x1080x24", "-nolisten", "tcp"])
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap()];
+        wait_for(|| Path::new(&format!("/tmp/.X11-unix/X{number}")).exists());
+        children.push(
+            Command::new("i3")
+                .args(["-c".as_ref(), dir.join("i3.config").as_os_str()])
+                .env("DISPLAY", &display)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap(),
+        );
+        wait_for(|| I3Connection::connect_to(&socket).is_ok());
+        let i3 = I3Connection::connect_to(&socket).unwrap();
+
+        children.push(
+            Command::new(env!("CARGO_BIN_EXE_i3-alternating-layout-rs"))
+                .args(["--quiet", "--config", "/dev/null"])
+                .args(args)
+                .env("I3SOCK", &socket)
+                .env("XDG_RUNTIME_DIR", &dir)
+                .env("XDG_STATE_HOME", &dir)
+                .env_remove("SWAYSOCK")
+                .spawn()
+                .unwrap(),
+        );
+        // The control socket is named after the socket of i3.
+        wait_for(|| {
+            fs::read_dir(&dir).unwrap().flatten().any(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                name.starts_with("i3-alternating-layout-") && name.ends_with(".sock")
+            })
+        });
+
+        Session {
+            dir,
+            display,
+            children,
+            i3,
+        }
+    }
+
+    /// Opens a window and waits for it to be laid out.
+    fn open_window(&mut self) {
+        let windows = self.windows().len();
+        self.children.push(
+            Command::new("xterm")
+                .env("DISPLAY", &self.display)
+                .spawn()
+                .unwrap(),
+        );
+        wait_for(|| self.windows().len() == windows + 1);
+        // Give the daemon a moment to react to the new window.
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    /// The windows of the focused workspace, with the layouts of their parents.
+    fn windows(&mut self) -> Vec<Window> {
+        fn collect(node: &Node, windows: &mut Vec<Window>) {
+            for child in &node.nodes {
+                match child.nodetype {
+                    NodeType::Con if child.nodes.is_empty() => {
+                        windows.push((node.layout.clone(), child.rect))
+                    }
+                    _ => collect(child, windows),
+                }
+            }
+        }
+
+        let tree = self.i3.get_tree().unwrap();
+        let mut windows = Vec::new();
+        if let Some(workspace) = tree.descendants().find(|n| {
+            n.nodetype == NodeType::Workspace && n.name.as_deref() != Some("__i3_scratch")
+        }) {
+            collect(workspace, &mut windows);
+        }
+        windows
+    }
+
+    /// Sends a command to the daemon through its control socket.
+    fn ctl(&self, command: &str) {
+        let status = Command::new(env!("CARGO_BIN_EXE_i3-alternating-layout-rs"))
+            .args(["ctl", command])
+            .env("I3SOCK", self.dir.join("i3.sock"))
+            .env_remove("SWAYSOCK")
+            .env("XDG_RUNTIME_DIR", &self.dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "ctl {command} failed");
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for child in self.children.iter_mut().rev() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn wait_for(mut ready: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !ready() {
+        assert!(start.elapsed() < TIMEOUT, "timed out");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// How much longer the longer side of `rect` is.
+fn aspect(rect: (i32, i32, i32, i32)) -> f64 {
+    let (_, _, width, height) = rect;
+    f64::from(width.max(height)) / f64::from(width.min(height))
+}
+
+#[test]
+#[ignore = "needs Xvfb, i3 and xterm"]
+fn alternates_splits() {
+    let mut session = Session::start(&[]);
+    for _ in 0..4 {
+        session.open_window();
+    }
+
+    // Splitting along the longer side keeps every window from becoming a sliver.
+    let windows = session.windows();
+    assert_eq!(windows.len(), 4);
+    for (_, rect) in windows {
+        assert!(aspect(rect) < 2.1, "{rect:?} is a sliver");
+    }
+}
+
+#[test]
+#[ignore = "needs Xvfb, i3 and xterm"]
+fn pausing_stops_splitting() {
+    let mut session = Session::start(&[]);
+    session.ctl("pause");
+    for _ in 0..4 {
+        session.open_window();
+    }
+
+    // Without splits, i3 puts all windows side by side.
+    for (layout, (_, _, _, height)) in session.windows() {
+        assert_eq!(layout, NodeLayout::SplitH);
+        assert!(height > 1000, "a window was split off");
+    }
+}
+
+#[test]
+#[ignore = "needs Xvfb, i3 and xterm"]
+fn keeps_tabbed_containers() {
+    let mut session = Session::start(&[]);
+    session.open_window();
+    session.i3.run_command("layout tabbed").unwrap();
+    for _ in 0..2 {
+        session.open_window();
+    }
+
+    let windows = session.windows();
+    assert_eq!(windows.len(), 3);
+    assert!(windows
+        .iter()
+        .all(|(layout, _)| *layout == NodeLayout::Tabbed));
+}
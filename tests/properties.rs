@@ -0,0 +1,195 @@
+//! Properties of the split decision, checked on randomly generated trees.
+//!
+//! The trees come from a small xorshift generator rather than a property testing crate, seeded
+//! per case so a failure names the seed to reproduce it with.
+
+use i3_alternating_layout::{
+    config::Config,
+    engine::{Command, Status},
+    ipc::{
+        event::{Binding, BindingEventInfo, Event, WindowChange, WindowEventInfo},
+        fake::{FakeConnection, NodeBuilder, TreeBuilder},
+        reply::{Node, NodeLayout},
+    },
+    I3Split, LayoutEngine,
+};
+
+const CASES: u64 = 500;
+
+/// xorshift64*, good enough for generating test cases.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must not be zero.
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `range`.
+    fn range(&mut self, range: std::ops::Range<u64>) -> u64 {
+        range.start + self.next() % (range.end - range.start)
+    }
+
+    fn layout(&mut self) -> NodeLayout {
+        match self.range(0..6) {
+            0 => NodeLayout::Tabbed,
+            1 => NodeLayout::Stacked,
+            2 | 3 => NodeLayout::SplitV,
+            _ => NodeLayout::SplitH,
+        }
+    }
+
+    /// A container with up to four children nested `depth` more levels at most. The window
+    /// `focused` counts down to is focused.
+    fn container(&mut self, depth: u32, focused: &mut u64) -> NodeBuilder {
+        let children: Vec<_> = (0..self.range(1..5))
+            .map(|_| match self.range(0..3) {
+                0 if depth > 0 => self.container(depth - 1, focused),
+                _ => {
+                    let window = NodeBuilder::window();
+                    *focused = focused.wrapping_sub(1);
+                    match *focused {
+                        u64::MAX => window.focused(),
+                        _ => window,
+                    }
+                }
+            })
+            .collect();
+        NodeBuilder::container(self.layout(), children)
+    }
+
+    /// A tree with a random output size and layout and one window focused.
+    fn tree(&mut self) -> Node {
+        // Generate the tree once to count its windows, and again the same way with one of them
+        // focused.
+        let state = self.next() | 1;
+        let mut count = u64::MAX;
+        Rng(state).container(3, &mut count);
+        let mut focused = self.range(0..u64::MAX - count);
+
+        let rect = (
+            0,
+            0,
+            self.range(200..4000) as i32,
+            self.range(200..4000) as i32,
+        );
+        TreeBuilder::new(rect)
+            .workspace("1", Rng(state).container(3, &mut focused))
+            .build()
+    }
+}
+
+fn parent_of_focused(tree: &Node) -> &Node {
+    tree.descendants()
+        .find(|n| n.nodes.iter().any(|child| child.focused))
+        .expect("nothing is focused")
+}
+
+fn focus_binding() -> Event {
+    Event::Binding(BindingEventInfo {
+        binding: Binding {
+            command: "focus left".to_owned(),
+        },
+    })
+}
+
+fn decide(config: Config, tree: &Node, event: &Event) -> Vec<Command> {
+    let mut wm = FakeConnection::new(tree.clone());
+    LayoutEngine::new(config).handle_event(&mut wm, event)
+}
+
+fn runs(commands: &[Command]) -> Vec<&str> {
+    commands
+        .iter()
+        .filter_map(|command| match command {
+            Command::Run(command) => Some(command.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn splits_follow_the_longer_side_of_the_parent() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let tree = rng.tree();
+        let parent = parent_of_focused(&tree);
+        let (_, _, width, height) = parent.rect;
+
+        let commands = decide(Config::default(), &tree, &focus_binding());
+        let expected = match parent.layout {
+            NodeLayout::Tabbed | NodeLayout::Stacked => vec![],
+            _ if width > height => vec!["split horizontal"],
+            _ => vec!["split vertical"],
+        };
+        assert_eq!(runs(&commands), expected, "seed {seed}: {parent:?}");
+    }
+}
+
+#[test]
+fn tabbed_and_stacked_parents_are_never_split() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let tree = rng.tree();
+        let parent = parent_of_focused(&tree);
+        if !matches!(parent.layout, NodeLayout::Tabbed | NodeLayout::Stacked) {
+            continue;
+        }
+
+        let commands = decide(Config::default(), &tree, &focus_binding());
+        assert!(runs(&commands).is_empty(), "seed {seed}");
+        let split = match parent.layout {
+            NodeLayout::Tabbed => I3Split::Tabbed,
+            _ => I3Split::Stacked,
+        };
+        assert!(
+            commands.contains(&Command::Status(Status::Split(split))),
+            "seed {seed}: {commands:?}"
+        );
+    }
+}
+
+#[test]
+fn every_decision_shows_one_status() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let tree = rng.tree();
+        let config = Config {
+            ratio: rng.range(1..40) as f64 / 10.0,
+            ..Config::default()
+        };
+
+        let commands = decide(config, &tree, &focus_binding());
+        let statuses = commands
+            .iter()
+            .filter(|command| matches!(command, Command::Status(_)))
+            .count();
+        assert_eq!(statuses, 1, "seed {seed}: {commands:?}");
+    }
+}
+
+#[test]
+fn deciding_from_the_event_agrees_with_the_tree() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let tree = rng.tree();
+        let window = tree.descendants().find(|n| n.focused).unwrap().clone();
+        let event = Event::Window(WindowEventInfo {
+            change: WindowChange::Focus,
+            container: Box::new(window),
+        });
+
+        assert_eq!(
+            runs(&decide(Config::default(), &tree, &event)),
+            runs(&decide(Config::default(), &tree, &focus_binding())),
+            "seed {seed}"
+        );
+    }
+}